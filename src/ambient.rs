@@ -0,0 +1,125 @@
+use defmt::info;
+use embedded_hal::adc::OneShot;
+use fugit::MillisDurationU64;
+use rp2040_hal::{
+    adc::AdcPin,
+    gpio::{bank0::Gpio26, FunctionSioInput, Pin, PullNone},
+    timer::Instant,
+    Adc,
+};
+
+/// Highest reading of the 12-bit ADC.
+const ADC_MAX: u16 = 4095;
+
+/// Readings this close to either rail are taken as a disconnected or shorted
+/// sensor rather than real light, and skipped.
+const RAIL_MARGIN: u16 = 16;
+
+/// How many samples in a row may be skipped before the reading is abandoned
+/// and the brightness heads back to `AmbientConfig::day_brightness`.
+const MAX_SKIPPED_SAMPLES: u8 = 8;
+
+/// How hard the readings are smoothed: each sample moves the average
+/// `1 / 2^shift` of the way towards it.
+const SMOOTHING_SHIFT: u32 = 3;
+
+/// Tuning for [`AmbientLight`].
+///
+/// The photoresistor is expected from 3V3 to GP26 with a fixed resistor from
+/// GP26 to ground, so more light reads higher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AmbientConfig {
+    /// How often the ADC is sampled.
+    pub sample_period: MillisDurationU64,
+    /// The smoothed reading has to fall below this to switch to night.
+    pub night_below: u16,
+    /// The smoothed reading has to rise above this to switch back to day.
+    /// The gap to `night_below` is the hysteresis that stops it flickering.
+    pub day_above: u16,
+    pub night_brightness: u8,
+    pub day_brightness: u8,
+    /// How far the brightness moves per sample, so day and night fade into each other.
+    pub ramp_step: u8,
+}
+
+/// Recommends a master brightness from a photoresistor on GP26.
+///
+/// Starts, and falls back, at the day level: if the sensor is missing or the
+/// readings sit on a rail, the lights stay at full rather than guessing.
+pub struct AmbientLight {
+    pin: AdcPin<Pin<Gpio26, FunctionSioInput, PullNone>>,
+    config: AmbientConfig,
+    sampled_at: Option<Instant>,
+    /// Smoothed reading, or `None` until the first usable sample or after too many skipped ones.
+    reading: Option<u16>,
+    skipped: u8,
+    night: bool,
+    brightness: u8,
+}
+
+impl AmbientLight {
+    pub fn new(pin: Pin<Gpio26, FunctionSioInput, PullNone>, config: AmbientConfig) -> Self {
+        Self {
+            pin: AdcPin::new(pin),
+            config,
+            sampled_at: None,
+            reading: None,
+            skipped: 0,
+            night: false,
+            brightness: config.day_brightness,
+        }
+    }
+
+    /// Samples the ADC if a period has passed, and returns the brightness to use.
+    ///
+    /// The ADC is borrowed rather than owned, as the battery monitor shares it.
+    pub fn update(&mut self, adc: &mut Adc, now: Instant) -> u8 {
+        if self
+            .sampled_at
+            .is_some_and(|at| now - at < self.config.sample_period)
+        {
+            return self.brightness;
+        }
+        self.sampled_at = Some(now);
+
+        // The conversion takes 2 µs, and `read` waits for it
+        let sample: Option<u16> = adc.read(&mut self.pin).ok();
+        match sample.filter(|&sample| (RAIL_MARGIN..=ADC_MAX - RAIL_MARGIN).contains(&sample)) {
+            Some(sample) => {
+                self.skipped = 0;
+                self.reading = Some(match self.reading {
+                    Some(reading) => {
+                        let reading = reading as i32;
+                        (reading + ((sample as i32 - reading) >> SMOOTHING_SHIFT)) as u16
+                    }
+                    None => sample,
+                });
+            }
+            None if self.skipped < MAX_SKIPPED_SAMPLES => self.skipped += 1,
+            None => self.reading = None,
+        }
+
+        let night = match self.reading {
+            Some(reading) if self.night => reading <= self.config.day_above,
+            Some(reading) => reading < self.config.night_below,
+            None => false,
+        };
+        if night != self.night {
+            info!("Ambient light: {}", if night { "night" } else { "day" });
+            self.night = night;
+        }
+
+        let target = if night {
+            self.config.night_brightness
+        } else {
+            self.config.day_brightness
+        };
+        let step = self.config.ramp_step.max(1);
+        self.brightness = if target > self.brightness {
+            self.brightness.saturating_add(step).min(target)
+        } else {
+            self.brightness.saturating_sub(step).max(target)
+        };
+        self.brightness
+    }
+}
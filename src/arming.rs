@@ -0,0 +1,112 @@
+use core::ops::RangeInclusive;
+
+use fugit::MillisDurationU64;
+use rp2040_hal::timer::Instant;
+
+/// Throttle pulses (in µs) that count as neutral.
+const NEUTRAL_US: RangeInclusive<u16> = 1400..=1600;
+
+/// Throttle pulses (in µs) at or above this count as full forward.
+const FULL_FORWARD_US: u16 = 1900;
+
+/// How long each step of the gesture may take before it starts over.
+const GESTURE_TIMEOUT: MillisDurationU64 = MillisDurationU64::millis(2000u64);
+
+/// What the driver has to do with the throttle before the car responds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // The firmware arms one way only, set by ARMING_GESTURE in main
+pub enum ArmingGesture {
+    /// No gesture: armed whenever there is a signal.
+    None,
+    /// Full forward, then back to neutral, each within `GESTURE_TIMEOUT`.
+    FullThenNeutral,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Waiting for the throttle to reach full forward.
+    WaitFull,
+    /// Saw full forward at the given time, waiting for neutral.
+    WaitNeutral(Instant),
+    Armed,
+}
+
+/// Recognises the arming gesture on the throttle channel.
+///
+/// Starts disarmed. Losing the signal disarms again, so the gesture has to be
+/// repeated after every failsafe.
+///
+/// The throttle is only sampled when [`Arming::update`] is called, so a flick
+/// to full and back that is shorter than the update period can be missed.
+pub struct Arming {
+    gesture: ArmingGesture,
+    state: State,
+}
+
+impl Arming {
+    pub fn new(gesture: ArmingGesture) -> Self {
+        Self {
+            gesture,
+            state: State::WaitFull,
+        }
+    }
+
+    /// Feeds the latest throttle pulse and returns whether the car is armed.
+    pub fn update(&mut self, throttle: u16, failsafe: bool, now: Instant) -> bool {
+        if failsafe {
+            self.state = State::WaitFull;
+            return false;
+        }
+
+        self.state = match (self.gesture, self.state) {
+            (ArmingGesture::None, _) | (_, State::Armed) => State::Armed,
+            (ArmingGesture::FullThenNeutral, State::WaitFull) => {
+                if throttle >= FULL_FORWARD_US {
+                    State::WaitNeutral(now)
+                } else {
+                    State::WaitFull
+                }
+            }
+            (ArmingGesture::FullThenNeutral, State::WaitNeutral(since)) => {
+                if NEUTRAL_US.contains(&throttle) {
+                    State::Armed
+                } else if throttle >= FULL_FORWARD_US {
+                    // Still holding full, the timeout runs from when it was released
+                    State::WaitNeutral(now)
+                } else if now - since > GESTURE_TIMEOUT {
+                    State::WaitFull
+                } else {
+                    State::WaitNeutral(since)
+                }
+            }
+        };
+
+        self.is_armed()
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.state == State::Armed
+    }
+}
+
+/// The overall safety state, as shown on the external indicator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SafetyState {
+    /// Signal lost or both channels faulted.
+    Failsafe,
+    /// Signal fine, waiting for the arming gesture.
+    Disarmed,
+    Armed,
+}
+
+impl SafetyState {
+    pub fn new(failsafe: bool, armed: bool) -> Self {
+        if failsafe {
+            SafetyState::Failsafe
+        } else if armed {
+            SafetyState::Armed
+        } else {
+            SafetyState::Disarmed
+        }
+    }
+}
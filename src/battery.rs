@@ -0,0 +1,135 @@
+use defmt::{info, warn};
+use embedded_hal::adc::OneShot;
+use fugit::MillisDurationU64;
+use rp2040_hal::{
+    adc::AdcPin,
+    gpio::{bank0::Gpio27, FunctionSioInput, Pin, PullNone},
+    timer::Instant,
+    Adc,
+};
+
+/// Highest reading of the 12-bit ADC.
+const ADC_MAX: u32 = 4095;
+
+/// The ADC reference, which is the Pico's 3V3 rail.
+const ADC_REFERENCE_MV: u32 = 3300;
+
+/// How many samples the reading is averaged over.
+const AVERAGED_SAMPLES: usize = 8;
+
+/// Tuning for [`BatteryMonitor`].
+///
+/// The pack is expected through a voltage divider into GP27, with the
+/// divider chosen so a full pack stays under 3.3 V at the pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryConfig {
+    /// How often the ADC is sampled.
+    pub sample_period: MillisDurationU64,
+    /// The pack voltage over the voltage at the pin, in thousandths. A 20k
+    /// over 10k divider is 3000.
+    pub divider_ratio_milli: u32,
+    /// The averaged pack voltage has to fall below this to warn.
+    pub low_below_mv: u32,
+    /// The averaged pack voltage has to rise above this to stop warning.
+    /// The gap to `low_below_mv` is the hysteresis that stops it flickering
+    /// as the pack sags under load.
+    pub recover_above_mv: u32,
+}
+
+/// Converts a raw ADC reading to the pack voltage in mV, for a divider of `ratio_milli`.
+const fn adc_to_mv(raw: u16, ratio_milli: u32) -> u32 {
+    (raw as u64 * ADC_REFERENCE_MV as u64 * ratio_milli as u64 / (ADC_MAX as u64 * 1000)) as u32
+}
+
+const _: () = {
+    assert!(adc_to_mv(0, 3000) == 0);
+    assert!(adc_to_mv(4095, 1000) == 3300);
+    assert!(adc_to_mv(4095, 3000) == 9900);
+    // Half scale through a 3:1 divider is half of 9.9 V
+    assert!(adc_to_mv(2048, 3000) == 4951);
+};
+
+/// Watches the pack voltage through a divider on GP27, and says when it runs low.
+///
+/// The reading is the average of the last `AVERAGED_SAMPLES` samples, so
+/// noise and brief sags under load don't trip the warning. Until that many
+/// have been taken it averages over the ones there are, and before the first
+/// one there is no reading at all, which never counts as low.
+pub struct BatteryMonitor {
+    pin: AdcPin<Pin<Gpio27, FunctionSioInput, PullNone>>,
+    config: BatteryConfig,
+    sampled_at: Option<Instant>,
+    samples: [u16; AVERAGED_SAMPLES],
+    /// How many of `samples` hold a reading, up to `AVERAGED_SAMPLES`.
+    taken: usize,
+    /// Where the next sample goes in `samples`.
+    next: usize,
+    low: bool,
+}
+
+impl BatteryMonitor {
+    pub fn new(pin: Pin<Gpio27, FunctionSioInput, PullNone>, config: BatteryConfig) -> Self {
+        Self {
+            pin: AdcPin::new(pin),
+            config,
+            sampled_at: None,
+            samples: [0; AVERAGED_SAMPLES],
+            taken: 0,
+            next: 0,
+            low: false,
+        }
+    }
+
+    /// Samples the ADC if a period has passed, and returns whether the battery is low.
+    ///
+    /// The first call samples straight away, so there is a reading from the
+    /// first update on.
+    pub fn update(&mut self, adc: &mut Adc, now: Instant) -> bool {
+        if self
+            .sampled_at
+            .is_some_and(|at| now - at < self.config.sample_period)
+        {
+            return self.low;
+        }
+        self.sampled_at = Some(now);
+
+        // The conversion takes 2 µs, and `read` waits for it
+        let sample: Option<u16> = adc.read(&mut self.pin).ok();
+        if let Some(sample) = sample {
+            self.samples[self.next] = sample;
+            self.next = (self.next + 1) % AVERAGED_SAMPLES;
+            self.taken = (self.taken + 1).min(AVERAGED_SAMPLES);
+        }
+
+        let Some(mv) = self.battery_mv() else {
+            return self.low;
+        };
+        let low = if self.low {
+            mv <= self.config.recover_above_mv
+        } else {
+            mv < self.config.low_below_mv
+        };
+        if low != self.low {
+            if low {
+                warn!("Battery low: {} mV", mv);
+            } else {
+                info!("Battery recovered: {} mV", mv);
+            }
+            self.low = low;
+        }
+        self.low
+    }
+
+    /// The averaged pack voltage in mV, or `None` before the first sample.
+    pub fn battery_mv(&self) -> Option<u32> {
+        if self.taken == 0 {
+            return None;
+        }
+        let sum: u32 = self.samples[..self.taken]
+            .iter()
+            .map(|&sample| sample as u32)
+            .sum();
+        let average = (sum / self.taken as u32) as u16;
+        Some(adc_to_mv(average, self.config.divider_ratio_milli))
+    }
+}
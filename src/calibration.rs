@@ -0,0 +1,119 @@
+use defmt::info;
+use fugit::MillisDurationU64;
+use rp2040_hal::timer::Instant;
+
+use crate::receiver::{CalibrationResult, Receiver};
+
+/// How long after the first frame the calibration gesture may start.
+const GESTURE_WINDOW: MillisDurationU64 = MillisDurationU64::millis(5000u64);
+
+/// How long the gesture has to be held.
+const GESTURE_HOLD: MillisDurationU64 = MillisDurationU64::millis(1000u64);
+
+/// Steering at or past this percentage either way counts as held at full.
+const FULL_STEERING_PERCENT: i16 = 90;
+
+/// How long the endpoints are recorded for once the gesture is recognised.
+const RECORD_DURATION: MillisDurationU64 = MillisDurationU64::millis(5000u64);
+
+/// How to ask for endpoint calibration at power-up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // HoldFullSteering is opt-in through CALIBRATION_GESTURE
+pub enum CalibrationGesture {
+    /// Never calibrate; the endpoints in `ReceiverConfig` are used as they are.
+    None,
+    /// Hold the steering at full lock, either way, for `GESTURE_HOLD` within
+    /// `GESTURE_WINDOW` of the first frame.
+    HoldFullSteering,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    WaitSignal,
+    /// The first frame arrived at `signal_at`; steering has been held at full since `held_since`.
+    WaitGesture {
+        signal_at: Instant,
+        held_since: Option<Instant>,
+    },
+    /// Recording endpoints since the given time.
+    Recording(Instant),
+    Done,
+}
+
+/// Runs [`Receiver::begin_calibration`] and [`Receiver::end_calibration`] from
+/// a gesture at power-up, or whenever [`BootCalibration::start`] asks.
+///
+/// Once the gesture is recognised there are `RECORD_DURATION` to move the
+/// steering and throttle to both ends. The gesture only counts just after
+/// boot, so full lock while driving never starts a calibration. Calibrated
+/// endpoints last until the next power cycle.
+pub struct BootCalibration {
+    state: State,
+}
+
+impl BootCalibration {
+    pub fn new(gesture: CalibrationGesture) -> Self {
+        let state = match gesture {
+            CalibrationGesture::None => State::Done,
+            CalibrationGesture::HoldFullSteering => State::WaitSignal,
+        };
+        Self { state }
+    }
+
+    /// Starts recording straight away, as if the gesture had just been
+    /// recognised. A run already in progress starts over.
+    #[cfg(all(feature = "cli", not(feature = "rtic")))] // Only the console asks for it
+    pub fn start(&mut self, receiver: &mut Receiver, now: Instant) {
+        self.state = Self::record(receiver, now);
+    }
+
+    fn record(receiver: &mut Receiver, now: Instant) -> State {
+        info!("Calibrating: move steering and throttle to both ends");
+        receiver.begin_calibration();
+        State::Recording(now)
+    }
+
+    /// Steps the calibration along. Call this on every update.
+    ///
+    /// Returns the result on the update that finishes a run, so the new
+    /// endpoints can be saved.
+    pub fn update(&mut self, receiver: &mut Receiver, now: Instant) -> Option<CalibrationResult> {
+        let mut finished = None;
+        self.state = match self.state {
+            State::WaitSignal if receiver.has_seen_signal() => State::WaitGesture {
+                signal_at: now,
+                held_since: None,
+            },
+            State::WaitGesture { signal_at, .. } if now - signal_at > GESTURE_WINDOW => State::Done,
+            State::WaitGesture {
+                signal_at,
+                held_since,
+            } => {
+                let held = !receiver.in_failsafe()
+                    && receiver.steering_percent().abs() >= FULL_STEERING_PERCENT;
+                match held_since {
+                    Some(since) if held && now - since >= GESTURE_HOLD => {
+                        Self::record(receiver, now)
+                    }
+                    _ => State::WaitGesture {
+                        signal_at,
+                        held_since: held.then(|| held_since.unwrap_or(now)),
+                    },
+                }
+            }
+            State::Recording(since) if now - since >= RECORD_DURATION => {
+                let result = receiver.end_calibration();
+                info!(
+                    "Calibrated {}: steering {}, throttle {}",
+                    result,
+                    receiver.steering_endpoints(),
+                    receiver.throttle_endpoints()
+                );
+                finished = Some(result);
+                State::Done
+            }
+            state => state,
+        };
+        finished
+    }
+}
@@ -0,0 +1,199 @@
+use core::fmt::{self, Write};
+
+use rp2040_hal::{clocks::UsbClock, pac, usb::UsbBus};
+use usb_device::{class_prelude::UsbBusAllocator, prelude::*};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// The pid.codes test VID/PID, which is fine for a console that never leaves the bench.
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+
+/// Longest command line. Anything longer is thrown away.
+const LINE_LEN: usize = 32;
+
+/// Output waiting for the host. A reply that doesn't fit is cut short.
+const OUTBOX_LEN: usize = 512;
+
+const USAGE: &str =
+    "commands: bright <0-255>, blink <ms>, watchdog <ms>, beam, test, cal, dump, reset";
+
+/// A command typed on the console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Caps the master brightness.
+    Bright(u8),
+    /// Sets how long the turn signals stay on, and then off, in ms.
+    Blink(u16),
+    /// Sets how long the receiver can go without a frame before it fails safe, in ms.
+    Watchdog(u32),
+    /// Moves the headlights on to the next beam: off, low, high, then off again.
+    Beam,
+    /// Starts the lights test, or stops it if it is running.
+    Test,
+    /// Starts recording the steering and throttle endpoints.
+    Cal,
+    /// Prints the receiver readings, config and recent pulses.
+    Dump,
+    /// Zeroes the receiver's diagnostic counters and forgets the extremes seen.
+    Reset,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Command, &'static str> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("bright"), Some(level)) => {
+                Command::Bright(level.parse().map_err(|_| "bright takes 0 to 255")?)
+            }
+            (Some("blink"), Some(ms)) => Command::Blink(
+                ms.parse()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or("blink takes 1 to 65535 ms")?,
+            ),
+            (Some("watchdog"), Some(ms)) => Command::Watchdog(
+                ms.parse()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or("watchdog takes a timeout in ms, above 0")?,
+            ),
+            (Some("beam"), None) => Command::Beam,
+            (Some("test"), None) => Command::Test,
+            (Some("cal"), None) => Command::Cal,
+            (Some("dump"), None) => Command::Dump,
+            (Some("reset"), None) => Command::Reset,
+            _ => return Err(USAGE),
+        };
+        if words.next().is_some() {
+            return Err(USAGE);
+        }
+        Ok(command)
+    }
+}
+
+/// Bytes queued for the host, so a reply never waits for it to read.
+struct Outbox {
+    bytes: [u8; OUTBOX_LEN],
+    len: usize,
+}
+
+impl Write for Outbox {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(OUTBOX_LEN - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        if count == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// A text console on the USB port, for tuning on the bench.
+///
+/// Nothing here waits on the host: each [`Cli::poll`] services the USB
+/// device once, drains whatever input is already buffered and hands the
+/// host as much queued output as it will take, then returns. That keeps
+/// it well under a millisecond, so it can be polled on every spin of the
+/// control loop.
+pub struct Cli {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+    line: [u8; LINE_LEN],
+    line_len: usize,
+    /// Set once the line has run past `LINE_LEN`, so the rest of it is ignored.
+    overflowed: bool,
+    outbox: Outbox,
+}
+
+impl Cli {
+    /// Can only be called once, as the USB bus has to live for the rest of the program.
+    pub fn new(
+        regs: pac::USBCTRL_REGS,
+        dpram: pac::USBCTRL_DPRAM,
+        clock: UsbClock,
+        resets: &mut pac::RESETS,
+    ) -> Self {
+        let bus = cortex_m::singleton!(
+            : UsbBusAllocator<UsbBus> =
+                UsbBusAllocator::new(UsbBus::new(regs, dpram, clock, true, resets))
+        )
+        .unwrap();
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("picotrx4m")
+            .product("TRX4M lights console")
+            .serial_number("0001")
+            .device_class(USB_CLASS_CDC)
+            .build();
+
+        Self {
+            device,
+            serial,
+            line: [0; LINE_LEN],
+            line_len: 0,
+            overflowed: false,
+            outbox: Outbox {
+                bytes: [0; OUTBOX_LEN],
+                len: 0,
+            },
+        }
+    }
+
+    /// Services the USB port and returns the next command typed, if a whole line has arrived.
+    ///
+    /// Input is echoed back, and a line that doesn't parse gets the usage in reply.
+    pub fn poll(&mut self) -> Option<Command> {
+        self.device.poll(&mut [&mut self.serial]);
+        self.flush();
+
+        let mut byte = [0u8];
+        while let Ok(1) = self.serial.read(&mut byte) {
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    let line = &self.line[..self.line_len];
+                    let parsed = match core::str::from_utf8(line) {
+                        Ok(line) if line.trim().is_empty() => None,
+                        Ok(line) if !self.overflowed => Some(Command::parse(line)),
+                        _ => Some(Err(USAGE)),
+                    };
+                    self.line_len = 0;
+                    self.overflowed = false;
+                    let _ = self.outbox.write_str("\r\n");
+                    match parsed {
+                        Some(Ok(command)) => return Some(command),
+                        Some(Err(message)) => self.reply(format_args!("{}", message)),
+                        None => {}
+                    }
+                }
+                byte if self.line_len < LINE_LEN => {
+                    self.line[self.line_len] = byte;
+                    self.line_len += 1;
+                    if let Ok(echo) = core::str::from_utf8(&[byte]) {
+                        let _ = self.outbox.write_str(echo);
+                    }
+                }
+                _ => self.overflowed = true,
+            }
+        }
+        None
+    }
+
+    /// Queues a line for the host. It goes out over the next few polls.
+    pub fn reply(&mut self, args: fmt::Arguments) {
+        let _ = self.outbox.write_fmt(args);
+        let _ = self.outbox.write_str("\r\n");
+    }
+
+    /// Hands the host as much of the outbox as it will take right now.
+    fn flush(&mut self) {
+        let Outbox { bytes, len } = &mut self.outbox;
+        if *len == 0 {
+            return;
+        }
+        if let Ok(written) = self.serial.write(&bytes[..*len]) {
+            bytes.copy_within(written..*len, 0);
+            *len -= written;
+        }
+    }
+}
@@ -0,0 +1,237 @@
+#[cfg(feature = "receiver")]
+use rp2040_hal::rom_data;
+
+/// Bytes in a flash sector, the smallest unit that can be erased.
+const SECTOR_SIZE: usize = 4096;
+
+/// Bytes in a flash page, the unit that is programmed.
+#[cfg(feature = "receiver")]
+const PAGE_SIZE: usize = 256;
+
+/// Where the config lives: the last sector of the Pico's 2 MB flash.
+/// `memory.x` keeps the firmware out of it.
+const CONFIG_OFFSET: u32 = 2 * 1024 * 1024 - SECTOR_SIZE as u32;
+
+/// Where flash is mapped for execute-in-place reads.
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// Erase in 64 KB blocks where possible; the boot ROM falls back to 4 KB sectors.
+#[cfg(feature = "receiver")]
+const BLOCK_SIZE: u32 = 1 << 16;
+#[cfg(feature = "receiver")]
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+
+/// Bytes of the second stage bootloader at the start of flash, which sets XIP back up.
+#[cfg(feature = "receiver")]
+const BOOT2_SIZE: usize = 256;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"TRX4");
+
+/// Bumped whenever the layout below changes, so an old layout reads as defaults.
+const VERSION: u8 = 3;
+
+/// Magic (4), version (1), which fields are set (1), two sets of endpoints
+/// (12), color order (1), brightness (1), turn signal blink (2), failsafe
+/// pattern (1), then the CRC (4).
+const ENCODED_LEN: usize = 27;
+
+const HAS_STEERING: u8 = 1 << 0;
+const HAS_THROTTLE: u8 = 1 << 1;
+const HAS_COLOR_ORDER: u8 = 1 << 2;
+const HAS_BRIGHTNESS: u8 = 1 << 3;
+const HAS_BLINK: u8 = 1 << 4;
+const HAS_FAILSAFE_PATTERN: u8 = 1 << 5;
+
+/// CRC-32 (IEEE), bit by bit. It only ever runs over a few bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Settings that survive a power cycle.
+///
+/// Every field is optional: `None` means use what the firmware was built
+/// with, which is also what a blank or corrupt sector loads as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Config {
+    /// Calibrated steering endpoints in µs: min, neutral, max.
+    pub steering_us: Option<[u16; 3]>,
+    /// Calibrated throttle endpoints in µs: min, neutral, max.
+    pub throttle_us: Option<[u16; 3]>,
+    /// The LED strip's `ColorOrder`, as its position in the enum.
+    pub color_order: Option<u8>,
+    /// Master brightness. With a receiver the aux dimmer still works, but can't go above this.
+    pub brightness: Option<u8>,
+    /// How long the turn signals stay on, and then off, in each blink, in ms.
+    pub blink_ms: Option<u16>,
+    /// What the lights show in failsafe, as its position in `FailsafePattern`.
+    pub failsafe_pattern: Option<u8>,
+}
+
+impl Config {
+    #[cfg(feature = "receiver")] // Only calibration saves anything so far
+    fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut bytes = [0u8; ENCODED_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[4] = VERSION;
+
+        let mut flags = 0;
+        for (field, flag, at) in [
+            (self.steering_us, HAS_STEERING, 6),
+            (self.throttle_us, HAS_THROTTLE, 12),
+        ] {
+            if let Some(endpoints) = field {
+                flags |= flag;
+                for (i, value) in endpoints.into_iter().enumerate() {
+                    bytes[at + i * 2..at + i * 2 + 2].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        for (field, flag, at) in [
+            (self.color_order, HAS_COLOR_ORDER, 18),
+            (self.brightness, HAS_BRIGHTNESS, 19),
+            (self.failsafe_pattern, HAS_FAILSAFE_PATTERN, 22),
+        ] {
+            if let Some(value) = field {
+                flags |= flag;
+                bytes[at] = value;
+            }
+        }
+        if let Some(ms) = self.blink_ms {
+            flags |= HAS_BLINK;
+            bytes[20..22].copy_from_slice(&ms.to_le_bytes());
+        }
+        bytes[5] = flags;
+
+        let crc = crc32(&bytes[..ENCODED_LEN - 4]);
+        bytes[ENCODED_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// `None` unless the magic, version and CRC all check out.
+    fn decode(bytes: &[u8; ENCODED_LEN]) -> Option<Config> {
+        let (body, crc) = bytes.split_at(ENCODED_LEN - 4);
+        if crc32(body) != u32::from_le_bytes(crc.try_into().ok()?)
+            || body[0..4] != MAGIC.to_le_bytes()
+            || body[4] != VERSION
+        {
+            return None;
+        }
+
+        let flags = body[5];
+        let endpoints = |flag, at: usize| {
+            (flags & flag != 0).then(|| {
+                core::array::from_fn(|i| {
+                    u16::from_le_bytes([body[at + i * 2], body[at + i * 2 + 1]])
+                })
+            })
+        };
+        Some(Config {
+            steering_us: endpoints(HAS_STEERING, 6),
+            throttle_us: endpoints(HAS_THROTTLE, 12),
+            color_order: (flags & HAS_COLOR_ORDER != 0).then_some(body[18]),
+            brightness: (flags & HAS_BRIGHTNESS != 0).then_some(body[19]),
+            blink_ms: (flags & HAS_BLINK != 0).then(|| u16::from_le_bytes([body[20], body[21]])),
+            failsafe_pattern: (flags & HAS_FAILSAFE_PATTERN != 0).then_some(body[22]),
+        })
+    }
+
+    /// The raw bytes at the start of the config sector.
+    fn stored() -> [u8; ENCODED_LEN] {
+        let base = (XIP_BASE + CONFIG_OFFSET) as *const u8;
+        #[allow(unsafe_code)] // Flash is always mapped at `XIP_BASE`, and this sector is ours
+        core::array::from_fn(|i| unsafe { base.add(i).read_volatile() })
+    }
+
+    /// Reads the saved config, or all defaults if the sector is blank, from an
+    /// older layout, or was only partly written.
+    pub fn load() -> Config {
+        Config::decode(&Config::stored()).unwrap_or_default()
+    }
+
+    /// Writes the config to flash, unless it is already what is stored there.
+    ///
+    /// Flash can't be read while it is being written, so this runs with
+    /// interrupts off and takes tens of milliseconds to erase the sector.
+    /// Nothing else may be running from flash meanwhile, which means core1
+    /// has to be parked first; see `LedOutput::with_flash_access`. A sector
+    /// is good for about 100k erases, so this is meant for the odd change of
+    /// setting, not for every update.
+    #[cfg(feature = "receiver")]
+    pub fn save(&self) {
+        let encoded = self.encode();
+        if encoded == Config::stored() {
+            return;
+        }
+
+        let mut page = [0xFFu8; PAGE_SIZE];
+        page[..ENCODED_LEN].copy_from_slice(&encoded);
+
+        // Copied out while XIP still works, to run once it has been torn down
+        let boot2: [u32; BOOT2_SIZE / 4] = {
+            let base = XIP_BASE as *const u32;
+            #[allow(unsafe_code)]
+            // Flash is always mapped at `XIP_BASE`, and boot2 sits at its start
+            core::array::from_fn(|i| unsafe { base.add(i).read_volatile() })
+        };
+
+        let rom = RomFlash {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        };
+
+        cortex_m::interrupt::free(|_| {
+            #[allow(unsafe_code)] // Interrupts are off, and the caller has parked the other core
+            unsafe {
+                write_config_sector(&rom, &page, &boot2);
+            }
+        });
+    }
+}
+
+/// The boot ROM's flash routines, looked up beforehand because the lookup itself runs from flash.
+#[cfg(feature = "receiver")]
+struct RomFlash {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+/// Erases the config sector and programs `page` into it, then restores XIP with `boot2`.
+///
+/// # Safety
+///
+/// This runs from RAM, and nothing may touch flash until it returns: no
+/// interrupts on this core and nothing running from flash on the other.
+#[cfg(feature = "receiver")]
+#[link_section = ".data.ram_func"]
+#[inline(never)]
+#[allow(unsafe_code)] // Only called from `Config::save`, with the above upheld
+unsafe fn write_config_sector(
+    rom: &RomFlash,
+    page: &[u8; PAGE_SIZE],
+    boot2: &[u32; BOOT2_SIZE / 4],
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(CONFIG_OFFSET, SECTOR_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    (rom.flash_range_program)(CONFIG_OFFSET, page.as_ptr(), PAGE_SIZE);
+    (rom.flash_flush_cache)();
+
+    // The ROM's own `flash_enter_cmd_xip` only sets up slow single-bit reads;
+    // boot2 puts back the fast mode the firmware booted with
+    let enter_xip: unsafe extern "C" fn() = core::mem::transmute(boot2.as_ptr() as usize | 1);
+    enter_xip();
+}
@@ -0,0 +1,225 @@
+use fugit::MillisDurationU64;
+use rp2040_hal::timer::Instant;
+
+use crate::{
+    lights::{lerp_channel, Fade},
+    receiver::ThrottleState,
+};
+
+/// How long the throttle has to stay in reverse before it counts as reversing rather than braking.
+const REVERSE_SUSTAIN: MillisDurationU64 = MillisDurationU64::millis(1000u64);
+
+/// Which drive lights should be on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct DriveLights {
+    pub brake: bool,
+    pub reverse: bool,
+}
+
+/// Turns throttle states into brake and reverse lights.
+///
+/// The brake fires on the deceleration edge, the moment the throttle leaves
+/// forward, not just whenever it sits below neutral. It then holds while the
+/// throttle stays in reverse (the ESC is braking), and for `hold` after the
+/// throttle settles in neutral, like a car waiting at a light, before going
+/// off by itself. Braking again within the hold lights it steadily and starts
+/// the hold over once it is released. Reverse held for `REVERSE_SUSTAIN` is
+/// real reversing: the reverse lights come on and the brake goes off, and
+/// coming out of it into neutral doesn't light the brake. Going forward again
+/// clears both.
+pub struct DriveTracker {
+    hold: MillisDurationU64,
+    previous: ThrottleState,
+    brake_since: Option<Instant>,
+    /// When the throttle last settled in neutral out of braking, which the hold is timed from.
+    released_at: Option<Instant>,
+    reverse_since: Option<Instant>,
+}
+
+impl DriveTracker {
+    pub fn new(hold: MillisDurationU64) -> Self {
+        Self {
+            hold,
+            previous: ThrottleState::Neutral,
+            brake_since: None,
+            released_at: None,
+            reverse_since: None,
+        }
+    }
+
+    /// Feeds the latest throttle state and returns the lights to show.
+    pub fn update(&mut self, state: ThrottleState, now: Instant) -> DriveLights {
+        if self.previous == ThrottleState::Forward && state != ThrottleState::Forward {
+            self.brake_since = Some(now);
+        }
+        if state == ThrottleState::Neutral && self.previous != ThrottleState::Neutral {
+            let was_reversing = self
+                .reverse_since
+                .is_some_and(|since| now - since >= REVERSE_SUSTAIN);
+            self.released_at = (self.brake_since.is_some() && !was_reversing).then_some(now);
+        }
+        self.previous = state;
+
+        match state {
+            ThrottleState::Forward => {
+                self.brake_since = None;
+                self.released_at = None;
+                self.reverse_since = None;
+            }
+            ThrottleState::Neutral => self.reverse_since = None,
+            ThrottleState::Reverse => {
+                self.reverse_since.get_or_insert(now);
+            }
+        }
+
+        let reverse = self
+            .reverse_since
+            .is_some_and(|since| now - since >= REVERSE_SUSTAIN);
+        let held = self.released_at.is_some_and(|at| now - at < self.hold);
+        let brake =
+            !reverse && self.brake_since.is_some() && (state == ThrottleState::Reverse || held);
+
+        DriveLights { brake, reverse }
+    }
+}
+
+/// Half a cycle of the flashing reverse lights, so they flash at 2 Hz.
+const REVERSE_FLASH_HALF_PERIOD: MillisDurationU64 = MillisDurationU64::millis(250u64);
+
+/// Flashes the reverse lights while [`DriveLights::reverse`] is set.
+///
+/// Builds on the tracker's reverse, so a momentary dip into reverse while
+/// braking never starts it. The flash is timed from when reverse began and
+/// starts lit, so entering reverse lights up straight away and leaving it goes
+/// dark on the same update. It is only ever fully on or fully off.
+pub struct ReverseFlash {
+    since: Option<Instant>,
+}
+
+impl ReverseFlash {
+    pub fn new() -> Self {
+        Self { since: None }
+    }
+
+    /// The rear white level to show at `now`.
+    pub fn update(&mut self, reverse: bool, now: Instant) -> u8 {
+        if !reverse {
+            self.since = None;
+            return 0;
+        }
+        let since = *self.since.get_or_insert(now);
+        let half = (now - since).to_millis() / REVERSE_FLASH_HALF_PERIOD.to_millis();
+        if half % 2 == 0 {
+            u8::MAX
+        } else {
+            0
+        }
+    }
+}
+
+/// Flashes the brake lights a few times as hard braking begins, then leaves them steady.
+///
+/// Braking is hard once its intensity, from the deceleration detector,
+/// reaches `hard_at`. On that edge the lights go fully on and off `count`
+/// times, each half lasting `half_period`, and after that
+/// [`update`](Self::update) hands back to the steady brake light. Releasing
+/// the brake ends the flash on the same update, wherever it had got to, and
+/// the next hard stop starts it afresh. A `count` of 0 never flashes.
+pub struct BrakeFlash {
+    count: u8,
+    half_period: MillisDurationU64,
+    hard_at: u8,
+    since: Option<Instant>,
+    /// Whether the current stop has already been hard, so it flashes once per stop.
+    was_hard: bool,
+}
+
+impl BrakeFlash {
+    pub fn new(count: u8, half_period: MillisDurationU64, hard_at: u8) -> Self {
+        Self {
+            count,
+            half_period,
+            hard_at,
+            since: None,
+            was_hard: false,
+        }
+    }
+
+    /// Whether the flash has the brake lights lit at `now`, or `None` to show them steady.
+    pub fn update(&mut self, braking: bool, intensity: u8, now: Instant) -> Option<bool> {
+        if !braking {
+            self.since = None;
+            self.was_hard = false;
+            return None;
+        }
+        if self.count > 0 && !self.was_hard && intensity >= self.hard_at {
+            self.was_hard = true;
+            self.since = Some(now);
+        }
+        let since = self.since?;
+        let half = (now - since).to_millis() / self.half_period.to_millis().max(1);
+        if half >= 2 * self.count as u64 {
+            self.since = None;
+            return None;
+        }
+        Some(half % 2 == 0)
+    }
+}
+
+/// How far off centre, in percent, the steering can sit and still count as idle.
+const PARK_STEERING_BAND: i16 = 5;
+
+/// Whether the controls leave the car idle: throttle in neutral and steering
+/// within `PARK_STEERING_BAND` of centre.
+pub fn is_idle(throttle: ThrottleState, steering: i16) -> bool {
+    throttle == ThrottleState::Neutral && steering.abs() <= PARK_STEERING_BAND
+}
+
+/// Dims the lights to a parked level once the car has been left alone.
+///
+/// The car is idle while the throttle sits in neutral and the steering within
+/// `PARK_STEERING_BAND` of centre. Once it has been idle for `timeout` the
+/// returned level fades down to `parked` over `fade`, and the first input
+/// after that fades it back up to full the same way. A fade that is
+/// interrupted carries on from where it had got to.
+///
+/// Losing the signal is not input: the idle time keeps counting through it,
+/// so a car whose transmitter was switched off still dims.
+pub struct ParkDimmer {
+    timeout: MillisDurationU64,
+    parked: u8,
+    idle_since: Option<Instant>,
+    level: Fade<u8>,
+}
+
+impl ParkDimmer {
+    /// Starts at full, as if the car had just been touched. A zero `fade` switches instantly.
+    pub fn new(timeout: MillisDurationU64, parked: u8, fade: MillisDurationU64) -> Self {
+        Self {
+            timeout,
+            parked,
+            idle_since: None,
+            level: Fade::new(u8::MAX, fade, lerp_channel),
+        }
+    }
+
+    /// Feeds the latest throttle state and steering percentage, or `None`
+    /// without a signal, and returns the brightness to scale the lights by.
+    pub fn update(&mut self, controls: Option<(ThrottleState, i16)>, now: Instant) -> u8 {
+        let active = controls.is_some_and(|(throttle, steering)| !is_idle(throttle, steering));
+        if active {
+            self.idle_since = None;
+        } else {
+            self.idle_since.get_or_insert(now);
+        }
+
+        let parked = self
+            .idle_since
+            .is_some_and(|since| now - since >= self.timeout);
+        let target = if parked { self.parked } else { u8::MAX };
+        if self.level.set_target(target, now) {
+            defmt::info!("{}", if parked { "Parked" } else { "Unparked" });
+        }
+        self.level.at(now)
+    }
+}
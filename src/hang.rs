@@ -0,0 +1,37 @@
+use fugit::MicrosDurationU32;
+use rp2040_hal::watchdog::Watchdog;
+
+/// How long the control loop may go without petting [`HangWatchdog`] before the chip resets.
+///
+/// An update runs every `UPDATE_PERIOD_MS`, and the longest a healthy loop
+/// ever stalls is the flash write when the config is saved, which is a few
+/// tens of ms. A second leaves plenty of margin while still catching a hang
+/// before the lights have been frozen for long.
+pub const HANG_TIMEOUT: MicrosDurationU32 = MicrosDurationU32::millis(1000);
+
+/// The RP2040's hardware watchdog, used to reset the chip if the firmware hangs.
+///
+/// This is not the receiver's signal watchdog (`Receiver::has_watchdog_expired`),
+/// which notices the transmitter going quiet while the firmware carries on. This one notices
+/// the firmware stopping, say on a stuck PIO FIFO, however healthy the
+/// signal is. After the reset the board boots as if powered on, so the
+/// lights show the no-signal state until frames arrive again rather than
+/// staying frozen in whatever the hang left them.
+///
+/// The countdown pauses while a debugger has the core halted, so stepping
+/// through the code doesn't reset it.
+pub struct HangWatchdog(Watchdog);
+
+impl HangWatchdog {
+    /// Starts the countdown. Anything slow at boot, like the startup sequence, should run before this.
+    pub fn start(mut watchdog: Watchdog) -> Self {
+        watchdog.pause_on_debug(true);
+        watchdog.start(HANG_TIMEOUT);
+        Self(watchdog)
+    }
+
+    /// Tells the watchdog the loop is still running, restarting the `HANG_TIMEOUT` countdown.
+    pub fn pet(&self) {
+        self.0.feed();
+    }
+}
@@ -0,0 +1,87 @@
+use defmt::info;
+use fugit::MillisDurationU64;
+use rp2040_hal::timer::Instant;
+
+use crate::lights::{lerp_channel, Fade, FrontLeds};
+
+/// Level the headlight channels are driven at when lit.
+const BEAM_LEVEL: u8 = 255;
+
+/// Which headlight beam is selected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Beam {
+    Off,
+    Low,
+    High,
+}
+
+/// The headlight state, and how it maps onto the `low_beam` and `high_beam` channels.
+///
+/// Low lights only the low beam. High lights the high beam and, like a real
+/// car, keeps the low beam on alongside it, unless built with
+/// `low_with_high` unset, in which case it lights the high beam alone.
+///
+/// Like an incandescent bulb, each channel ramps to its new level over
+/// `ramp` rather than switching instantly, up and down alike. A change part
+/// way through a ramp carries on from where that ramp had got to, and once
+/// `ramp` has passed the channel sits exactly on its level.
+pub struct Headlights {
+    beam: Beam,
+    low_with_high: bool,
+    /// The `(low_beam, high_beam)` levels.
+    levels: Fade<(u8, u8)>,
+}
+
+impl Headlights {
+    /// Starts with the headlights off. A zero `ramp` switches them instantly.
+    pub fn new(low_with_high: bool, ramp: MillisDurationU64) -> Self {
+        Self {
+            beam: Beam::Off,
+            low_with_high,
+            levels: Fade::new((0, 0), ramp, ramp_levels),
+        }
+    }
+
+    /// Selects `beam` directly, such as from a switch position.
+    pub fn set(&mut self, beam: Beam) {
+        if beam != self.beam {
+            info!("Headlights {}", beam);
+        }
+        self.beam = beam;
+    }
+
+    /// Moves to the next beam in the Off, Low, High cycle and returns it.
+    #[cfg(all(feature = "cli", not(feature = "rtic")))] // Only the console steps through them
+    pub fn advance(&mut self) -> Beam {
+        self.set(match self.beam {
+            Beam::Off => Beam::Low,
+            Beam::Low => Beam::High,
+            Beam::High => Beam::Off,
+        });
+        self.beam
+    }
+
+    /// Stamps the selected beam's levels at `now` onto `front`'s headlight channels,
+    /// leaving its yellow alone.
+    pub fn apply(&mut self, front: &mut FrontLeds, now: Instant) {
+        let target = match self.beam {
+            Beam::Off => (0, 0),
+            Beam::Low => (BEAM_LEVEL, 0),
+            Beam::High if self.low_with_high => (BEAM_LEVEL, BEAM_LEVEL),
+            Beam::High => (0, BEAM_LEVEL),
+        };
+        self.levels.set_target(target, now);
+        (front.low_beam, front.high_beam) = self.levels.at(now);
+    }
+}
+
+/// The `(low_beam, high_beam)` levels `elapsed` ms into a `duration` long ramp.
+fn ramp_levels(from: (u8, u8), to: (u8, u8), elapsed: u64, duration: u64) -> (u8, u8) {
+    // A lit channel never reads zero, even at the very start of its ramp,
+    // so the low beam always shows while the state is Low
+    let ramp = |from, to| match lerp_channel(from, to, elapsed, duration) {
+        0 if to > 0 => 1,
+        level => level,
+    };
+    (ramp(from.0, to.0), ramp(from.1, to.1))
+}
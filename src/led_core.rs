@@ -0,0 +1,208 @@
+use core::sync::atomic::{fence, AtomicBool, AtomicU32};
+
+use defmt::warn;
+use rp2040_hal::{
+    multicore::{Multicore, Stack},
+    pac::{self, PPB, PSM},
+    sio::{Sio, SioFifo},
+};
+
+#[cfg(not(feature = "rtic"))]
+use crate::lights::SplitLeds;
+use crate::lights::{ColorOrder, LedDma, Leds, PixelFormat, FRAME_WORDS};
+
+/// Core1's stack, in words. The LED loop needs next to nothing.
+const CORE1_STACK_WORDS: usize = 1024;
+
+/// What core0 posts on the FIFO to wake core1.
+const WAKE_FRAME: u32 = 0;
+const WAKE_PARK: u32 = 1;
+
+/// Set by core1 once it is parked off flash, and cleared by core0 to let it go.
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+/// The latest packed frame, passed from core0 to core1 without a lock.
+///
+/// This is a sequence lock with a single writer. The sequence is odd while
+/// `publish` is part way through the words, and the reader retries until it
+/// sees the same even sequence before and after copying them. Core0 never
+/// waits on core1, and core1 only ever waits for the few cycles a write takes,
+/// so neither a critical section on core0 nor the DMA wait on core1 can hold
+/// the other up. The RP2040 has no atomic read-modify-write, which rules out
+/// most other lock-free schemes, but plain atomic loads and stores are all
+/// this needs.
+struct FrameMailbox {
+    sequence: AtomicU32,
+    words: [AtomicU32; FRAME_WORDS],
+}
+
+impl FrameMailbox {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)] // Only used to fill the array below
+        const ZERO: AtomicU32 = AtomicU32::new(0);
+        Self {
+            sequence: AtomicU32::new(0),
+            words: [ZERO; FRAME_WORDS],
+        }
+    }
+
+    /// Replaces the frame. Only core0 calls this.
+    fn publish(&self, words: [u32; FRAME_WORDS]) {
+        let sequence = self.sequence.load(core::sync::atomic::Ordering::Relaxed);
+        self.sequence.store(
+            sequence.wrapping_add(1),
+            core::sync::atomic::Ordering::Relaxed,
+        );
+        fence(core::sync::atomic::Ordering::Release);
+        for (slot, word) in self.words.iter().zip(words) {
+            slot.store(word, core::sync::atomic::Ordering::Relaxed);
+        }
+        self.sequence.store(
+            sequence.wrapping_add(2),
+            core::sync::atomic::Ordering::Release,
+        );
+    }
+
+    /// Copies out the latest complete frame. Only core1 calls this.
+    fn read(&self) -> [u32; FRAME_WORDS] {
+        loop {
+            let before = self.sequence.load(core::sync::atomic::Ordering::Acquire);
+            let words =
+                core::array::from_fn(|i| self.words[i].load(core::sync::atomic::Ordering::Relaxed));
+            fence(core::sync::atomic::Ordering::Acquire);
+            let after = self.sequence.load(core::sync::atomic::Ordering::Relaxed);
+            if before & 1 == 0 && before == after {
+                return words;
+            }
+        }
+    }
+}
+
+static MAILBOX: FrameMailbox = FrameMailbox::new();
+
+/// Core0's end of an LED strip driven from core1. See [`spawn_led_core`].
+pub struct Core1Leds {
+    fifo: SioFifo,
+    order: ColorOrder,
+    format: PixelFormat,
+}
+
+impl Core1Leds {
+    /// Publishes a frame and wakes core1 to send it.
+    ///
+    /// Never blocks. If core1 hasn't picked up the last wake-up yet, it will
+    /// read this frame when it does, so the wake-up isn't repeated.
+    pub fn show(&mut self, leds: &Leds) {
+        MAILBOX.publish(leds.debug_words(self.order, self.format));
+        if self.fifo.is_write_ready() {
+            self.fifo.write(WAKE_FRAME);
+        }
+    }
+
+    /// Runs `f` with core1 spinning in RAM, so `f` can erase and write flash.
+    ///
+    /// Frames published meanwhile wait until core1 is let go.
+    #[cfg(feature = "receiver")] // Only calibration writes flash so far
+    pub fn with_core1_parked<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.fifo.write_blocking(WAKE_PARK);
+        while !PARKED.load(core::sync::atomic::Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        let result = f();
+        PARKED.store(false, core::sync::atomic::Ordering::Release);
+        result
+    }
+}
+
+/// Where frames go: straight to the strip from this core, to core1, or to
+/// front and rear strips on their own pins.
+pub enum LedOutput {
+    Local(LedDma),
+    Core1(Core1Leds),
+    #[cfg(not(feature = "rtic"))] // The RTIC build only drives one chain
+    Split(SplitLeds),
+}
+
+impl LedOutput {
+    /// Sends `leds` to the strip, however it is driven.
+    pub fn show(&mut self, leds: &Leds) {
+        match self {
+            LedOutput::Local(strip) => {
+                if !strip.frame_complete() {
+                    warn!("LED frame written before the previous one finished");
+                }
+                leds.start_dma(strip);
+            }
+            LedOutput::Core1(core1) => core1.show(leds),
+            #[cfg(not(feature = "rtic"))]
+            LedOutput::Split(strips) => strips.show(leds),
+        }
+    }
+
+    /// Runs `f` while nothing but this core is running code from flash. See `Config::save`.
+    #[cfg(feature = "receiver")]
+    pub fn with_flash_access<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        match self {
+            LedOutput::Local(_) => f(),
+            LedOutput::Core1(core1) => core1.with_core1_parked(f),
+            #[cfg(not(feature = "rtic"))]
+            LedOutput::Split(_) => f(),
+        }
+    }
+}
+
+/// Starts core1 feeding `strip`, and returns core0's handle for sending it frames.
+///
+/// Core1 sleeps on the SIO FIFO until core0 posts a frame, then starts its
+/// DMA transfer. So frame timing on the wire no longer depends on what core0
+/// is doing. `fifo` is core0's end of the FIFO, which the handle keeps for
+/// the wake-ups. Can only be called once.
+pub fn spawn_led_core(psm: &mut PSM, ppb: &mut PPB, mut fifo: SioFifo, strip: LedDma) -> Core1Leds {
+    let (order, format) = (strip.order(), strip.format());
+    let stack = cortex_m::singleton!(: Stack<CORE1_STACK_WORDS> = Stack::new()).unwrap();
+
+    let mut multicore = Multicore::new(psm, ppb, &mut fifo);
+    multicore.cores()[1]
+        .spawn(&mut stack.mem, move || core1_main(strip))
+        .unwrap();
+
+    Core1Leds {
+        fifo,
+        order,
+        format,
+    }
+}
+
+fn core1_main(mut strip: LedDma) -> ! {
+    // The FIFO registers are banked per core, so this only reaches core1's
+    // end of them, which nothing on core0 can touch
+    #[allow(unsafe_code)] // We've checked that core1 only uses its own side of the SIO
+    let pac = unsafe { pac::Peripherals::steal() };
+    let mut sio = Sio::new(pac.SIO);
+
+    loop {
+        if sio.fifo.read_blocking() == WAKE_PARK {
+            park_in_ram();
+            continue;
+        }
+        if !strip.frame_complete() {
+            warn!("LED frame written before the previous one finished");
+        }
+        strip.start(MAILBOX.read());
+    }
+}
+
+/// Holds core1 off flash until core0 clears `PARKED`.
+///
+/// Lives in RAM, and the atomic accesses compile to plain loads and stores,
+/// so nothing here fetches from flash while core0 has XIP torn down. A
+/// transfer already running keeps going, as it only reads RAM.
+#[link_section = ".data.ram_func"]
+#[inline(never)]
+#[allow(unsafe_code)] // Placing code in RAM is sound here; it only touches a static atomic
+fn park_in_ram() {
+    PARKED.store(true, core::sync::atomic::Ordering::Release);
+    while PARKED.load(core::sync::atomic::Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
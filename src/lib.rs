@@ -0,0 +1,10 @@
+//! The parts of the firmware that don't touch the hardware, so they can be
+//! tested on the host as well as built for the Pico. The firmware itself is
+//! the `picotrx4m` binary, which uses these through its own modules.
+#![cfg_attr(not(test), no_std)]
+#![deny(unsafe_code)]
+#![deny(warnings)]
+
+pub mod pixel;
+pub mod scaling;
+pub mod watchdog;
@@ -1,26 +1,299 @@
+use cortex_m::delay::Delay;
+use fugit::{MicrosDurationU64, MillisDurationU64};
+use rp2040_hal::timer::Instant;
 use rp2040_hal::{
     clocks::ClocksManager,
+    dma::{single_buffer, Channel, CH0},
     gpio::{DynPinId, FunctionPio0, Pin, PullDown},
     pac::PIO0,
-    pio::{PIOBuilder, PinDir, Tx, UninitStateMachine, PIO, SM0},
+    pio::{
+        InstalledProgram, PIOBuilder, PinDir, StateMachineIndex, Tx, UninitStateMachine, PIO, SM0,
+        SM1,
+    },
     Clock,
 };
 
+pub use picotrx4m::pixel::{Color, FrontLeds, IndicatorLed, RearLeds, RgbwColor};
+
+#[cfg(feature = "scope-pin")]
+use crate::status::StatusPin;
+#[cfg(feature = "scope-pin")]
+use embedded_hal::digital::v2::OutputPin;
+
+/// Default bit rate for [`initialize_lights`], which suits the WS2812s on the corners.
+#[cfg(not(feature = "ws2811_400khz"))]
+pub const LED_FREQUENCY_HZ: u32 = 871_000;
+
+/// Default bit rate for [`initialize_lights`] with the `ws2811_400khz` feature,
+/// for older WS2811 strips that only run at the slow speed. Every step of the
+/// program is stretched by the same factor, so the high times keep the same
+/// shares of the bit.
+#[cfg(feature = "ws2811_400khz")]
+pub const LED_FREQUENCY_HZ: u32 = 400_000;
+
+/// PIO cycles per bit. Must match `t1 + t2 + t3` in the program below.
+const CYCLES_PER_BIT: u32 = 22;
+
+/// The PIO clock divisor, in 16.8 fixed point, that clocks out `frequency_hz`
+/// bits per second from a `system_hz` system clock.
+pub const fn clock_divisor(system_hz: u32, frequency_hz: u32) -> (u16, u8) {
+    let frequency_per_bit = frequency_hz * CYCLES_PER_BIT;
+    let int_part = system_hz / frequency_per_bit;
+    let remainder = system_hz % frequency_per_bit;
+    let fract_part = (remainder as u64 * 256 / frequency_per_bit as u64) as u32;
+    (int_part as u16, fract_part as u8)
+}
+
+// 125 MHz / (800 kHz * 22) = 7.102, and 0.102 * 256 rounds down to 26
+const _: () = assert!(matches!(clock_divisor(125_000_000, 800_000), (7, 26)));
+// Both defaults fit the divisor from the default 125 MHz clock: 6.523 for the
+// WS2812s, and 14.205 for WS2811s at 400 kHz
+const _: () = assert!(matches!(clock_divisor(125_000_000, 871_000), (6, 133)));
+const _: () = assert!(matches!(clock_divisor(125_000_000, 400_000), (14, 52)));
+
+/// Pixel words in a `Leds` frame: the four corners, the centre lamp and the
+/// indicator (or the indicator and a spare blank), and the blank pixel after them.
+const FRAME_PIXELS: u32 = 7;
+
+/// Words in a `Leds` frame: the pixel count and the pixel words.
+pub const FRAME_WORDS: usize = FRAME_PIXELS as usize + 1;
+
+/// Shortest time, in µs, the line is held low after a frame so the LEDs latch
+/// it, unless [`initialize_lights`] is given a `reset_us`.
+///
+/// WS2812s latch once the line has been low for more than 50 µs; the rest is
+/// margin for slow parts. Newer parts want 280 µs or more.
+const LATCH_LOW_US: u32 = 60;
+
+/// The low time to latch for: `reset_us`, or `LATCH_LOW_US` without one.
+const fn latch_low_us(reset_us: Option<u32>) -> u32 {
+    match reset_us {
+        Some(reset_us) => reset_us,
+        None => LATCH_LOW_US,
+    }
+}
+
+/// PIO cycles in the latch outside the `keep_looping` iterations: 3 for the
+/// pixel loop to fall through, 1 to load the count and 8 in the trailing `nop`.
+/// Jumping back and pulling the next frame's count is part of its first bit's
+/// low lead.
+const LATCH_FIXED_CYCLES: u32 = 3 + 1 + 8;
+
+/// PIO cycles per `keep_looping` iteration.
+const LATCH_LOOP_CYCLES: u32 = 8;
+
+/// The `keep_looping` count that holds the line low for at least `low_us` at `frequency_hz`.
+///
+/// The PIO runs at `CYCLES_PER_BIT` times the bit rate, so a fixed count would
+/// latch for less time the faster the strip is clocked. [`initialize_lights`]
+/// loads this once at startup, and the program keeps it for every frame.
+const fn latch_loops(frequency_hz: u32, low_us: u32) -> u32 {
+    let needed = (low_us as u64 * frequency_hz as u64 * CYCLES_PER_BIT as u64).div_ceil(1_000_000);
+    // `jmp x--` runs the loop once more than the count it starts from
+    let iterations = needed
+        .saturating_sub(LATCH_FIXED_CYCLES as u64)
+        .div_ceil(LATCH_LOOP_CYCLES as u64);
+    iterations.saturating_sub(1) as u32
+}
+
+/// PIO cycles spent in the latch sequence at `frequency_hz`, from the end of
+/// the last data bit, all with the line low.
+const fn latch_cycles(frequency_hz: u32, low_us: u32) -> u32 {
+    LATCH_FIXED_CYCLES + (latch_loops(frequency_hz, low_us) + 1) * LATCH_LOOP_CYCLES
+}
+
+/// How long, in whole µs, [`initialize_lights`] holds the line low after each
+/// frame for the same `frequency_hz` and `reset_us`.
+///
+/// The loop runs in whole iterations, so this is at least what was asked for
+/// and usually a little more.
+pub const fn latch_us(frequency_hz: u32, reset_us: Option<u32>) -> u32 {
+    let cycles = latch_cycles(frequency_hz, latch_low_us(reset_us)) as u64;
+    (cycles * 1_000_000 / (frequency_hz as u64 * CYCLES_PER_BIT as u64)) as u32
+}
+
+// The latch is long enough across the bit rates a strip might be run at, and
+// at 871 kHz it is 142 loops: 12 + 143 * 8 = 1156 cycles, just over 60 µs
+const _: () = {
+    const fn latches(frequency_hz: u32, low_us: u32) -> bool {
+        latch_cycles(frequency_hz, low_us) as u64 * 1_000_000
+            >= low_us as u64 * frequency_hz as u64 * CYCLES_PER_BIT as u64
+    }
+    assert!(latches(400_000, LATCH_LOW_US));
+    assert!(latches(800_000, LATCH_LOW_US));
+    assert!(latches(LED_FREQUENCY_HZ, LATCH_LOW_US));
+    assert!(latches(1_000_000, LATCH_LOW_US));
+    assert!(latch_loops(871_000, LATCH_LOW_US) == 142);
+    assert!(latch_us(871_000, None) == 60);
+    // The longer latch newer parts need
+    assert!(latches(800_000, 300));
+    assert!(latches(LED_FREQUENCY_HZ, 300));
+    assert!(latch_us(800_000, Some(300)) >= 300);
+    assert!(latch_us(800_000, Some(300)) < 301);
+    // Even asking for none still runs the fixed part of the sequence
+    assert!(latches(800_000, 0));
+};
+
+/// How many bits of each pixel word go out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // Rgbw is only for strips with a white element, which the stock LEDs lack
+pub enum PixelFormat {
+    /// Three channels per pixel, packed from `Color`.
+    Rgb,
+    /// Four channels per pixel, packed from `RgbwColor`, for strips with a separate white element.
+    Rgbw,
+}
+
+impl PixelFormat {
+    /// What to pass to [`initialize_lights`] for strips in this format.
+    pub const fn bits_per_pixel(self) -> u8 {
+        match self {
+            PixelFormat::Rgb => 24,
+            PixelFormat::Rgbw => 32,
+        }
+    }
+}
+
+/// How long it takes to clock out `pixels` pixel words plus the latch, in microseconds.
+///
+/// Each pixel word takes exactly `bits_per_pixel` bit periods at `frequency_hz`,
+/// because the program's per-word overhead is folded into the low tail of a
+/// word's last bit. The latch adds `latch_cycles` for `reset_us` at the PIO
+/// clock of `frequency_hz * CYCLES_PER_BIT`. The result is rounded up, so it
+/// is safe to use as a minimum interval between frames.
+pub const fn frame_transmit_us(
+    pixels: u32,
+    frequency_hz: u32,
+    bits_per_pixel: u8,
+    reset_us: Option<u32>,
+) -> u32 {
+    let cycles = pixels as u64 * bits_per_pixel as u64 * CYCLES_PER_BIT as u64
+        + latch_cycles(frequency_hz, latch_low_us(reset_us)) as u64;
+    let cycles_per_second = frequency_hz as u64 * CYCLES_PER_BIT as u64;
+    (cycles * 1_000_000).div_ceil(cycles_per_second) as u32
+}
+
+/// Why [`initialize_lights`] couldn't start the strip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LightsError {
+    /// The WS2812 program didn't fit in what is left of the PIO's instruction memory.
+    Install,
+    /// The state machine can't be set up as asked: the pixels aren't 24 or 32
+    /// bits, or the bit rate needs a clock divisor the PIO doesn't have.
+    StateMachine,
+}
+
+/// Loads the WS2812 program into `sm` and starts it driving `pin`.
+///
+/// `frequency_hz` is the line's bit rate, usually 800 kHz; the default
+/// `LED_FREQUENCY_HZ` runs a little fast, which the corners tolerate, or is
+/// 400 kHz with the `ws2811_400khz` feature.
+/// `bits_per_pixel` is how much of each pixel word goes out, 24 or 32, from
+/// the low bit up; see [`PixelFormat::bits_per_pixel`].
+///
+/// Each frame starts with one less than its number of pixels, then the pixel
+/// words, which [`Leds::debug_words`] lays out. As the count says where the
+/// pixels end, any pixel word can be sent, including an all-dark RGBW one that
+/// is entirely zero. After the last pixel the line is held low for at least
+/// `reset_us`, or `LATCH_LOW_US` without one, worked out from `frequency_hz`
+/// so the strip latches. The time it actually holds is logged, and is what
+/// [`latch_us`] gives.
+///
+/// Each failure is logged as it happens, and leaves the state machine stopped.
 pub fn initialize_lights(
     pio: &mut PIO<PIO0>,
     sm: UninitStateMachine<(PIO0, SM0)>,
     clocks: &ClocksManager,
     pin: Pin<DynPinId, FunctionPio0, PullDown>,
-) -> Tx<(PIO0, SM0)> {
+    frequency_hz: u32,
+    bits_per_pixel: u8,
+    reset_us: Option<u32>,
+) -> Result<Tx<(PIO0, SM0)>, LightsError> {
+    let installed = install_ws2812(pio)?;
+    let divisor = ws2812_divisor(clocks, frequency_hz, bits_per_pixel)?;
+    let tx = start_ws2812(
+        installed,
+        sm,
+        pin,
+        divisor,
+        bits_per_pixel,
+        frequency_hz,
+        reset_us,
+    );
+    defmt::debug!("LEDs latch for {=u32} us", latch_us(frequency_hz, reset_us));
+    Ok(tx)
+}
+
+/// The front strip's FIFO, when the front and rear are split.
+pub type FrontTx = Tx<(PIO0, SM0)>;
+
+/// The rear strip's FIFO, when the front and rear are split.
+pub type RearTx = Tx<(PIO0, SM1)>;
+
+/// Like [`initialize_lights`], but for a front and a rear strip on two separate pins.
+///
+/// The front strip runs on `sm0` from `front_pin` and the rear on `sm1` from
+/// `rear_pin`, both from the one copy of the program at the same bit rate and
+/// latch. Each has its own FIFO, so [`SplitLeds`] can update one without
+/// touching the other.
+#[cfg_attr(feature = "rtic", allow(dead_code))] // The RTIC build only drives one chain
+#[allow(clippy::too_many_arguments)] // One per pin and state machine, as `initialize_lights` has
+pub fn initialize_split_lights(
+    pio: &mut PIO<PIO0>,
+    sm0: UninitStateMachine<(PIO0, SM0)>,
+    sm1: UninitStateMachine<(PIO0, SM1)>,
+    clocks: &ClocksManager,
+    front_pin: Pin<DynPinId, FunctionPio0, PullDown>,
+    rear_pin: Pin<DynPinId, FunctionPio0, PullDown>,
+    frequency_hz: u32,
+    bits_per_pixel: u8,
+    reset_us: Option<u32>,
+) -> Result<(FrontTx, RearTx), LightsError> {
+    let installed = install_ws2812(pio)?;
+    let divisor = ws2812_divisor(clocks, frequency_hz, bits_per_pixel)?;
+    #[allow(unsafe_code)] // The program is never uninstalled, so neither handle can outlive it
+    let shared = unsafe { installed.share() };
+    let front = start_ws2812(
+        installed,
+        sm0,
+        front_pin,
+        divisor,
+        bits_per_pixel,
+        frequency_hz,
+        reset_us,
+    );
+    let rear = start_ws2812(
+        shared,
+        sm1,
+        rear_pin,
+        divisor,
+        bits_per_pixel,
+        frequency_hz,
+        reset_us,
+    );
+    defmt::debug!(
+        "Front and rear LEDs latch for {=u32} us",
+        latch_us(frequency_hz, reset_us)
+    );
+    Ok((front, rear))
+}
+
+/// Loads the WS2812 program into `pio`, for [`start_ws2812`] to run.
+fn install_ws2812(pio: &mut PIO<PIO0>) -> Result<InstalledProgram<PIO0>, LightsError> {
     let program = pio_proc::pio_asm!(
         ".define public t1 8", // High time at start
         ".define public t2 6", // Delta
         ".define public t3 8", // Low time at end
         ".side_set 1",
-        "new_data:"
+        // Once, before the first frame: the latch loop count, kept in the
+        // otherwise unused ISR
         "pull       side 0 [0]",
+        "mov isr osr side 0 [0]",
+        "new_frame:",
+        "pull       side 0 [0]", // Pixel count minus one
         "mov x osr  side 0 [0]",
-        "jmp !x do_stop side 0 [0]",
+        "next_pixel:",
+        "pull       side 0 [0]",
         "out y, 1       side 0 [2]",
         "jmp check_bit side 0 [0]",
         "bitloop:",
@@ -33,92 +306,1808 @@ pub fn initialize_lights(
         "do_zero:",
         "jmp !osre bitloop    side 0 [t2 - 1]",
         ".wrap_target",
-        "jmp new_data       side 0 [0]",
-        "do_stop:",
-        "pull       side 0 [0]",
-        "mov x osr  side 0 [0]",
+        "jmp x-- next_pixel side 0 [2]",
+        // The latch: the line is held low while the count from startup runs down
+        "mov x isr  side 0 [0]",
         "keep_looping:",
-        "jmp x-- keep_looping   side 0 [7]", // TODO
-        "nop   side 1 [7]", // TODO
-        "jmp new_data       side 0 [0]", // TODO
+        "jmp x-- keep_looping   side 0 [7]",
+        // Low as well, as any high time here reads as the start of another bit
+        "nop   side 0 [7]",
+        // Then waits, still low, at the next frame's count
+        "jmp new_frame       side 0 [0]",
     );
-    let installed = pio.install(&program.program).unwrap();
-
-    let frequency = 871000;
     let cycles_per_bit =
         (program.public_defines.t1 + program.public_defines.t2 + program.public_defines.t3) as u32;
-    let frequency_per_bit = frequency * cycles_per_bit;
+    debug_assert_eq!(cycles_per_bit, CYCLES_PER_BIT);
+    pio.install(&program.program).map_err(|_| {
+        defmt::error!("WS2812 program didn't fit in PIO0");
+        LightsError::Install
+    })
+}
 
-    let int_part = clocks.system_clock.freq().to_Hz() / frequency_per_bit;
-    let remainder = clocks.system_clock.freq().to_Hz() % frequency_per_bit;
-    let fract_part = (remainder * 256) / frequency_per_bit;
+/// The PIO clock divisor for `frequency_hz`, once the pixel size and rate are checked.
+fn ws2812_divisor(
+    clocks: &ClocksManager,
+    frequency_hz: u32,
+    bits_per_pixel: u8,
+) -> Result<(u16, u8), LightsError> {
+    if !matches!(bits_per_pixel, 24 | 32) {
+        defmt::error!("Can't send {=u8}-bit pixels, only 24 or 32", bits_per_pixel);
+        return Err(LightsError::StateMachine);
+    }
+    let system_hz = clocks.system_clock.freq().to_Hz();
+    // Zero would divide by zero below, and the integer part has to fit 1..=65535
+    let int_part = system_hz.checked_div(frequency_hz.saturating_mul(CYCLES_PER_BIT));
+    if !int_part.is_some_and(|int_part| (1..=u16::MAX as u32).contains(&int_part)) {
+        defmt::error!(
+            "Can't clock {=u32} Hz LEDs from a {=u32} Hz system clock",
+            frequency_hz,
+            system_hz
+        );
+        return Err(LightsError::StateMachine);
+    }
+    Ok(clock_divisor(system_hz, frequency_hz))
+}
 
-    let (mut sm, _, tx) = PIOBuilder::from_program(installed)
+/// Starts `sm` running the installed program on `pin`, and hands it the latch count.
+fn start_ws2812<SM: StateMachineIndex>(
+    installed: InstalledProgram<PIO0>,
+    sm: UninitStateMachine<(PIO0, SM)>,
+    pin: Pin<DynPinId, FunctionPio0, PullDown>,
+    (int_part, fract_part): (u16, u8),
+    bits_per_pixel: u8,
+    frequency_hz: u32,
+    reset_us: Option<u32>,
+) -> Tx<(PIO0, SM)> {
+    let (mut sm, _, mut tx) = PIOBuilder::from_program(installed)
         .side_set_pin_base(pin.id().num)
         .out_shift_direction(rp2040_hal::pio::ShiftDirection::Right)
         .autopull(false)
-        .pull_threshold(24)
-        .clock_divisor_fixed_point(int_part as u16, fract_part as u8)
+        .pull_threshold(bits_per_pixel)
+        .clock_divisor_fixed_point(int_part, fract_part)
         .buffers(rp2040_hal::pio::Buffers::OnlyTx)
         .build(sm);
 
     sm.set_pindirs([(pin.id().num, PinDir::Output)]);
 
     sm.start();
-
+    // The FIFO is empty, so this always fits
+    tx.write(latch_loops(frequency_hz, latch_low_us(reset_us)));
     tx
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct FrontLeds {
-    pub yellow: u8,
-    pub low_beam: u8,
-    pub high_beam: u8,
+/// The order a strip wants each pixel's three channels in, first sent first.
+///
+/// The PIO program shifts a word out from the low byte up, so `Rgb` sends a
+/// `Color`'s `r`, `g` and `b` in that order, which is the plain `u32` packing
+/// and how the corners here are wired. Pick another if a strip's colors come
+/// out swapped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ColorOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ColorOrder {
+    const ALL: [ColorOrder; 6] = [
+        ColorOrder::Rgb,
+        ColorOrder::Rbg,
+        ColorOrder::Grb,
+        ColorOrder::Gbr,
+        ColorOrder::Brg,
+        ColorOrder::Bgr,
+    ];
+
+    /// The order at `index` in the enum, as stored in `Config::color_order`.
+    pub fn from_index(index: u8) -> Option<ColorOrder> {
+        ColorOrder::ALL.get(index as usize).copied()
+    }
+
+    /// This order's position in the enum, the inverse of [`ColorOrder::from_index`].
+    #[allow(dead_code)] // For saving a new order; nothing in the firmware changes it yet
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    /// Packs `color` into a 32-bit pixel word, with the color channels in this
+    /// order and white last, which is how RGBW strips take it.
+    pub const fn pack_rgbw(self, color: RgbwColor) -> u32 {
+        let RgbwColor { r, g, b, w } = color;
+        let rgb: u32 = self.pack(Color { r, g, b });
+        rgb | (w as u32) << 24
+    }
+
+    /// Packs `color` into a pixel word with its channels in this order.
+    pub const fn pack(self, color: Color) -> u32 {
+        let Color { r, g, b } = color;
+        let (first, second, third) = match self {
+            ColorOrder::Rgb => (r, g, b),
+            ColorOrder::Rbg => (r, b, g),
+            ColorOrder::Grb => (g, r, b),
+            ColorOrder::Gbr => (g, b, r),
+            ColorOrder::Brg => (b, r, g),
+            ColorOrder::Bgr => (b, g, r),
+        };
+        Color {
+            r: first,
+            g: second,
+            b: third,
+        }
+        .packed()
+    }
 }
 
-impl From<FrontLeds> for u32 {
-    fn from(value: FrontLeds) -> Self {
-        let mut ret = 0xFF000000u32;
-        ret |= (value.high_beam as u32) << 16;
-        ret |= (value.low_beam as u32) << 8;
-        ret | (value.yellow as u32)
+// Every order against its word, with a different byte on each channel
+const _: () = {
+    const COLOR: Color = Color {
+        r: 0xa1,
+        g: 0xb2,
+        b: 0xc3,
+    };
+    const WORDS: [(ColorOrder, u32); 6] = [
+        (ColorOrder::Rgb, 0x00c3_b2a1),
+        (ColorOrder::Rbg, 0x00b2_c3a1),
+        (ColorOrder::Grb, 0x00c3_a1b2),
+        (ColorOrder::Gbr, 0x00a1_c3b2),
+        (ColorOrder::Brg, 0x00b2_a1c3),
+        (ColorOrder::Bgr, 0x00a1_b2c3),
+    ];
+    let mut i = 0;
+    while i < WORDS.len() {
+        let (order, word) = WORDS[i];
+        assert!(order.pack(COLOR) == word);
+        // White always goes in the top byte, whatever the order
+        let rgbw = RgbwColor {
+            r: COLOR.r,
+            g: COLOR.g,
+            b: COLOR.b,
+            w: 0xd4,
+        };
+        assert!(order.pack_rgbw(rgbw) == word | 0xd400_0000);
+        i += 1;
     }
+};
+
+/// Which corners of the chain are fitted, for [`Leds::enabled`].
+///
+/// A corner that's off is always sent dark, whatever the frame asks it to
+/// show, so a front-only kit never puts stray output into an unused segment.
+/// The default is every corner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct CornerMask {
+    pub front_left: bool,
+    pub front_right: bool,
+    pub rear_right: bool,
+    pub rear_left: bool,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct RearLeds {
-    pub yellow: u8,
-    pub white: u8,
-    pub red: u8,
+impl CornerMask {
+    pub const ALL: CornerMask = CornerMask {
+        front_left: true,
+        front_right: true,
+        rear_right: true,
+        rear_left: true,
+    };
 }
 
-impl From<RearLeds> for u32 {
-    fn from(value: RearLeds) -> Self {
-        let mut ret = 0xFF000000u32;
-        ret |= (value.red as u32) << 16;
-        ret |= (value.white as u32) << 8;
-        ret | (value.yellow as u32)
+impl Default for CornerMask {
+    fn default() -> Self {
+        CornerMask::ALL
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Leds {
     pub front_right: FrontLeds,
     pub front_left: FrontLeds,
     pub rear_right: RearLeds,
     pub rear_left: RearLeds,
+    pub indicator: IndicatorLed,
+    /// The red level of a centre high-mount stop lamp chained straight after
+    /// the rear left corner, or `None` for builds without one.
+    ///
+    /// With a lamp the indicator moves one pixel further down the chain, to
+    /// make room for it. Without one its word isn't sent, and the indicator
+    /// stays straight after the corners, with a second blank pixel at the end
+    /// to keep the frame the same length. The corners never move.
+    pub center: Option<u8>,
+    /// The corners that are sent. The rest go out dark, in their usual place on the chain.
+    pub enabled: CornerMask,
+}
+
+impl Leds {
+    /// Every channel dark.
+    pub const OFF: Leds = Leds {
+        front_right: FrontLeds {
+            yellow: 0,
+            low_beam: 0,
+            high_beam: 0,
+        },
+        front_left: FrontLeds {
+            yellow: 0,
+            low_beam: 0,
+            high_beam: 0,
+        },
+        rear_right: RearLeds {
+            yellow: 0,
+            white: 0,
+            red: 0,
+        },
+        rear_left: RearLeds {
+            yellow: 0,
+            white: 0,
+            red: 0,
+        },
+        indicator: IndicatorLed {
+            red: 0,
+            green: 0,
+            blue: 0,
+        },
+        center: None,
+        enabled: CornerMask::ALL,
+    };
+
+    /// Time to clock out one frame written by [`Leds::write`], latch included,
+    /// on a strip set up with the same arguments to [`initialize_lights`].
+    pub const fn frame_transmit_us(
+        frequency_hz: u32,
+        bits_per_pixel: u8,
+        reset_us: Option<u32>,
+    ) -> u32 {
+        frame_transmit_us(FRAME_PIXELS, frequency_hz, bits_per_pixel, reset_us)
+    }
+
+    /// The exact words [`Leds::write`] pushes to the PIO FIFO for a strip in `order` and `format`.
+    ///
+    /// Every pixel in the frame is packed in the one `format`, so a frame
+    /// can't mix 24 and 32-bit pixels. The centre lamp, if there is one, is
+    /// packed as an indicator with only its red lit. Corners that aren't
+    /// [`Leds::enabled`] are sent as 0.
+    pub fn debug_words(&self, order: ColorOrder, format: PixelFormat) -> [u32; FRAME_WORDS] {
+        let center = IndicatorLed {
+            red: self.center.unwrap_or(0),
+            ..IndicatorLed::default()
+        };
+        let pixels: [u32; 6] = match format {
+            PixelFormat::Rgb => [
+                order.pack(self.front_left.into()),
+                order.pack(self.front_right.into()),
+                order.pack(self.rear_right.into()),
+                order.pack(self.rear_left.into()),
+                order.pack(center.into()),
+                order.pack(self.indicator.into()),
+            ],
+            PixelFormat::Rgbw => [
+                order.pack_rgbw(self.front_left.into()),
+                order.pack_rgbw(self.front_right.into()),
+                order.pack_rgbw(self.rear_right.into()),
+                order.pack_rgbw(self.rear_left.into()),
+                order.pack_rgbw(center.into()),
+                order.pack_rgbw(self.indicator.into()),
+            ],
+        };
+        let [front_left, front_right, rear_right, rear_left, center, indicator] = pixels;
+        let corner = |enabled: bool, word: u32| if enabled { word } else { 0 };
+        let front_left = corner(self.enabled.front_left, front_left);
+        let front_right = corner(self.enabled.front_right, front_right);
+        let rear_right = corner(self.enabled.rear_right, rear_right);
+        let rear_left = corner(self.enabled.rear_left, rear_left);
+        // Without a centre lamp the indicator takes its place, and a blank fills the gap at the end
+        let (fifth, sixth) = match self.center {
+            Some(_) => (center, indicator),
+            None => (indicator, 0),
+        };
+        [
+            FRAME_PIXELS - 1,
+            front_left,
+            front_right,
+            rear_right,
+            rear_left,
+            fifth,
+            sixth,
+            // The blank pixel, sent dark
+            0,
+        ]
+    }
+
+    /// The frame's words as space-separated hex, for logging over defmt or serial.
+    pub fn debug_hex(&self, order: ColorOrder, format: PixelFormat) -> FrameHex {
+        FrameHex(self.debug_words(order, format))
+    }
+
+    /// Hands the frame to `strip`'s DMA channel and returns without waiting for it to go out.
+    ///
+    /// If the previous frame's transfer is still feeding the FIFO, this first
+    /// waits for it to finish, so a frame is never overwritten part way through.
+    pub fn start_dma(&self, strip: &mut LedDma) {
+        strip.start(self.debug_words(strip.order, strip.format));
+    }
+
+    pub fn write(&self, tx: &mut Tx<(PIO0, SM0)>, order: ColorOrder, format: PixelFormat) {
+        let words = self.debug_words(order, format);
+        defmt::trace!("LED words {}", FrameHex(words));
+        write_words(tx, &words);
+    }
+
+    /// The frame split into front and rear strips, for [`SplitLeds`].
+    ///
+    /// The front strip has the front left then front right corner and a blank
+    /// pixel. The rear has everything else in single-chain order: rear right,
+    /// rear left, the centre lamp and indicator as in [`Leds::debug_words`],
+    /// and a blank.
+    pub fn split_words(
+        &self,
+        order: ColorOrder,
+        format: PixelFormat,
+    ) -> ([u32; FRONT_WORDS], [u32; REAR_WORDS]) {
+        let [_, front_left, front_right, rear_right, rear_left, fifth, sixth, blank] =
+            self.debug_words(order, format);
+        (
+            [FRONT_PIXELS - 1, front_left, front_right, blank],
+            [REAR_PIXELS - 1, rear_right, rear_left, fifth, sixth, blank],
+        )
+    }
+
+    /// Clears the strip, e.g. before a reset or a low-power state, and only
+    /// returns once the dark frame is fully clocked out, latch included.
+    ///
+    /// Waits for any frame already going out first, so the dark one can't be
+    /// dropped on a full FIFO. Every pixel is sent dark, the blanks after the
+    /// indicator too, so nothing is left holding an older frame's level.
+    #[allow(dead_code)] // The blocking path; the DMA strip has `LedDma::all_off`
+    pub fn all_off(tx: &mut Tx<(PIO0, SM0)>, order: ColorOrder, format: PixelFormat) {
+        while !frame_complete(tx) {}
+        Leds::OFF.write(tx, order, format);
+        while !frame_complete(tx) {}
+    }
+}
+
+/// Pushes a whole frame into `tx`'s FIFO, waiting for room if the last frame is still going out.
+fn write_words<SM: StateMachineIndex>(tx: &mut Tx<(PIO0, SM)>, words: &[u32]) {
+    critical_section::with(|cs| {
+        scope_mark(cs, true);
+        // Re-armed here so `frame_complete` only sees the stall at the end of this frame
+        tx.clear_stalled_flag();
+        for &word in words {
+            while !tx.write(word) {}
+        }
+        scope_mark(cs, false);
+    });
+}
+
+/// The pin [`set_scope_pin`] handed over, if it has been called yet.
+#[cfg(feature = "scope-pin")]
+static SCOPE_PIN: critical_section::Mutex<core::cell::RefCell<Option<StatusPin>>> =
+    critical_section::Mutex::new(core::cell::RefCell::new(None));
+
+/// Marks every LED frame write on `pin`, for timing the strip on a scope.
+///
+/// The pin goes high as a frame starts being written and low once its words
+/// are all handed over: pushed into the FIFO by [`Leds::write`], or to the
+/// DMA channel by [`LedDma::start`]. The rising edges are a frame period
+/// apart, and the frame itself runs from one to the end of the data on the
+/// strip pin, with the latch after that.
+#[cfg(feature = "scope-pin")]
+pub fn set_scope_pin(pin: StatusPin) {
+    critical_section::with(|cs| SCOPE_PIN.borrow(cs).replace(Some(pin)));
+}
+
+/// Drives the [`set_scope_pin`] pin, if there is one. Without the
+/// `scope-pin` feature there is never a pin, and this compiles to nothing.
+#[inline(always)]
+#[cfg_attr(not(feature = "scope-pin"), allow(unused_variables))]
+fn scope_mark(cs: critical_section::CriticalSection, high: bool) {
+    #[cfg(feature = "scope-pin")]
+    if let Some(pin) = SCOPE_PIN.borrow(cs).borrow_mut().as_mut() {
+        // Setting an SIO output can't fail
+        let _ = pin.set_state(high.into());
+    }
+}
+
+/// Pixel words in the front strip of [`SplitLeds`]: the two front corners and a blank.
+const FRONT_PIXELS: u32 = 3;
+
+/// Words in a front strip frame: the pixel count and the pixel words.
+pub const FRONT_WORDS: usize = FRONT_PIXELS as usize + 1;
+
+/// Pixel words in the rear strip of [`SplitLeds`]: the two rear corners, the
+/// centre lamp and indicator (or the indicator and a spare blank), and a blank.
+const REAR_PIXELS: u32 = 5;
+
+/// Words in a rear strip frame: the pixel count and the pixel words.
+pub const REAR_WORDS: usize = REAR_PIXELS as usize + 1;
+
+/// Front and rear strips on their own pins, from [`initialize_split_lights`].
+///
+/// Each strip is only sent its half of a frame when that half has changed,
+/// so a frame that only changes the rear, such as the brake lights coming on,
+/// leaves the front line idle. A half that changes while its strip is still
+/// clocking out the last one is held back and goes out on the next
+/// [`show`](Self::show) after the strip is free, so nothing is dropped or
+/// written over a frame part way through. Both are written from the CPU, like
+/// [`Leds::write`], and a rear frame fits in the FIFO with room to spare.
+#[cfg_attr(feature = "rtic", allow(dead_code))] // The RTIC build only drives one chain
+pub struct SplitLeds {
+    front: FrontTx,
+    rear: RearTx,
+    order: ColorOrder,
+    format: PixelFormat,
+    /// What each strip was last sent, or `None` before its first frame.
+    front_sent: Option<[u32; FRONT_WORDS]>,
+    rear_sent: Option<[u32; REAR_WORDS]>,
+}
+
+#[cfg_attr(feature = "rtic", allow(dead_code))] // The RTIC build only drives one chain
+impl SplitLeds {
+    /// Both strips wired in the same `order` and `format`.
+    pub fn new(front: FrontTx, rear: RearTx, order: ColorOrder, format: PixelFormat) -> Self {
+        Self {
+            front,
+            rear,
+            order,
+            format,
+            front_sent: None,
+            rear_sent: None,
+        }
+    }
+
+    /// Sends each strip its half of `leds`, if it changed and the strip is free.
+    pub fn show(&mut self, leds: &Leds) {
+        let (front, rear) = leds.split_words(self.order, self.format);
+        if self.front_sent != Some(front) && frame_complete(&self.front) {
+            defmt::trace!("Front LED words {=[u32]:08x}", &front[..]);
+            write_words(&mut self.front, &front);
+            self.front_sent = Some(front);
+        }
+        if self.rear_sent != Some(rear) && frame_complete(&self.rear) {
+            defmt::trace!("Rear LED words {=[u32]:08x}", &rear[..]);
+            write_words(&mut self.rear, &rear);
+            self.rear_sent = Some(rear);
+        }
+    }
+}
+
+/// Share of a white channel's level that full warmth adds to the amber channel beside it.
+const MAX_WARMTH_BLEND_DIV: u16 = 4;
+
+/// Amber to add next to a white channel at `white` for a given `warmth`.
+fn warmth_blend(white: u8, warmth: u8) -> u8 {
+    (white as u16 * warmth as u16 / (u8::MAX as u16 * MAX_WARMTH_BLEND_DIV)) as u8
+}
+
+impl Leds {
+    /// Warms the white outputs by blending in some of the yellow/amber channel.
+    ///
+    /// `warmth` runs from 0 (cool, whites untouched) to 255 (warm), where the
+    /// amber channel in the same pixel gains up to a quarter of the white
+    /// level. At the rear the white is the `white` channel; at the front it is
+    /// the brighter of the two beams. The amber is added with saturation, so
+    /// no channel goes past 255, and an indicator already at full stays full.
+    /// While a white is lit the yellow in that pixel never goes fully dark, so
+    /// keep `warmth` low if indicator contrast matters.
+    pub fn set_white_warmth(&mut self, warmth: u8) {
+        for front in [&mut self.front_left, &mut self.front_right] {
+            let white = front.low_beam.max(front.high_beam);
+            front.yellow = front.yellow.saturating_add(warmth_blend(white, warmth));
+        }
+
+        for rear in [&mut self.rear_left, &mut self.rear_right] {
+            rear.yellow = rear.yellow.saturating_add(warmth_blend(rear.white, warmth));
+        }
+    }
+}
+
+/// Whether the last frame written to `tx` has been fully clocked out, latch included.
+///
+/// An empty FIFO is not enough: the FIFO empties as soon as the state machine
+/// has pulled the last pixel word, while that pixel and the whole latch
+/// `Leds::frame_transmit_us` accounts for are still to run. Only once the latch
+/// finishes does the program loop back to its blocking `pull` with nothing to
+/// read, which sets the TX stall flag. So this is idle in the sense that the
+/// data line is back to low with nothing queued, and a new frame can be
+/// written without landing in the middle of one.
+///
+/// [`Leds::write`] pushes a whole frame at once, and it fits in the FIFO, so
+/// the program never stalls part way through a frame.
+pub fn frame_complete<SM: StateMachineIndex>(tx: &Tx<(PIO0, SM)>) -> bool {
+    tx.is_empty() && tx.has_stalled()
+}
+
+type FrameBuffer = &'static mut [u32; FRAME_WORDS];
+
+enum DmaState {
+    Idle(Channel<CH0>, FrameBuffer, Tx<(PIO0, SM0)>),
+    Busy(single_buffer::Transfer<Channel<CH0>, FrameBuffer, Tx<(PIO0, SM0)>>),
+}
+
+/// The LED strip fed by DMA, written with [`Leds::start_dma`].
+///
+/// The DMA channel paces itself on the PIO's FIFO, so the frame goes out as
+/// one unbroken burst without holding a critical section around the copy the
+/// way [`Leds::write`] has to. Owns the PIO's `Tx` and a static frame buffer,
+/// which move into the transfer while it runs.
+pub struct LedDma {
+    /// Only `None` while a method is swapping the state over.
+    state: Option<DmaState>,
+    order: ColorOrder,
+    format: PixelFormat,
+}
+
+impl LedDma {
+    /// Claims the frame buffer for a strip wired in `order` and `format`. Can only be called once.
+    pub fn new(
+        channel: Channel<CH0>,
+        tx: Tx<(PIO0, SM0)>,
+        order: ColorOrder,
+        format: PixelFormat,
+    ) -> Self {
+        let buffer = cortex_m::singleton!(: [u32; FRAME_WORDS] = [0; FRAME_WORDS]).unwrap();
+        Self {
+            state: Some(DmaState::Idle(channel, buffer, tx)),
+            order,
+            format,
+        }
+    }
+
+    /// The order the strip's pixels take their channels in.
+    pub fn order(&self) -> ColorOrder {
+        self.order
+    }
+
+    /// How many channels the strip's pixels have.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Starts sending an already packed frame. Like [`Leds::start_dma`], this
+    /// first waits for a previous frame that is still going out.
+    pub fn start(&mut self, words: [u32; FRAME_WORDS]) {
+        defmt::trace!("LED words {}", FrameHex(words));
+        let (channel, buffer, tx) = self.idle();
+        *buffer = words;
+        critical_section::with(|cs| scope_mark(cs, true));
+        // Re-armed here so `frame_complete` only sees the stall at the end of this frame
+        tx.clear_stalled_flag();
+        self.state = Some(DmaState::Busy(
+            single_buffer::Config::new(channel, buffer, tx).start(),
+        ));
+        critical_section::with(|cs| scope_mark(cs, false));
+    }
+
+    /// Clears the strip like [`Leds::all_off`], returning once the dark frame has been clocked out.
+    pub fn all_off(&mut self) {
+        self.start(Leds::OFF.debug_words(self.order, self.format));
+        while !self.frame_complete() {}
+    }
+
+    /// Takes the parts back, waiting for a running transfer to finish first.
+    fn idle(&mut self) -> (Channel<CH0>, FrameBuffer, Tx<(PIO0, SM0)>) {
+        match self.state.take().unwrap() {
+            DmaState::Idle(channel, buffer, tx) => (channel, buffer, tx),
+            DmaState::Busy(transfer) => transfer.wait(),
+        }
+    }
+
+    /// Whether the last frame has been fully handed over and clocked out. See [`frame_complete`].
+    pub fn frame_complete(&mut self) -> bool {
+        if let Some(DmaState::Busy(transfer)) = &self.state {
+            if !transfer.is_done() {
+                return false;
+            }
+        }
+
+        let (channel, buffer, tx) = self.idle();
+        let complete = frame_complete(&tx);
+        self.state = Some(DmaState::Idle(channel, buffer, tx));
+        complete
+    }
+}
+
+/// The level of a lit yellow indicator: the turn signals, the failsafe and
+/// not-ready blinks, the courtesy flash and the test pattern all use it, so
+/// they look alike. Dim, so none of them reads as an alarm.
+pub const INDICATOR_LEVEL: u8 = 42;
+
+/// How long each channel stays lit in [`run_startup_sequence`].
+const STARTUP_STEP_MS: u32 = 150;
+
+/// Level each channel is lit to in [`run_startup_sequence`]: enough to see, not enough to dazzle.
+const STARTUP_LEVEL: u8 = 64;
+
+/// Lights every channel of each corner in turn, then clears the strip, so the wiring can be checked.
+///
+/// Goes front left, front right, rear right, rear left, and within each corner
+/// the channels in wire order. Corners that aren't `enabled` stay dark for
+/// their turn. Blocks for about two seconds. It only touches the strip, so the
+/// receiver's interrupt keeps running underneath it.
+pub fn run_startup_sequence(strip: &mut LedDma, delay: &mut Delay, enabled: CornerMask) {
+    let corners: [fn(Color) -> Leds; 4] = [
+        |color| Leds {
+            front_left: color.into(),
+            ..Leds::default()
+        },
+        |color| Leds {
+            front_right: color.into(),
+            ..Leds::default()
+        },
+        |color| Leds {
+            rear_right: color.into(),
+            ..Leds::default()
+        },
+        |color| Leds {
+            rear_left: color.into(),
+            ..Leds::default()
+        },
+    ];
+    let channels = [
+        Color {
+            r: STARTUP_LEVEL,
+            ..Color::default()
+        },
+        Color {
+            g: STARTUP_LEVEL,
+            ..Color::default()
+        },
+        Color {
+            b: STARTUP_LEVEL,
+            ..Color::default()
+        },
+    ];
+
+    for corner in corners {
+        for color in channels {
+            Leds {
+                enabled,
+                ..corner(color)
+            }
+            .start_dma(strip);
+            delay.delay_ms(STARTUP_STEP_MS);
+        }
+    }
+    strip.all_off();
+}
+
+/// Formats a frame as its raw words, e.g. `00000006 0000002a 00000000 ... 00000000`.
+pub struct FrameHex(pub [u32; FRAME_WORDS]);
+
+impl defmt::Format for FrameHex {
+    fn format(&self, f: defmt::Formatter) {
+        for (i, word) in self.0.iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, " ");
+            }
+            defmt::write!(f, "{=u32:08x}", word);
+        }
+    }
+}
+
+impl core::fmt::Display for FrameHex {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, word) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:08x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+/// How long the test pattern holds each channel before moving to the next.
+#[cfg(feature = "receiver")]
+const TEST_PATTERN_STEP_MS: u64 = 1000;
+
+/// A slow sweep that lights one channel at a time, for checking LED wiring on the bench.
+///
+/// Steps through all twelve channels, corner by corner in write order, one
+/// every `TEST_PATTERN_STEP_MS`. The channel is picked from `now` alone, so
+/// callers can sample it at any rate.
+#[cfg(feature = "receiver")] // Only shown while waiting for the first receiver frame
+pub fn test_pattern_frame(now: Instant) -> Leds {
+    let step = now.duration_since_epoch().to_millis() / TEST_PATTERN_STEP_MS;
+    single_channel_frame(step as usize % CHANNEL_NAMES.len(), INDICATOR_LEVEL)
+}
+
+/// Every LED channel in write order, by where it is on the car.
+#[cfg(feature = "receiver")]
+const CHANNEL_NAMES: [&str; 12] = [
+    "front left yellow",
+    "front left low beam",
+    "front left high beam",
+    "front right yellow",
+    "front right low beam",
+    "front right high beam",
+    "rear right yellow",
+    "rear right white",
+    "rear right red",
+    "rear left yellow",
+    "rear left white",
+    "rear left red",
+];
+
+/// A frame with only the channel at `index` in `CHANNEL_NAMES` lit, at `level`.
+#[cfg(feature = "receiver")]
+fn single_channel_frame(index: usize, level: u8) -> Leds {
+    let mut leds = Leds::default();
+    let channel = match index {
+        0 => &mut leds.front_left.yellow,
+        1 => &mut leds.front_left.low_beam,
+        2 => &mut leds.front_left.high_beam,
+        3 => &mut leds.front_right.yellow,
+        4 => &mut leds.front_right.low_beam,
+        5 => &mut leds.front_right.high_beam,
+        6 => &mut leds.rear_right.yellow,
+        7 => &mut leds.rear_right.white,
+        8 => &mut leds.rear_right.red,
+        9 => &mut leds.rear_left.yellow,
+        10 => &mut leds.rear_left.white,
+        _ => &mut leds.rear_left.red,
+    };
+    *channel = level;
+
+    leds
+}
+
+/// How long the lights test holds each channel.
+#[cfg(feature = "receiver")]
+const LIGHTS_TEST_STEP: MillisDurationU64 = MillisDurationU64::millis(1500);
+
+/// Brightness of the lit channel in the lights test.
+#[cfg(feature = "receiver")]
+const LIGHTS_TEST_LEVEL: u8 = 128;
+
+/// A diagnostic mode that walks through the LED channels one at a time to find miswired corners.
+///
+/// Unlike the boot-time test pattern, it is started and stopped on demand,
+/// and it starts from the first channel each time. Each step lasts
+/// `LIGHTS_TEST_STEP` and is picked from the time since the start, so it
+/// advances by itself however often [`LightsTest::update`] runs. The channel
+/// being lit is logged as each step begins.
+#[cfg(feature = "receiver")]
+pub struct LightsTest {
+    /// When the test started and the last step logged, or `None` while stopped.
+    running: Option<(Instant, usize)>,
+}
+
+#[cfg(feature = "receiver")]
+impl LightsTest {
+    pub fn new() -> Self {
+        Self { running: None }
+    }
+
+    /// Starts the test from the first channel if it is stopped, or stops it if it is running.
+    pub fn toggle(&mut self, now: Instant) {
+        self.running = match self.running {
+            Some(_) => {
+                defmt::info!("Lights test stopped");
+                None
+            }
+            None => {
+                defmt::info!("Lights test started");
+                // Not a valid step, so the first one is logged
+                Some((now, usize::MAX))
+            }
+        };
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// The frame to show at `now`, or `None` while the test is stopped.
+    pub fn update(&mut self, now: Instant) -> Option<Leds> {
+        let (since, logged) = self.running.as_mut()?;
+        let step = (now - *since).to_millis() / LIGHTS_TEST_STEP.to_millis();
+        let index = step as usize % CHANNEL_NAMES.len();
+        if index != *logged {
+            defmt::info!(
+                "Lights test: channel {=usize} of {=usize}, {=str}",
+                index + 1,
+                CHANNEL_NAMES.len(),
+                CHANNEL_NAMES[index]
+            );
+            *logged = index;
+        }
+        Some(single_channel_frame(index, LIGHTS_TEST_LEVEL))
+    }
+}
+
+/// Rear red level while the brake is on.
+#[cfg(feature = "receiver")]
+const BRAKE_LEVEL: u8 = 255;
+
+/// Brake lights that grow outward from the centre of a corner's LEDs, then hold.
+///
+/// `N` is the number of red LEDs in one rear corner. When the brake engages,
+/// LEDs light from the middle out over `duration`, and collapse back towards
+/// the middle at the same rate when it releases. Releasing part way through
+/// reverses from wherever the animation got to. With a single LED (`N == 1`)
+/// there is nothing to animate, so it is simply on while braking and off
+/// otherwise.
+#[cfg(feature = "receiver")] // Braking comes from the throttle channel
+pub struct BrakeExpand<const N: usize> {
+    duration: MillisDurationU64,
+    /// How far through the animation we are, in µs from fully collapsed.
+    progress_us: u64,
+    last_update: Option<Instant>,
+}
+
+#[cfg(feature = "receiver")]
+impl<const N: usize> BrakeExpand<N> {
+    pub fn new(duration: MillisDurationU64) -> Self {
+        Self {
+            duration,
+            progress_us: 0,
+            last_update: None,
+        }
+    }
+
+    /// Advances the animation to `now` and returns each LED's red level, left to right.
+    pub fn update(&mut self, braking: bool, now: Instant) -> [u8; N] {
+        let elapsed_us = match self.last_update {
+            Some(last) => (now - last).to_micros(),
+            None => 0,
+        };
+        self.last_update = Some(now);
+
+        let duration_us = self.duration.to_micros();
+        self.progress_us = if braking {
+            (self.progress_us + elapsed_us).min(duration_us)
+        } else {
+            self.progress_us.saturating_sub(elapsed_us)
+        };
+
+        if N == 1 || duration_us == 0 {
+            return [if braking { BRAKE_LEVEL } else { 0 }; N];
+        }
+
+        // Each LED's distance from the centre, in half-LED steps: 0 or 1 in the
+        // middle, N - 1 at the ends. An LED is lit once the spread passes it.
+        let spread = self.progress_us * N as u64 / duration_us;
+        let mut levels = [0; N];
+        for (i, level) in levels.iter_mut().enumerate() {
+            let distance = (2 * i as i32 + 1 - N as i32).unsigned_abs() as u64;
+            if distance < spread {
+                *level = BRAKE_LEVEL;
+            }
+        }
+        levels
+    }
+}
+
+/// When the signal-acquired flash fires.
+#[cfg(feature = "receiver")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // ACQUIRE_FLASH_MODE sets one; the flash only matches on the rest
+pub enum AcquireFlashMode {
+    Off,
+    /// Only the first time a signal appears after power on.
+    FirstSignal,
+    /// Every time the signal comes back after being lost.
+    EveryReconnect,
+}
+
+/// A one-shot flash confirming the link is live.
+///
+/// Fires on the transition from no signal to signal, and stays active for
+/// `duration` from then, measured on the `Timer`. What it shows is up to the
+/// caller. This is driven by the signal, not by time since boot, so a board
+/// that powers up with the transmitter already on flashes as soon as the
+/// first frame arrives.
+#[cfg(feature = "receiver")]
+pub struct AcquireFlash {
+    mode: AcquireFlashMode,
+    duration: MillisDurationU64,
+    had_signal: bool,
+    fired: bool,
+    started: Option<Instant>,
+}
+
+#[cfg(feature = "receiver")]
+impl AcquireFlash {
+    pub fn new(mode: AcquireFlashMode, duration: MillisDurationU64) -> Self {
+        Self {
+            mode,
+            duration,
+            had_signal: false,
+            fired: false,
+            started: None,
+        }
+    }
+
+    /// Feeds the current signal state and returns whether the flash should be showing.
+    pub fn update(&mut self, has_signal: bool, now: Instant) -> bool {
+        let acquired = has_signal && !self.had_signal;
+        self.had_signal = has_signal;
+
+        let allowed = match self.mode {
+            AcquireFlashMode::Off => false,
+            AcquireFlashMode::FirstSignal => !self.fired,
+            AcquireFlashMode::EveryReconnect => true,
+        };
+        if acquired && allowed {
+            self.fired = true;
+            self.started = Some(now);
+        }
+
+        match self.started {
+            Some(started) if now - started < self.duration => true,
+            _ => {
+                self.started = None;
+                false
+            }
+        }
+    }
+}
+
+/// The share of a welcome or farewell the courtesy flash lasts, as a divisor.
+#[cfg(feature = "receiver")]
+const COURTESY_FLASH_DIV: u64 = 4;
+
+/// Which of [`WelcomeSequence`]'s animations is playing.
+#[cfg(feature = "receiver")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+enum Greeting {
+    Welcome,
+    Farewell,
+}
+
+/// The "coming home" and "leaving home" animations, played as the car is
+/// armed and disarmed.
+///
+/// Arming plays the welcome: the indicators give one courtesy flash while
+/// the headlights fade up. Disarming, or losing the signal, plays the
+/// farewell: the same flash while the headlights fade back down. Each takes
+/// `duration`, and is timed from the `Timer` by [`update`](Self::update)
+/// rather than by delays, so nothing blocks and the receiver's interrupt
+/// keeps running.
+///
+/// A change only counts once it has held for `debounce`, so a signal that
+/// flickers at power-up plays one welcome once it settles rather than a loop
+/// of them. A new edge cuts short whatever is still playing.
+#[cfg(feature = "receiver")]
+pub struct WelcomeSequence {
+    enabled: bool,
+    duration: MillisDurationU64,
+    debounce: MillisDurationU64,
+    /// The debounced state. It starts disarmed, so power-up never says farewell.
+    armed: bool,
+    /// When the input started disagreeing with `armed`.
+    changed_at: Option<Instant>,
+    playing: Option<(Greeting, Instant)>,
+}
+
+#[cfg(feature = "receiver")]
+impl WelcomeSequence {
+    /// With `enabled` false the edges are still followed, but nothing ever plays.
+    pub fn new(enabled: bool, duration: MillisDurationU64, debounce: MillisDurationU64) -> Self {
+        Self {
+            enabled,
+            duration,
+            debounce,
+            armed: false,
+            changed_at: None,
+            playing: None,
+        }
+    }
+
+    /// Feeds whether the car is armed with a signal, and returns the frame to
+    /// show, or `None` when no animation is playing.
+    pub fn update(&mut self, armed: bool, now: Instant) -> Option<Leds> {
+        if armed == self.armed {
+            self.changed_at = None;
+        } else {
+            let since = *self.changed_at.get_or_insert(now);
+            if now - since >= self.debounce {
+                self.armed = armed;
+                self.changed_at = None;
+                if self.enabled {
+                    let greeting = if armed {
+                        Greeting::Welcome
+                    } else {
+                        Greeting::Farewell
+                    };
+                    defmt::info!("Playing {}", greeting);
+                    self.playing = Some((greeting, now));
+                }
+            }
+        }
+
+        let (greeting, started) = self.playing?;
+        let elapsed = (now - started).to_millis();
+        let duration = self.duration.to_millis().max(1);
+        if elapsed >= duration {
+            self.playing = None;
+            return None;
+        }
+        let up = lerp_channel(0, u8::MAX, elapsed, duration);
+        let low_beam = match greeting {
+            Greeting::Welcome => up,
+            Greeting::Farewell => u8::MAX - up,
+        };
+        let yellow = if elapsed < duration / COURTESY_FLASH_DIV {
+            INDICATOR_LEVEL
+        } else {
+            0
+        };
+        let front = FrontLeds {
+            yellow,
+            low_beam,
+            high_beam: 0,
+        };
+        let rear = RearLeds {
+            yellow,
+            white: 0,
+            red: 0,
+        };
+        Some(Leds {
+            front_right: front,
+            front_left: front,
+            rear_right: rear,
+            rear_left: rear,
+            ..Leds::default()
+        })
+    }
+}
+
+/// How many times longer than `frames` the gate can end up waiting for a flapping signal.
+#[cfg(feature = "receiver")]
+const MAX_RECOVERY_BACKOFF: u32 = 8;
+
+/// Keeps the lights in failsafe after the signal returns until it has proven
+/// stable, then fades them back in.
+///
+/// The signal has to deliver `frames` frames in a row, with no failsafe and
+/// no glitch in between, before [`update`](Self::update) lets go. The lights
+/// then come up from dark over `fade`, see [`level`](Self::level), rather
+/// than snapping to whatever was commanded. Losing the signal again goes
+/// straight back to failsafe, as it always has.
+///
+/// A signal that drops again within `flap_window` of being let go doubles
+/// the frames the next recovery needs, up to `MAX_RECOVERY_BACKOFF` times
+/// `frames`, so one that keeps coming and going stays in failsafe instead of
+/// flickering in and out. After a `flap_window` without a drop it is back to
+/// `frames`. A `frames` of 0 lets go on the first update without failsafe.
+#[cfg(feature = "receiver")]
+pub struct RecoveryGate {
+    frames: u32,
+    fade: MillisDurationU64,
+    flap_window: MillisDurationU64,
+    /// Frames the current recovery needs, `frames` or more while flapping.
+    needed: u32,
+    /// The frame and glitch counts the current run of good frames started from,
+    /// or `None` while in failsafe.
+    run_from: Option<(u32, u32)>,
+    /// When the gate last let go, or `None` while holding.
+    released_at: Option<Instant>,
+}
+
+#[cfg(feature = "receiver")]
+impl RecoveryGate {
+    pub fn new(frames: u32, fade: MillisDurationU64, flap_window: MillisDurationU64) -> Self {
+        Self {
+            frames,
+            fade,
+            flap_window,
+            needed: frames,
+            run_from: None,
+            released_at: None,
+        }
+    }
+
+    /// Whether the lights should still show failsafe, given the receiver's
+    /// own `failsafe` and its running frame and glitch counts.
+    pub fn update(&mut self, failsafe: bool, frames: u32, glitches: u32, now: Instant) -> bool {
+        if failsafe {
+            if let Some(released_at) = self.released_at.take() {
+                self.needed = if now - released_at < self.flap_window {
+                    (self.needed * 2).min(self.frames * MAX_RECOVERY_BACKOFF)
+                } else {
+                    self.frames
+                };
+            }
+            self.run_from = None;
+            return true;
+        }
+        if let Some(released_at) = self.released_at {
+            if now - released_at >= self.flap_window {
+                self.needed = self.frames;
+            }
+            return false;
+        }
+
+        let (from_frames, from_glitches) = *self.run_from.get_or_insert((frames, glitches));
+        // A glitch breaks the run, and so do counters going backwards, as after a reset
+        if glitches != from_glitches || frames < from_frames {
+            self.run_from = Some((frames, glitches));
+            return true;
+        }
+        if frames - from_frames < self.needed {
+            return true;
+        }
+        self.released_at = Some(now);
+        false
+    }
+
+    /// How far the fade back in has got at `now`, from 0 as the gate lets go
+    /// up to 255 once `fade` has passed. 255 while holding, as failsafe shows
+    /// as it is.
+    pub fn level(&self, now: Instant) -> u8 {
+        match self.released_at {
+            Some(released_at) if now - released_at < self.fade => lerp_channel(
+                0,
+                u8::MAX,
+                (now - released_at).to_millis(),
+                self.fade.to_millis(),
+            ),
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// The brightness `phase` ms into a breath `period` ms long: `max` at the
+/// start and end, `min` halfway, easing in and out of each like a sine.
+///
+/// Each half is two quadratic pieces meeting at the midpoint, which is close
+/// enough to a raised cosine to look smooth without any floating point.
+#[cfg(feature = "receiver")]
+const fn breathe_level(phase: u64, period: u64, min: u8, max: u8) -> u8 {
+    let half = period / 2;
+    if half == 0 {
+        return max;
+    }
+    let phase = phase % period;
+    let from_top = if phase < half { phase } else { period - phase };
+    // How far down towards `min`, with 256 at the bottom
+    let u = if from_top >= half {
+        256
+    } else {
+        from_top * 256 / half
+    };
+    // Eased depth, with 65536 all the way down
+    let depth = if u < 128 {
+        2 * u * u
+    } else {
+        65536 - 2 * (256 - u) * (256 - u)
+    };
+    let span = max.saturating_sub(min) as u64;
+    max - (span * depth / 65536) as u8
+}
+
+#[cfg(feature = "receiver")]
+const _: () = {
+    assert!(breathe_level(0, 4000, 64, 255) == 255);
+    assert!(breathe_level(2000, 4000, 64, 255) == 64);
+    assert!(breathe_level(4000, 4000, 64, 255) == 255);
+    // Halfway down at a quarter period, and the same on the way back up
+    assert!(breathe_level(1000, 4000, 0, 254) == 127);
+    assert!(breathe_level(3000, 4000, 0, 254) == 127);
+    // Slow at the top, fast in the middle
+    assert!(breathe_level(200, 4000, 0, 255) > 250);
+    assert!(breathe_level(1200, 4000, 0, 255) < 102);
+    // Degenerate settings hold still
+    assert!(breathe_level(1000, 0, 64, 255) == 255);
+    assert!(breathe_level(1000, 4000, 200, 100) == 100);
+};
+
+/// A slow brightness wave, such as for headlights breathing while parked.
+///
+/// Each breath starts at `max`, eases down to `min` and back up over
+/// `period`. Starting begins a breath from the top, and stopping lets the
+/// breath in progress finish, so the level never jumps: it only ever leaves
+/// or settles on `max`, which [`Breathe::tick`] also returns while stopped.
+#[cfg(feature = "receiver")]
+pub struct Breathe {
+    period: MillisDurationU64,
+    min: u8,
+    max: u8,
+    /// When the first breath started, or `None` while stopped.
+    since: Option<Instant>,
+    /// The breath to finish on after a stop, counted from `since`.
+    last_breath: Option<u64>,
+}
+
+#[cfg(feature = "receiver")]
+impl Breathe {
+    pub fn new(period: MillisDurationU64, min: u8, max: u8) -> Self {
+        Self {
+            period,
+            min,
+            max,
+            since: None,
+            last_breath: None,
+        }
+    }
+
+    /// Starts breathing from the top, or keeps going if it already is,
+    /// cancelling a stop that hadn't finished yet.
+    pub fn start(&mut self, now: Instant) {
+        self.since.get_or_insert(now);
+        self.last_breath = None;
+    }
+
+    /// Stops once the breath in progress has come back up to `max`.
+    pub fn stop(&mut self, now: Instant) {
+        if let Some(since) = self.since {
+            let breath = (now - since).to_millis() / self.period.to_millis().max(1);
+            self.last_breath.get_or_insert(breath);
+        }
+    }
+
+    /// The brightness at `now`.
+    pub fn tick(&mut self, now: Instant) -> u8 {
+        let Some(since) = self.since else {
+            return self.max;
+        };
+
+        let elapsed = (now - since).to_millis();
+        let period = self.period.to_millis().max(1);
+        if self.last_breath.is_some_and(|last| elapsed / period > last) {
+            self.since = None;
+            self.last_breath = None;
+            return self.max;
+        }
+        breathe_level(elapsed, period, self.min, self.max)
+    }
+}
+
+/// Scales one channel by a master brightness, where 255 leaves it unchanged.
+pub const fn scale_channel(value: u8, brightness: u8) -> u8 {
+    ((value as u16 * brightness as u16 + 127) / 255) as u8
+}
+
+const _: () = {
+    // The largest product plus the rounding still fits the u16
+    assert!(u8::MAX as u32 * u8::MAX as u32 + 127 <= u16::MAX as u32);
+    let mut value = 0;
+    loop {
+        assert!(scale_channel(value, 255) == value);
+        assert!(scale_channel(value, 0) == 0);
+        if value == u8::MAX {
+            break;
+        }
+        value += 1;
+    }
+    // Rounded to the nearest level, not truncated
+    assert!(scale_channel(255, 128) == 128);
+    assert!(scale_channel(1, 127) == 0);
+    assert!(scale_channel(1, 128) == 1);
+};
+
+/// `255 * (i / 255) ^ 2.2`, rounded, for every 8-bit level `i`.
+///
+/// Generated offline so it lives in flash. Levels below 15 all round to 0, so the
+/// table only rises monotonically, not strictly.
+const GAMMA_TABLE: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11,
+    11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 22, 22, 23,
+    23, 24, 25, 25, 26, 26, 27, 28, 28, 29, 30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39,
+    40, 41, 42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61,
+    62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88,
+    89, 90, 91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111, 113, 114, 116,
+    117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135, 137, 138, 140, 141, 143, 145,
+    146, 148, 149, 151, 153, 154, 156, 158, 159, 161, 163, 165, 166, 168, 170, 172, 173, 175, 177,
+    179, 181, 182, 184, 186, 188, 190, 192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213,
+    215, 217, 219, 221, 223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253,
+    255,
+];
+
+/// Maps a linear channel level to what the LEDs need to look that bright.
+///
+/// WS2812s are linear in light output, but the eye isn't, so uncorrected low
+/// levels look far too bright. 0 and 255 map to themselves.
+pub const fn gamma8(value: u8) -> u8 {
+    GAMMA_TABLE[value as usize]
+}
+
+const _: () = {
+    assert!(gamma8(0) == 0);
+    assert!(gamma8(255) == 255);
+    // A mistyped entry would show as a dip, dimming a light as it fades up
+    let mut i = 0;
+    while i < 255 {
+        assert!(gamma8(i) <= gamma8(i + 1));
+        i += 1;
+    }
+};
+
+/// Runs `f` over each of a pixel's three channels, whatever they light.
+fn map_pixel<P: Into<Color> + From<Color>>(pixel: P, f: impl Fn(u8) -> u8) -> P {
+    let color = pixel.into();
+    P::from(Color {
+        r: f(color.r),
+        g: f(color.g),
+        b: f(color.b),
+    })
+}
+
+/// Runs `f` over each pair of matching channels in two pixels.
+fn zip_pixel<P: Into<Color> + From<Color>>(a: P, b: P, f: impl Fn(u8, u8) -> u8) -> P {
+    let (a, b) = (a.into(), b.into());
+    P::from(Color {
+        r: f(a.r, b.r),
+        g: f(a.g, b.g),
+        b: f(a.b, b.b),
+    })
 }
 
 impl Leds {
-    pub fn write(&self, tx: &mut Tx<(PIO0, SM0)>) {
-        critical_section::with(|_cs| {
-            tx.write(self.front_left.into());
-            tx.write(self.front_right.into());
-            tx.write(self.rear_right.into());
-            tx.write(self.rear_left.into());
-            tx.write(0xFF000000u32);
-            tx.write(0);
-            tx.write(42);
+    /// Combines each channel with the matching one in `other`.
+    ///
+    /// `other` is the frame being moved towards, so its corner mask is the one kept.
+    fn zip_channels(&self, other: &Leds, f: impl Fn(u8, u8) -> u8) -> Leds {
+        Leds {
+            front_right: zip_pixel(self.front_right, other.front_right, &f),
+            front_left: zip_pixel(self.front_left, other.front_left, &f),
+            rear_right: zip_pixel(self.rear_right, other.rear_right, &f),
+            rear_left: zip_pixel(self.rear_left, other.rear_left, &f),
+            indicator: zip_pixel(self.indicator, other.indicator, &f),
+            // A lamp missing from one side reads as dark, so it fades in and out like the rest
+            center: (self.center.is_some() || other.center.is_some())
+                .then(|| f(self.center.unwrap_or(0), other.center.unwrap_or(0))),
+            enabled: other.enabled,
+        }
+    }
+
+    fn map_channels(&self, f: impl Fn(u8) -> u8) -> Leds {
+        Leds {
+            front_right: map_pixel(self.front_right, &f),
+            front_left: map_pixel(self.front_left, &f),
+            rear_right: map_pixel(self.rear_right, &f),
+            rear_left: map_pixel(self.rear_left, &f),
+            indicator: map_pixel(self.indicator, &f),
+            center: self.center.map(&f),
+            enabled: self.enabled,
+        }
+    }
+
+    /// Every channel scaled by a master `brightness`, where 255 is unchanged and 0 is off.
+    pub fn scaled(&self, brightness: u8) -> Leds {
+        self.map_channels(|value| scale_channel(value, brightness))
+    }
+
+    /// Every channel run through [`gamma8`]. Frames that are already corrected
+    /// should be written as they are instead.
+    pub fn gamma_corrected(&self) -> Leds {
+        self.map_channels(gamma8)
+    }
+
+    /// Writes the frame dimmed by a master `brightness`. At 255 this sends exactly what `write` does.
+    #[allow(dead_code)] // The blocking path, for builds that need DMA channel 0 for something else
+    pub fn write_scaled(
+        &self,
+        tx: &mut Tx<(PIO0, SM0)>,
+        brightness: u8,
+        order: ColorOrder,
+        format: PixelFormat,
+    ) {
+        self.scaled(brightness).write(tx, order, format);
+    }
+}
+
+/// Aux pulse widths (in µs) that map to the bottom and top of the dimmer.
+#[cfg(feature = "receiver")]
+const DIMMER_LOW_US: u16 = 1000;
+#[cfg(feature = "receiver")]
+const DIMMER_HIGH_US: u16 = 2000;
+
+/// Turns an aux channel (a knob or slider) into a master brightness.
+///
+/// The pulse maps linearly from `DIMMER_LOW_US..=DIMMER_HIGH_US` onto
+/// `min..=255`, clamping outside that range, so 1000 µs is `min`, 1500 µs is
+/// about halfway and 2000 µs is full. Each update moves a quarter of the way
+/// towards the new target, so turning the knob fades rather than steps. While
+/// there is no valid aux pulse the last brightness is held; it starts at full.
+#[cfg(feature = "receiver")]
+pub struct MasterDimmer {
+    min: u8,
+    /// Smoothed brightness in 8.8 fixed point.
+    level: u16,
+}
+
+#[cfg(feature = "receiver")]
+impl MasterDimmer {
+    pub fn new(min: u8) -> Self {
+        Self {
+            min,
+            level: (u8::MAX as u16) << 8,
+        }
+    }
+
+    /// Feeds the latest aux pulse and returns the brightness to use this tick.
+    pub fn update(&mut self, aux: Option<u16>) -> u8 {
+        if let Some(pulse) = aux {
+            let clamped = pulse.clamp(DIMMER_LOW_US, DIMMER_HIGH_US) - DIMMER_LOW_US;
+            let span = (u8::MAX - self.min) as u32;
+            let target =
+                self.min as u32 + (clamped as u32 * span) / (DIMMER_HIGH_US - DIMMER_LOW_US) as u32;
+
+            let target = (target << 8) as i32;
+            let level = self.level as i32;
+            self.level = (level + (target - level) / 4) as u16;
+        }
+
+        ((self.level + 0x80) >> 8).min(u8::MAX as u16) as u8
+    }
+}
+
+/// Keeps frames at least one frame time apart, so a strip is never handed a
+/// new frame while the last is still going out.
+///
+/// Ask [`FrameLimiter::ready`] before each write, as often as suits the
+/// caller: it only says yes once the interval since the last frame it allowed
+/// has passed, and a frame it turns away is simply dropped, as the next
+/// update brings a newer one.
+pub struct FrameLimiter {
+    min_interval_us: u32,
+    sent_at: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Allows one frame per `min_interval_us`, such as [`Leds::frame_transmit_us`] for the strip.
+    pub const fn new(min_interval_us: u32) -> Self {
+        Self {
+            min_interval_us,
+            sent_at: None,
+        }
+    }
+
+    /// Whether a frame may be written at `now`. Saying yes counts as the write.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        let interval = MicrosDurationU64::micros(self.min_interval_us as u64);
+        if self.sent_at.is_some_and(|at| now - at < interval) {
+            return false;
+        }
+        self.sent_at = Some(now);
+        true
+    }
+
+    /// The most frames per second this lets through.
+    pub const fn max_fps(&self) -> u32 {
+        1_000_000
+            / if self.min_interval_us == 0 {
+                1
+            } else {
+                self.min_interval_us
+            }
+    }
+}
+
+/// Moves `current` towards `target` by at most `max_delta`.
+fn slew_channel(current: u8, target: u8, max_delta: u8) -> u8 {
+    if target > current {
+        current.saturating_add(max_delta).min(target)
+    } else {
+        current.saturating_sub(max_delta).max(target)
+    }
+}
+
+/// Output-side smoothing that limits how far any channel can move per frame.
+///
+/// Each call to [`SlewLimiter::apply`] moves every channel at most `max_delta`
+/// towards the commanded value, so a 0 to 255 step takes `255 / max_delta`
+/// frames to complete. A `max_delta` of 255 (the default) passes frames through
+/// unchanged.
+///
+/// This applies to everything that is written, including animations. Effects
+/// that depend on hard edges, such as turn signal blinks, get rounded off when
+/// the limit is low relative to the frame rate.
+pub struct SlewLimiter {
+    max_delta: u8,
+    previous: Leds,
+}
+
+impl SlewLimiter {
+    pub const NO_LIMIT: u8 = u8::MAX;
+
+    pub fn new(max_delta: u8) -> Self {
+        Self {
+            max_delta,
+            previous: Leds::default(),
+        }
+    }
+
+    /// Returns the frame to send this tick and remembers it as the new starting point.
+    pub fn apply(&mut self, target: &Leds) -> Leds {
+        let max_delta = self.max_delta;
+        self.previous = self.previous.zip_channels(target, |current, target| {
+            slew_channel(current, target, max_delta)
         });
+        self.previous
+    }
+}
+
+/// Where a channel is `elapsed` of the way through a `duration` long fade from `from` to `to`.
+pub fn lerp_channel(from: u8, to: u8, elapsed: u64, duration: u64) -> u8 {
+    let span = to as i64 - from as i64;
+    (from as i64 + span * elapsed as i64 / duration as i64) as u8
+}
+
+/// How far a fade from the first value to the second has got, `elapsed` ms of `duration` in.
+pub type Blend<T> = fn(T, T, u64, u64) -> T;
+
+/// A value fading to its target over a fixed time.
+///
+/// A new target starts from wherever the last fade had got to, and once
+/// `duration` has passed the target is returned exactly. A zero `duration`
+/// switches straight to it.
+pub struct Fade<T> {
+    duration: MillisDurationU64,
+    blend: Blend<T>,
+    from: T,
+    target: T,
+    /// When the current fade started, or `None` once it has finished.
+    since: Option<Instant>,
+}
+
+impl<T: Copy + PartialEq> Fade<T> {
+    /// Starts sitting on `value`.
+    pub fn new(value: T, duration: MillisDurationU64, blend: Blend<T>) -> Self {
+        Self {
+            duration,
+            blend,
+            from: value,
+            target: value,
+            since: None,
+        }
+    }
+
+    /// Starts fading towards `target`, unless it is already the target.
+    ///
+    /// Returns whether a new fade started.
+    pub fn set_target(&mut self, target: T, now: Instant) -> bool {
+        if target == self.target {
+            return false;
+        }
+        self.from = self.at(now);
+        self.target = target;
+        self.since = Some(now);
+        true
+    }
+
+    /// The value at `now`.
+    pub fn at(&mut self, now: Instant) -> T {
+        let Some(since) = self.since else {
+            return self.target;
+        };
+
+        let elapsed = (now - since).to_millis();
+        let duration = self.duration.to_millis();
+        if elapsed >= duration {
+            self.since = None;
+            return self.target;
+        }
+        (self.blend)(self.from, self.target, elapsed, duration)
+    }
+}
+
+/// Fades linearly between frames over a fixed time.
+///
+/// Each new target starts a fade from whatever was showing at that moment,
+/// so changing target part way through carries on from where the last fade
+/// had got to rather than jumping. Once `duration` has passed the target is
+/// returned exactly, so a fade never stops a step short. A zero `duration`
+/// passes frames straight through.
+///
+/// Like [`SlewLimiter`], this rounds off everything, turn signal blinks
+/// included, so long fades suit steady lights better than blinking ones.
+pub struct Animator {
+    fade: Fade<Leds>,
+}
+
+impl Animator {
+    pub fn new(duration: MillisDurationU64) -> Self {
+        Self {
+            fade: Fade::new(Leds::default(), duration, |from, to, elapsed, duration| {
+                from.zip_channels(&to, |from, to| lerp_channel(from, to, elapsed, duration))
+            }),
+        }
+    }
+
+    /// Starts fading towards `target`, unless it is already the target.
+    pub fn set_target(&mut self, target: Leds, now: Instant) {
+        self.fade.set_target(target, now);
+    }
+
+    /// The frame to show at `now`.
+    pub fn tick(&mut self, now: Instant) -> Leds {
+        self.fade.at(now)
+    }
+}
+
+/// What one side of the car shows during a strobe step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Strobe {
+    Off,
+    /// The rear red, with the front yellow as the nearest thing the front has.
+    Red,
+    /// The front high beam and the rear white.
+    White,
+}
+
+/// One step of a strobe pattern: the left side, then the right.
+type StrobeStep = (Strobe, Strobe);
+
+const ALTERNATING_FLASH: &[StrobeStep] =
+    &[(Strobe::Red, Strobe::Off), (Strobe::Off, Strobe::White)];
+
+const QUAD_FLASH: &[StrobeStep] = &[
+    (Strobe::Red, Strobe::Off),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Red, Strobe::Off),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Red, Strobe::Off),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Red, Strobe::Off),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Off, Strobe::White),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Off, Strobe::White),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Off, Strobe::White),
+    (Strobe::Off, Strobe::Off),
+    (Strobe::Off, Strobe::White),
+    (Strobe::Off, Strobe::Off),
+];
+
+/// A police-style strobe, as played by [`PatternPlayer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // Only the pattern STROBE_PATTERN plays, if any, is ever built
+pub enum StrobePattern {
+    /// Left red, then right white.
+    AlternatingFlash,
+    /// Four quick red flashes on the left, then four white on the right.
+    QuadFlash,
+}
+
+impl StrobePattern {
+    fn steps(self) -> &'static [StrobeStep] {
+        match self {
+            StrobePattern::AlternatingFlash => ALTERNATING_FLASH,
+            StrobePattern::QuadFlash => QUAD_FLASH,
+        }
+    }
+}
+
+/// One side's front and rear corner for a strobe step, at full level.
+fn strobe_corners(strobe: Strobe) -> (FrontLeds, RearLeds) {
+    let (red, white) = match strobe {
+        Strobe::Off => (0, 0),
+        Strobe::Red => (255, 0),
+        Strobe::White => (0, 255),
+    };
+    (
+        FrontLeds {
+            yellow: red,
+            low_beam: 0,
+            high_beam: white,
+        },
+        RearLeds {
+            yellow: 0,
+            white,
+            red,
+        },
+    )
+}
+
+/// Plays a [`StrobePattern`] from the timer.
+///
+/// Each pattern is a table of steps that share `period` equally, so a
+/// pattern with more steps flashes faster. The step is picked from the
+/// time since the pattern started, so callers can sample it at any rate.
+/// Asking for a different pattern starts it from its first step.
+pub struct PatternPlayer {
+    period: MillisDurationU64,
+    playing: Option<(StrobePattern, Instant)>,
+}
+
+impl PatternPlayer {
+    /// `period` is how long one pass through a pattern takes.
+    pub fn new(period: MillisDurationU64) -> Self {
+        Self {
+            period,
+            playing: None,
+        }
+    }
+
+    /// The frame `pattern` shows at `now`.
+    pub fn update(&mut self, pattern: StrobePattern, now: Instant) -> Leds {
+        let since = match self.playing {
+            Some((playing, since)) if playing == pattern => since,
+            _ => now,
+        };
+        self.playing = Some((pattern, since));
+
+        let steps = pattern.steps();
+        let elapsed = (now - since).to_millis();
+        let step = elapsed * steps.len() as u64 / self.period.to_millis().max(1);
+        let (left, right) = steps[step as usize % steps.len()];
+        let (front_left, rear_left) = strobe_corners(left);
+        let (front_right, rear_right) = strobe_corners(right);
+
+        Leds {
+            front_right,
+            front_left,
+            rear_right,
+            rear_left,
+            indicator: IndicatorLed::default(),
+            center: None,
+            enabled: CornerMask::ALL,
+        }
+    }
+}
+
+/// Slowest tach pulse, at idle.
+#[cfg(feature = "receiver")]
+const TACH_IDLE_PERIOD_MS: u32 = 1200;
+
+/// Fastest tach pulse, at full throttle.
+#[cfg(feature = "receiver")]
+const TACH_FULL_PERIOD_MS: u32 = 120;
+
+/// Peak of the tach pulse at idle, and at full throttle.
+#[cfg(feature = "receiver")]
+const TACH_IDLE_LEVEL: u8 = 8;
+#[cfg(feature = "receiver")]
+const TACH_FULL_LEVEL: u8 = 64;
+
+/// One full tach cycle in `TachPulse::phase` units.
+#[cfg(feature = "receiver")]
+const TACH_CYCLE: u32 = 1 << 16;
+
+/// A triangle wave on the rear whites that speeds up and brightens with the throttle, like a tach.
+///
+/// The phase is advanced by the time since the last tick at the current
+/// rate, rather than worked out from the time since it started, so changing
+/// the throttle changes the speed without making the wave jump. Without a
+/// throttle reading it goes dark and starts again from dark when the signal
+/// returns, so it never runs on by itself.
+#[cfg(feature = "receiver")]
+pub struct TachPulse {
+    phase: u32,
+    ticked_at: Option<Instant>,
+}
+
+#[cfg(feature = "receiver")]
+impl TachPulse {
+    pub fn new() -> Self {
+        Self {
+            phase: 0,
+            ticked_at: None,
+        }
+    }
+
+    /// The white level at `now` for `throttle` in -100..=100 %, or `None` without a signal.
+    ///
+    /// Reverse revs it the same as forward.
+    pub fn tick(&mut self, throttle: Option<i16>, now: Instant) -> u8 {
+        let Some(throttle) = throttle else {
+            self.phase = 0;
+            self.ticked_at = None;
+            return 0;
+        };
+        let revs = throttle.unsigned_abs().min(100) as u32;
+        let period_ms =
+            TACH_IDLE_PERIOD_MS - (TACH_IDLE_PERIOD_MS - TACH_FULL_PERIOD_MS) * revs / 100;
+        let peak = TACH_IDLE_LEVEL as u32 + (TACH_FULL_LEVEL - TACH_IDLE_LEVEL) as u32 * revs / 100;
+
+        let elapsed_ms = self
+            .ticked_at
+            .map_or(0, |at| (now - at).to_millis().min(period_ms as u64) as u32);
+        self.ticked_at = Some(now);
+        self.phase = (self.phase + elapsed_ms * TACH_CYCLE / period_ms) % TACH_CYCLE;
+
+        let (half, phase) = (TACH_CYCLE / 2, self.phase);
+        let rising = if phase < half {
+            phase
+        } else {
+            TACH_CYCLE - phase
+        };
+        (peak * rising / half) as u8
     }
 }
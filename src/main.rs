@@ -8,23 +8,92 @@
 
 use defmt::*;
 use defmt_rtt as _;
-use hal::{entry, gpio::FunctionPio0, prelude::_rphal_pio_PIOExt};
+#[cfg(not(feature = "rtic"))]
+use hal::entry;
 use panic_probe as _;
 use rp2040_hal as hal;
 
+#[cfg(feature = "lights")]
+mod ambient;
+#[cfg(feature = "receiver")]
+mod arming;
+#[cfg(feature = "lights")]
+mod battery;
+#[cfg(feature = "receiver")]
+mod calibration;
+#[cfg(all(feature = "cli", not(feature = "rtic")))]
+mod cli;
+mod config;
+#[cfg(all(feature = "lights", feature = "receiver"))]
+mod drive;
+mod hang;
+#[cfg(all(feature = "lights", feature = "receiver"))]
+mod headlights;
+#[cfg(feature = "lights")]
+mod led_core;
+#[cfg(feature = "lights")]
 mod lights;
+#[cfg(feature = "receiver")]
 mod receiver;
+#[cfg(feature = "rtic")]
+#[allow(unsafe_code)] // The RTIC app macro expands to the vector table and startup code
+mod rtic_app;
+#[cfg(all(feature = "lights", feature = "receiver"))]
+mod signals;
+mod status;
+#[cfg(not(feature = "rtic"))]
+mod system;
 
 // Provide an alias for our BSP so we can switch targets quickly.
 // Uncomment the BSP you included in Cargo.toml, the rest of the code does not need to change.
 // use sparkfun_pro_micro_rp2040 as bsp;
 
-use hal::{clocks::Clock, pac, watchdog::Watchdog};
+#[cfg(not(feature = "rtic"))]
+use hal::pac;
 
+#[cfg(feature = "lights")]
+use crate::ambient::{AmbientConfig, AmbientLight};
+#[cfg(feature = "lights")]
+use crate::battery::{BatteryConfig, BatteryMonitor};
+#[cfg(not(feature = "rtic"))]
+use crate::system::System;
+#[cfg(feature = "receiver")]
 use crate::{
-    lights::{initialize_lights, FrontLeds, Leds, RearLeds},
-    receiver::initialize_receiver,
+    arming::{Arming, ArmingGesture, SafetyState},
+    calibration::{BootCalibration, CalibrationGesture},
+    receiver::{
+        CaptureMode, ChannelFaults, CombinedFaultPolicy, Diagnostics, Endpoints, FrameRateMeter,
+        LinkStats, LinkThresholds, Receiver, ReceiverConfig, SwitchPos, ThrottleState, WireCommand,
+    },
 };
+#[cfg(all(feature = "cli", not(feature = "rtic")))]
+use crate::{
+    cli::{Cli, Command},
+    receiver::PULSE_HISTORY,
+};
+use crate::{config::Config, status::StatusLed};
+#[cfg(all(feature = "lights", feature = "receiver"))]
+use crate::{
+    drive::{is_idle, BrakeFlash, DriveLights, DriveTracker, ParkDimmer, ReverseFlash},
+    headlights::{Beam, Headlights},
+    lights::{
+        test_pattern_frame, AcquireFlash, AcquireFlashMode, BrakeExpand, Breathe, LightsTest,
+        MasterDimmer, RecoveryGate, TachPulse, WelcomeSequence,
+    },
+    receiver::{LinkHealth, SwitchDebouncer},
+    signals::{BlinkController, SteeringGuard},
+};
+#[cfg(feature = "lights")]
+use crate::{
+    led_core::LedOutput,
+    lights::{
+        scale_channel, Animator, ColorOrder, CornerMask, FrameLimiter, FrontLeds, IndicatorLed,
+        Leds, PatternPlayer, PixelFormat, RearLeds, SlewLimiter, StrobePattern, INDICATOR_LEVEL,
+        LED_FREQUENCY_HZ,
+    },
+};
+use fugit::MillisDurationU64;
+use hal::timer::Instant;
 
 #[allow(unsafe_code)]
 #[link_section = ".boot2"]
@@ -33,121 +102,1719 @@ pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_GENERIC_03H;
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
-#[entry]
-fn main() -> ! {
-    info!("Program start");
-    let mut pac = pac::Peripherals::take().unwrap();
-    let core = pac::CorePeripherals::take().unwrap();
-    let mut watchdog = Watchdog::new(pac.WATCHDOG);
-
-    // Configure the clocks
-    let clocks = hal::clocks::init_clocks_and_plls(
-        XTAL_FREQ_HZ,
-        pac.XOSC,
-        pac.CLOCKS,
-        pac.PLL_SYS,
-        pac.PLL_USB,
-        &mut pac.RESETS,
-        &mut watchdog,
-    )
-    .ok()
-    .unwrap();
-
-    defmt::info!("{}", clocks.system_clock.freq().to_Hz());
-
-    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-
-    // The single-cycle I/O block controls our GPIO pins
-    let sio = hal::Sio::new(pac.SIO);
-
-    let pins = hal::gpio::Pins::new(
-        pac.IO_BANK0,
-        pac.PADS_BANK0,
-        sio.gpio_bank0,
-        &mut pac.RESETS,
-    );
+/// How often the outputs are updated. Blinks and effects are timed from the
+/// timer rather than by counting ticks, so this only sets how smoothly they move.
+const UPDATE_PERIOD_MS: u32 = 10;
 
-    let receiver = initialize_receiver(
-        pac.TIMER,
-        &mut pac.RESETS,
-        &clocks,
-        pac.PWM,
-        pins.gpio3,
-        pins.gpio5,
-        pins.gpio4,
-    );
+/// Half a cycle of the failsafe alarm and status LED blink.
+const BLINK_HALF_PERIOD: MillisDurationU64 = MillisDurationU64::millis(500);
+
+/// Whether `now` falls in the lit half of the alarm and status LED blink.
+fn blink_on(now: Instant) -> bool {
+    (now.duration_since_epoch().to_millis() / BLINK_HALF_PERIOD.to_millis()) % 2 == 0
+}
 
-    let pin = pins
-        .gpio8
-        .into_push_pull_output_in_state(hal::gpio::PinState::Low)
-        .into_function::<FunctionPio0>()
-        .into_dyn_pin();
+/// Blinks the status LED fast forever, for a fault that stops the firmware
+/// from starting at all. The fault itself has already been logged.
+#[cfg(feature = "lights")]
+fn halt_with_fault(status: &mut status::StatusLed, system_hz: u32) -> ! {
+    loop {
+        status.set(true);
+        cortex_m::asm::delay(system_hz / 10);
+        status.set(false);
+        cortex_m::asm::delay(system_hz / 10);
+    }
+}
 
-    let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
+/// Roughly what the ring oscillator runs the system clock at out of reset.
+///
+/// It varies a lot from chip to chip and with temperature, so anything timed
+/// from it is only ever about right.
+const ROSC_FREQ_HZ: u32 = 6_500_000;
 
-    let mut tx = initialize_lights(&mut pio, sm0, &clocks, pin);
+/// The short flash of [`fatal_blink`]. The long one is three of these.
+const FATAL_DOT_MS: u32 = 150;
 
+/// Blinks SOS on `pin` forever, for a fault before the peripherals or the
+/// clocks are up. The fault itself has already been logged.
+///
+/// Three short, three long and three short, then a pause, so it can't be
+/// mistaken for the even blink of [`halt_with_fault`] or of failsafe. With no
+/// clocks to trust it counts cycles at `ROSC_FREQ_HZ`.
+fn fatal_blink(pin: status::StatusPin) -> ! {
+    let mut status = status::StatusLed::new(pin).active_low(STATUS_LED_ACTIVE_LOW);
+    let dot = ROSC_FREQ_HZ / 1000 * FATAL_DOT_MS;
     loop {
-        let leds = Leds {
-            front_right: FrontLeds {
-                yellow: 0,
-                low_beam: 0,
-                high_beam: 0,
-            },
-            front_left: FrontLeds {
-                yellow: 42,
-                low_beam: 0,
-                high_beam: 0,
-            },
-            rear_left: RearLeds {
-                yellow: 42,
-                white: 0,
-                red: 0,
-            },
-            rear_right: RearLeds {
-                yellow: 0,
-                white: 0,
-                red: 0,
-            },
+        for length in [1, 1, 1, 3, 3, 3, 1, 1, 1] {
+            status.set(true);
+            cortex_m::asm::delay(length * dot);
+            status.set(false);
+            cortex_m::asm::delay(dot);
+        }
+        cortex_m::asm::delay(6 * dot);
+    }
+}
+
+/// Sets up GP25 straight from the raw peripherals, for [`fatal_blink`] when
+/// the rest of the pins were never split out.
+fn fatal_status_pin(
+    io_bank0: hal::pac::IO_BANK0,
+    pads_bank0: hal::pac::PADS_BANK0,
+    sio: hal::pac::SIO,
+    resets: &mut hal::pac::RESETS,
+) -> status::StatusPin {
+    let sio = hal::Sio::new(sio);
+    hal::gpio::Pins::new(io_bank0, pads_bank0, sio.gpio_bank0, resets)
+        .gpio25
+        .into_push_pull_output()
+        .into_dyn_pin()
+}
+
+/// How often the telemetry is logged, independent of the update rate.
+#[cfg(feature = "receiver")]
+const TELEMETRY_INTERVAL: MillisDurationU64 = MillisDurationU64::millis(500);
+
+/// Largest change any LED channel may make per frame. `SlewLimiter::NO_LIMIT` disables smoothing.
+#[cfg(feature = "lights")]
+const LED_MAX_DELTA: u8 = SlewLimiter::NO_LIMIT;
+
+/// How long each change of frame fades over. Zero keeps the hard edges.
+#[cfg(feature = "lights")]
+const LED_TRANSITION: MillisDurationU64 = MillisDurationU64::millis(0);
+
+/// Whether to gamma correct every channel before it goes out, so levels look
+/// evenly spaced. Leave off if the levels set in the code are already corrected.
+#[cfg(feature = "lights")]
+const GAMMA_CORRECTION: bool = false;
+
+/// Channel order the LED strip expects. `ColorOrder::Rgb` matches the default wiring.
+#[cfg(feature = "lights")]
+const LED_COLOR_ORDER: ColorOrder = ColorOrder::Rgb;
+
+/// A strobe to play instead of the normal lights, for a demo. `None` for normal lights.
+/// With a receiver, failsafe still shows the alarm.
+#[cfg(feature = "lights")]
+const STROBE_PATTERN: Option<StrobePattern> = None;
+
+/// How long one pass through `STROBE_PATTERN` takes.
+#[cfg(feature = "lights")]
+const STROBE_PERIOD: MillisDurationU64 = MillisDurationU64::millis(800);
+
+/// How many channels each pixel on the strip has. `PixelFormat::Rgb` matches the default LEDs;
+/// `PixelFormat::Rgbw` is for strips with a separate white element.
+#[cfg(feature = "lights")]
+const LED_PIXEL_FORMAT: PixelFormat = PixelFormat::Rgb;
+
+/// How long, in µs, the line is held low after each frame so the strip latches
+/// it. `None` keeps the default, which suits WS2812s; newer parts that need
+/// 280 µs or more, and would otherwise show the last color bleeding into the
+/// next frame, want it set.
+#[cfg(feature = "lights")]
+const LED_RESET_US: Option<u32> = None;
+
+/// Frames written closer together than the strip takes to clock one out are dropped.
+#[cfg(feature = "lights")]
+const LED_FRAME_LIMIT: FrameLimiter = FrameLimiter::new(Leds::frame_transmit_us(
+    LED_FREQUENCY_HZ,
+    LED_PIXEL_FORMAT.bits_per_pixel(),
+    LED_RESET_US,
+));
+
+/// Whether to sweep through every LED channel at power-up to check the wiring.
+#[cfg(feature = "lights")]
+const RUN_STARTUP_SEQUENCE: bool = true;
+
+/// Which core feeds the LED strip.
+#[cfg(feature = "lights")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // Core0 is there for builds that need core1 for something else
+enum LedCore {
+    /// The control loop starts each frame's DMA itself.
+    Core0,
+    /// Core1 sends the frames the control loop publishes, so they go out on
+    /// time even while core0 is held up in the receiver interrupt or a
+    /// critical section.
+    Core1,
+}
+
+#[cfg(feature = "lights")]
+const LED_CORE: LedCore = LedCore::Core1;
+
+/// How the LEDs are wired to the board.
+#[cfg(all(feature = "lights", not(feature = "rtic")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // The wiring is fixed per car
+enum LedWiring {
+    /// One chain on GP8: the front corners, the rear corners, then the centre lamp and indicator.
+    Chain,
+    /// The front corners on GP8 and the rest on GP9, as `SplitLeds` lays them out. Both
+    /// strips are written from core0, so `LED_CORE` and `RUN_STARTUP_SEQUENCE` don't apply.
+    Split,
+}
+
+#[cfg(all(feature = "lights", not(feature = "rtic")))]
+const LED_WIRING: LedWiring = LedWiring::Chain;
+
+/// How much amber to blend into the white channels, from 0 (cool) to 255 (warm).
+#[cfg(feature = "lights")]
+const WHITE_WARMTH: u8 = 0;
+
+/// Whether to dim the lights at night, from a photoresistor on GP26. See `AmbientConfig` for the wiring.
+#[cfg(feature = "lights")]
+const AMBIENT_DIMMING: bool = false;
+
+/// Day and night levels for `AMBIENT_DIMMING`, and when to switch between them.
+#[cfg(feature = "lights")]
+const AMBIENT_CONFIG: AmbientConfig = AmbientConfig {
+    sample_period: MillisDurationU64::millis(100),
+    night_below: 1200,
+    day_above: 1600,
+    night_brightness: 96,
+    day_brightness: u8::MAX,
+    // About 6 s from night to day
+    ramp_step: 4,
+};
+
+/// Whether to watch the battery through a divider on GP27, and triple-blink
+/// the yellows when it runs low. See `BatteryConfig` for the wiring.
+#[cfg(feature = "lights")]
+const BATTERY_MONITOR: bool = false;
+
+/// The divider and thresholds for `BATTERY_MONITOR`, here for a 2S LiPo.
+#[cfg(feature = "lights")]
+const BATTERY_CONFIG: BatteryConfig = BatteryConfig {
+    sample_period: MillisDurationU64::millis(250),
+    // 20k over 10k, so a full 8.4 V pack reads 2.8 V at the pin
+    divider_ratio_milli: 3000,
+    low_below_mv: 6800,
+    recover_above_mv: 7200,
+};
+
+/// How often the low battery warning triple-blinks the yellows.
+#[cfg(feature = "lights")]
+const BATTERY_WARNING_PERIOD: MillisDurationU64 = MillisDurationU64::millis(2000);
+
+/// How long each blink of the battery warning, and the gap between them, lasts.
+#[cfg(feature = "lights")]
+const BATTERY_WARNING_BLINK: MillisDurationU64 = MillisDurationU64::millis(120);
+
+/// `leds` with all four yellows lit if the low battery warning's triple
+/// blink is in one of its blinks at `now`.
+#[cfg(feature = "lights")]
+fn battery_warning(mut leds: Leds, now: Instant) -> Leds {
+    let phase = now.duration_since_epoch().to_millis() % BATTERY_WARNING_PERIOD.to_millis();
+    let blink = phase / BATTERY_WARNING_BLINK.to_millis();
+    if matches!(blink, 0 | 2 | 4) {
+        leds.front_left.yellow = u8::MAX;
+        leds.front_right.yellow = u8::MAX;
+        leds.rear_left.yellow = u8::MAX;
+        leds.rear_right.yellow = u8::MAX;
+    }
+    leds
+}
+
+/// Master brightness for builds without a receiver, from 0 (off) to 255 (full).
+#[cfg(all(feature = "lights", not(feature = "receiver")))]
+const MASTER_BRIGHTNESS: u8 = u8::MAX;
+
+/// Lowest master brightness the aux dimmer can reach, so the lights can't be turned fully off.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const MIN_BRIGHTNESS: u8 = 16;
+
+/// Red LEDs in each rear corner. With 1 the brake light is plain on/off.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const REAR_LEDS_PER_CORNER: usize = 1;
+
+/// How long the brake lights stay on once the throttle settles in neutral, so
+/// a rolling stop doesn't flick them straight off. Kept short, or a parked
+/// car looks like it is stuck braking.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BRAKE_HOLD: MillisDurationU64 = MillisDurationU64::millis(1000);
+
+/// How long the brake light takes to grow from the centre to full width.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BRAKE_EXPAND_DURATION: MillisDurationU64 = MillisDurationU64::millis(300);
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+type BrakeLights = BrakeExpand<REAR_LEDS_PER_CORNER>;
+
+/// Brake light level for a gentle stop. Backing off the throttle faster brightens it, up to full.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const MIN_BRAKE_LEVEL: u8 = 96;
+
+/// How many times the brake lights flash as a hard stop begins, before
+/// going steady. 0 turns the flash off.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BRAKE_FLASH_COUNT: u8 = 0;
+
+/// Half a cycle of the brake flash, so 60 ms flashes about 8 times a second.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BRAKE_FLASH_HALF_PERIOD: MillisDurationU64 = MillisDurationU64::millis(60);
+
+/// The brake intensity that counts as a hard stop and starts the flash.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const HARD_BRAKE_INTENSITY: u8 = 192;
+
+/// Whether a centre high-mount stop lamp is chained after the rear left
+/// corner. It shows the brake light alone, and is dark in every other mode.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const CENTER_BRAKE_LAMP: bool = false;
+
+/// The corners fitted on this build. The others are always sent dark, for a
+/// front-only kit, or to bench-test one corner on its own.
+#[cfg(feature = "lights")]
+const LED_CORNERS: CornerMask = CornerMask::ALL;
+
+/// How the rear whites show sustained reverse.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // Steady or Flash is a matter of taste, set once in REVERSE_LIGHTS
+enum ReverseLights {
+    /// Lit for as long as the car reverses.
+    Steady,
+    /// Flashing at 2 Hz, like a truck's reversing warning.
+    Flash,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const REVERSE_LIGHTS: ReverseLights = ReverseLights::Steady;
+
+/// Whether the rear whites pulse with the throttle, like a tach, while not reversing.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const TACH_PULSE: bool = false;
+
+/// How long the turn indicators stay on, and then off, in each blink.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BLINK_PERIOD: MillisDurationU64 = MillisDurationU64::millis(400);
+
+/// How many whole blinks a turn signal shows before it can cancel itself.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const TURN_SIGNAL_MIN_BLINKS: u8 = 3;
+
+/// How long the steering has to stay off a turn before the signal counts it
+/// as finished, so a quick counter-steer mid-turn doesn't cancel it.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const TURN_SIGNAL_CANCEL_AFTER: MillisDurationU64 = MillisDurationU64::millis(300);
+
+/// How long the aux switch has to sit in a new position before the lights follow it.
+/// Long enough to skip the middle position as the switch passes it, without a noticeable lag.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const AUX_SWITCH_DEBOUNCE: MillisDurationU64 = MillisDurationU64::millis(60);
+
+/// The fastest the steering can plausibly move, in percent per second, or
+/// `None` to never treat it as erratic. Lock to lock in one 10 ms update is
+/// over this, and nothing a hand on the wheel does comes close.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const MAX_STEERING_RATE: Option<u32> = Some(10_000);
+
+/// How long the steering has to go without a change over `MAX_STEERING_RATE`
+/// before it's trusted again.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const STEERING_SETTLE: MillisDurationU64 = MillisDurationU64::millis(1000);
+
+/// What turns the hazard lights on.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // AuxHigh needs a spare 3-position switch, so it's off unless wired
+enum HazardTrigger {
+    /// No hazard switch.
+    None,
+    /// The aux switch in its high position. Aux also drives the master
+    /// dimmer, so this only suits a 3-position switch used for nothing else.
+    AuxHigh,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const HAZARD_TRIGGER: HazardTrigger = HazardTrigger::None;
+
+/// What selects the headlight beam, besides the console's `beam` command.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // Aux is the dimmer unless a car frees it up for a beam switch
+enum HeadlightTrigger {
+    /// No switch: the headlights stay off unless the console changes them.
+    None,
+    /// The aux switch: low is off, mid is low beam, high is high beam. Aux
+    /// also drives the master dimmer, so this only suits a 3-position switch
+    /// used for nothing else, and not alongside `HazardTrigger::AuxHigh`.
+    AuxSwitch,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const HEADLIGHT_TRIGGER: HeadlightTrigger = HeadlightTrigger::None;
+
+/// What starts and stops the lights test, besides the console's `test` command.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // Holding aux high only starts the test where nothing else uses aux
+enum LightsTestTrigger {
+    None,
+    /// Holding the aux switch high for `LIGHTS_TEST_HOLD`. Aux also drives the
+    /// master dimmer, so this only suits a switch used for little else.
+    AuxHeldHigh,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LIGHTS_TEST_TRIGGER: LightsTestTrigger = LightsTestTrigger::None;
+
+/// How long the aux switch has to be held high to start or stop the lights test.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LIGHTS_TEST_HOLD: MillisDurationU64 = MillisDurationU64::millis(3000);
+
+/// Fires once each time the aux switch has been held high for `LIGHTS_TEST_HOLD`.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+struct AuxHold {
+    /// When the switch went high, and whether this hold has fired yet.
+    held: Option<(Instant, bool)>,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+impl AuxHold {
+    fn update(&mut self, high: bool, now: Instant) -> bool {
+        let (fired, held) = match (high, self.held) {
+            (false, _) => (false, None),
+            (true, None) => (false, Some((now, false))),
+            (true, Some((since, false))) if now - since >= LIGHTS_TEST_HOLD => {
+                (true, Some((since, true)))
+            }
+            (true, held) => (false, held),
+        };
+        self.held = held;
+        fired
+    }
+}
+
+/// How long the throttle has to sit in neutral, with the steering centred, before the
+/// lights dim to `PARKED_BRIGHTNESS`. `None` never dims them.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const IDLE_DIM_AFTER: Option<MillisDurationU64> = None;
+
+/// How bright the lights are while parked, scaling the master brightness like the ambient dimming.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const PARKED_BRIGHTNESS: u8 = 48;
+
+/// How long the lights take to fade down to `PARKED_BRIGHTNESS`, and back up again.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const IDLE_DIM_FADE: MillisDurationU64 = MillisDurationU64::millis(1000);
+
+/// How long the car has to sit armed and idle before the low beams start to
+/// breathe, fading slowly down and back up. `None` never breathes.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BREATHE_AFTER: Option<MillisDurationU64> = None;
+
+/// How long each breath of the low beams takes.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BREATHE_PERIOD: MillisDurationU64 = MillisDurationU64::millis(4000);
+
+/// How far each breath scales the low beams down to, and back up to. They stop on `BREATHE_MAX`.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BREATHE_MIN: u8 = 64;
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const BREATHE_MAX: u8 = 255;
+
+/// The controls `ParkDimmer` watches, or `None` while the signal is lost.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn park_controls(receiver: &Receiver) -> Option<(ThrottleState, i16)> {
+    (!receiver.has_watchdog_expired())
+        .then(|| (receiver.throttle_state(), receiver.steering_percent()))
+}
+
+/// Whether the low beam stays on with the high beam, as on a real car.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LOW_BEAM_WITH_HIGH: bool = true;
+
+/// How long the headlights take to come up to, or fade from, full. Zero switches them instantly.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const HEADLIGHT_RAMP: MillisDurationU64 = MillisDurationU64::millis(150);
+
+/// The Pico's on-board LED on GP25 is active-high. Set this for boards that wire it active-low.
+const STATUS_LED_ACTIVE_LOW: bool = false;
+
+/// Servo endpoints for the percentage readings, how quickly a lost signal is noticed,
+/// and how much the smoothed readings are filtered.
+#[cfg(feature = "receiver")]
+const RECEIVER_CONFIG: ReceiverConfig = ReceiverConfig {
+    neutral_us: 1500,
+    min_us: 1000,
+    max_us: 2000,
+    neutral_band_us: 50,
+    watchdog_timeout: MillisDurationU64::millis(100),
+    smoothing_shift: 2,
+    plausible_min_us: 800,
+    plausible_max_us: 2200,
+    // Below this the turn signals stay off, so it is also their threshold
+    steering_dead_band_percent: 30,
+    invert_steering: false,
+    invert_throttle: false,
+    // Lets a pulse generator on GP4 set the brightness and blink, see `receiver::wire_config`
+    wire_config: false,
+};
+
+/// Which link the receiver talks over.
+#[cfg(all(feature = "receiver", not(feature = "rtic")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // A car has one receiver, so RECEIVER_INPUT only ever names one link
+enum ReceiverInput {
+    /// Separate servo outputs on GP3 (steering), GP5 (throttle), GP7 (aux) and GP4 (update).
+    Pwm,
+    /// A PPM (CPPM) sum signal on GP3, with steering, throttle and aux on channels 1, 2 and 3.
+    /// `CAPTURE_MODE` doesn't apply.
+    Ppm,
+    /// An SBUS serial line on GP1, with steering, throttle and aux on channels 1, 2 and 3.
+    /// `CAPTURE_MODE` doesn't apply.
+    Sbus,
+    /// A CRSF (ExpressLRS, Crossfire) receiver's TX on GP5, with the channels as for SBUS.
+    /// `CAPTURE_MODE` doesn't apply.
+    Crsf,
+}
+
+#[cfg(all(feature = "receiver", not(feature = "rtic")))]
+const RECEIVER_INPUT: ReceiverInput = ReceiverInput::Pwm;
+
+/// Which pins `ReceiverInput::Pwm` captures from. See `ReceiverPins` for what else is possible.
+#[cfg(all(feature = "receiver", not(feature = "rtic")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // The pins follow how the board is wired, which doesn't change at run time
+enum PwmPins {
+    /// Steering on GP3, throttle on GP5, update on GP4 and aux on GP7.
+    Default,
+    /// Steering on GP19, throttle on GP21, update on GP20 and aux on GP7, for
+    /// boards that need GP3 to GP5 for something else.
+    Alternate,
+}
+
+#[cfg(all(feature = "receiver", not(feature = "rtic")))]
+const PWM_PINS: PwmPins = PwmPins::Default;
+
+/// How the receiver encodes channel positions. See `CaptureMode` for how to pick.
+#[cfg(feature = "receiver")]
+const CAPTURE_MODE: CaptureMode = CaptureMode::PulseWidth;
+
+/// How to react when steering and throttle both fault while frames keep arriving.
+#[cfg(feature = "receiver")]
+const COMBINED_FAULT_POLICY: CombinedFaultPolicy = CombinedFaultPolicy::Failsafe;
+
+/// Where a falling link quality warns, and where it fails safe. Only receivers
+/// that report link statistics, such as CRSF ones, ever trip these.
+#[cfg(feature = "receiver")]
+const LINK_THRESHOLDS: LinkThresholds = LinkThresholds {
+    warn_below: 50,
+    failsafe_below: 0,
+    hysteresis: 10,
+};
+
+/// Throttle gesture required before the car responds. `ArmingGesture::None` arms on signal.
+#[cfg(feature = "receiver")]
+const ARMING_GESTURE: ArmingGesture = ArmingGesture::None;
+
+/// Gesture at power-up that records the steering and throttle endpoints.
+/// `CalibrationGesture::None` always uses the ones in `RECEIVER_CONFIG`.
+#[cfg(feature = "receiver")]
+const CALIBRATION_GESTURE: CalibrationGesture = CalibrationGesture::None;
+
+/// Frames in a row, without a glitch, the signal has to deliver after a
+/// dropout before the lights leave failsafe. 0 leaves as soon as the receiver does.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const RECOVERY_FRAMES: u32 = 5;
+
+/// How long the lights take to fade back in once the signal has proven stable.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const RECOVERY_FADE: MillisDurationU64 = MillisDurationU64::millis(250);
+
+/// A dropout this soon after recovering counts as a flapping signal, and
+/// makes the next recovery wait longer.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const RECOVERY_FLAP_WINDOW: MillisDurationU64 = MillisDurationU64::millis(2000);
+
+/// Whether to flash the lights once when the transmitter's signal is acquired.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const ACQUIRE_FLASH_MODE: AcquireFlashMode = AcquireFlashMode::Off;
+
+/// How long the signal-acquired flash lasts. It shows for at least one update.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const ACQUIRE_FLASH_DURATION: MillisDurationU64 = MillisDurationU64::millis(300);
+
+/// Whether arming plays the welcome, headlights fading up behind a courtesy
+/// flash, and disarming or losing the signal plays the farewell.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const WELCOME_SEQUENCE: bool = false;
+
+/// How long the welcome and the farewell each take.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const WELCOME_DURATION: MillisDurationU64 = MillisDurationU64::millis(1500);
+
+/// How long arming or disarming has to hold before it plays the welcome or
+/// farewell, so a flaky signal at power-up doesn't play them over and over.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const WELCOME_DEBOUNCE: MillisDurationU64 = MillisDurationU64::millis(500);
+
+/// What the signal-acquired flash shows: every white channel at full.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const ACQUIRE_FLASH_FRAME: Leds = Leds {
+    front_right: FrontLeds {
+        yellow: 0,
+        low_beam: 255,
+        high_beam: 255,
+    },
+    front_left: FrontLeds {
+        yellow: 0,
+        low_beam: 255,
+        high_beam: 255,
+    },
+    rear_right: RearLeds {
+        yellow: 0,
+        white: 255,
+        red: 0,
+    },
+    rear_left: RearLeds {
+        yellow: 0,
+        white: 255,
+        red: 0,
+    },
+    ..Leds::OFF
+};
+
+/// Where the external armed/failsafe indicator is wired, if anywhere.
+#[cfg(feature = "receiver")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // Depends on what is wired to GP15 or after the strip, fixed per car
+enum ExternalIndicator {
+    None,
+    /// A plain LED on GP15: solid when armed, dark when disarmed, blinking in failsafe.
+    Gpio,
+    /// An extra pixel chained after the rear left corner: green when armed,
+    /// amber when disarmed, flashing red in failsafe.
+    #[cfg(feature = "lights")]
+    Pixel,
+}
+
+#[cfg(feature = "receiver")]
+const EXTERNAL_INDICATOR: ExternalIndicator = ExternalIndicator::None;
+
+/// What the lights show if no frame has arrived since boot.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // TestPattern is only for the bench, by editing NO_SIGNAL_AT_BOOT
+enum NoSignalAtBoot {
+    /// The same `FailsafePattern` as any other failsafe.
+    Alarm,
+    /// A slow, dim sweep through every channel, for checking the LEDs without a transmitter.
+    /// Normal operation takes over as soon as the first frame arrives.
+    TestPattern,
+}
+
+/// Set to `NoSignalAtBoot::TestPattern` to sweep the LEDs on the bench until a signal appears.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const NO_SIGNAL_AT_BOOT: NoSignalAtBoot = NoSignalAtBoot::Alarm;
+
+/// What the lights show in failsafe, instead of holding whatever they last showed.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+enum FailsafePattern {
+    /// All four yellows blinking together, in step with the status LED.
+    Blink,
+    /// Everything dark.
+    Off,
+}
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+impl FailsafePattern {
+    /// The pattern at `index` in declaration order, the way `Config` stores it.
+    fn from_index(index: u8) -> Option<Self> {
+        [FailsafePattern::Blink, FailsafePattern::Off]
+            .get(index as usize)
+            .copied()
+    }
+}
+
+/// Failsafe pattern unless the config picks another.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const FAILSAFE_PATTERN: FailsafePattern = FailsafePattern::Blink;
+
+/// The frame shown while the receiver is in failsafe.
+///
+/// Depends on nothing but `pattern` and `now`, so it is the same however the
+/// signal was lost, and normal lights come back on the first update after
+/// fresh frames arrive.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn failsafe_leds(pattern: FailsafePattern, now: Instant) -> Leds {
+    let on = pattern == FailsafePattern::Blink && blink_on(now);
+    let corner = FrontLeds {
+        yellow: if on { INDICATOR_LEVEL } else { 0 },
+        low_beam: 0,
+        high_beam: 0,
+    };
+    let rear = RearLeds {
+        yellow: corner.yellow,
+        white: 0,
+        red: 0,
+    };
+
+    Leds {
+        front_right: corner,
+        front_left: corner,
+        rear_left: rear,
+        rear_right: rear,
+        indicator: IndicatorLed::default(),
+        center: None,
+        enabled: CornerMask::ALL,
+    }
+}
+
+/// Half a cycle of the "not ready" blink, slower than the failsafe alarm so the two can be told apart.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const NOT_READY_HALF_PERIOD: MillisDurationU64 = MillisDurationU64::millis(1500);
+
+/// What the lights show until the throttle has been seen at neutral: the
+/// front yellows alone, blinking slowly. Nothing at the rear lights, so the
+/// car never looks like it is braking, reversing or about to move.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn not_ready_leds(now: Instant) -> Leds {
+    let on = (now.duration_since_epoch().to_millis() / NOT_READY_HALF_PERIOD.to_millis()) % 2 == 0;
+    let corner = FrontLeds {
+        yellow: if on { INDICATOR_LEVEL } else { 0 },
+        low_beam: 0,
+        high_beam: 0,
+    };
+
+    Leds {
+        front_right: corner,
+        front_left: corner,
+        ..Leds::default()
+    }
+}
+
+/// How often the low link quality warning double-blinks the rear reds.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LINK_WARNING_PERIOD: MillisDurationU64 = MillisDurationU64::millis(3000);
+
+/// How long each blink of the link warning, and the gap between them, lasts.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LINK_WARNING_BLINK: MillisDurationU64 = MillisDurationU64::millis(80);
+
+/// How bright the rear reds go in the link warning: clearly visible, but short of full brake.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const LINK_WARNING_LEVEL: u8 = 128;
+
+/// Whether the link warning's double blink is lit at `now`: on, off, on, then
+/// dark for the rest of each `LINK_WARNING_PERIOD`.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn link_warning_lit(now: Instant) -> bool {
+    let phase = now.duration_since_epoch().to_millis() % LINK_WARNING_PERIOD.to_millis();
+    let blink = phase / LINK_WARNING_BLINK.to_millis();
+    blink == 0 || blink == 2
+}
+
+/// Half a cycle of the erratic steering warning, which swaps the yellows from side to side.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const ERRATIC_STEERING_HALF_PERIOD: MillisDurationU64 = MillisDurationU64::millis(120);
+
+/// What the lights show while the steering is erratic: the yellows of each
+/// side in turn, quickly, a wig-wag no other mode uses.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn erratic_steering_leds(now: Instant) -> Leds {
+    let half = now.duration_since_epoch().to_millis() / ERRATIC_STEERING_HALF_PERIOD.to_millis();
+    let left = half % 2 == 0;
+    indicator_frame(left, !left)
+}
+
+/// The light mode state machine, and the effects each mode plays over time.
+///
+/// This is the one place that decides what the lights show: each update
+/// feeds it the latest inputs, it picks the mode with [`select_mode`], and it
+/// produces that mode's frame. [`LightController::current_mode`] reports the
+/// outcome for telemetry and the console.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+struct LightController {
+    drive: DriveTracker,
+    brake: BrakeLights,
+    brake_flash: BrakeFlash,
+    flash: AcquireFlash,
+    welcome: WelcomeSequence,
+    blinker: BlinkController,
+    steering_guard: SteeringGuard,
+    strobe: PatternPlayer,
+    headlights: Headlights,
+    tach: TachPulse,
+    reverse_flash: ReverseFlash,
+    lights_test: LightsTest,
+    aux_hold: AuxHold,
+    aux_switch: SwitchDebouncer,
+    breathe: Breathe,
+    /// When the car was last seen armed and idle, or `None` since it was touched.
+    idle_since: Option<Instant>,
+    /// The hardest braking seen since the brake lights came on.
+    brake_peak: u8,
+    /// What the last frame showed.
+    mode: LightMode,
+    failsafe_pattern: FailsafePattern,
+    recovery: RecoveryGate,
+}
+
+/// One reading of everything the debug output shows.
+#[cfg(feature = "receiver")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+struct Telemetry {
+    steering: u16,
+    throttle: u16,
+    steering_smoothed: u16,
+    throttle_smoothed: u16,
+    steering_percent: i16,
+    throttle_percent: i16,
+    /// The `(left, right)` mix from [`Receiver::differential`].
+    differential: (i16, i16),
+    throttle_state: ThrottleState,
+    brake_intensity: u8,
+    aux: Option<u16>,
+    aux_switch: Option<SwitchPos>,
+    watchdog_expired: bool,
+    seen_signal: bool,
+    /// The throttle has read neutral since boot, see [`Receiver::is_armed`].
+    receiver_armed: bool,
+    armed: bool,
+    channel_faults: ChannelFaults,
+    diagnostics: Diagnostics,
+    update_rate_hz: Option<u16>,
+    link_stats: Option<LinkStats>,
+    /// The `(min, max)` pulses since boot, see [`Receiver::steering_extremes`].
+    steering_extremes: (u16, u16),
+    throttle_extremes: (u16, u16),
+}
+
+#[cfg(feature = "receiver")]
+impl Telemetry {
+    fn new(receiver: &Receiver, armed: bool) -> Self {
+        // The channels and watchdog from one instant, so they agree with each other
+        let snapshot = receiver.snapshot();
+        Self {
+            steering: snapshot.steering.raw.unwrap_or(0),
+            throttle: snapshot.throttle.raw.unwrap_or(0),
+            steering_smoothed: receiver.steering_smoothed(),
+            throttle_smoothed: receiver.throttle_smoothed(),
+            steering_percent: snapshot.steering.percent.unwrap_or(0),
+            throttle_percent: snapshot.throttle.percent.unwrap_or(0),
+            differential: receiver.differential(),
+            throttle_state: receiver.throttle_state(),
+            brake_intensity: receiver.brake_intensity(),
+            aux: snapshot.aux,
+            aux_switch: receiver.aux_switch_position(),
+            watchdog_expired: snapshot.watchdog_expired,
+            seen_signal: receiver.has_seen_signal(),
+            receiver_armed: receiver.is_armed(),
+            armed,
+            channel_faults: receiver.channel_faults(),
+            diagnostics: receiver.diagnostics(),
+            update_rate_hz: receiver.update_rate_hz(),
+            link_stats: receiver.link_stats(),
+            steering_extremes: receiver.steering_extremes(),
+            throttle_extremes: receiver.throttle_extremes(),
+        }
+    }
+}
+
+/// Logs the telemetry every `TELEMETRY_INTERVAL`, however often it is called.
+///
+/// Only with the `telemetry` feature. Without it `log` returns before doing
+/// anything, which the compiler folds away along with the formatting.
+#[cfg(feature = "receiver")]
+struct TelemetryLog {
+    frame_rate: FrameRateMeter,
+    logged_at: Option<Instant>,
+}
+
+#[cfg(feature = "receiver")]
+impl TelemetryLog {
+    fn new() -> Self {
+        Self {
+            frame_rate: FrameRateMeter::new(),
+            logged_at: None,
+        }
+    }
+
+    /// Logs the headline readings as named fields, and everything else at debug level.
+    ///
+    /// `light_mode` is `None` in builds without the lights.
+    fn log(
+        &mut self,
+        receiver: &Receiver,
+        armed: bool,
+        light_mode: Option<LightMode>,
+        now: Instant,
+    ) {
+        if !cfg!(feature = "telemetry") {
+            return;
+        }
+        // Checked before anything is read, so the calls in between cost next to nothing
+        if self
+            .logged_at
+            .is_some_and(|at| now - at < TELEMETRY_INTERVAL)
+        {
+            return;
+        }
+        self.logged_at = Some(now);
+
+        let telemetry = Telemetry::new(receiver, armed);
+        #[cfg(feature = "lights")]
+        let led_max_fps = Some(LED_FRAME_LIMIT.max_fps());
+        #[cfg(not(feature = "lights"))]
+        let led_max_fps: Option<u32> = None;
+        info!(
+            "telemetry steering={=u16} throttle={=u16} update_rate_hz={} watchdog_expired={=bool} light_mode={} led_max_fps={} frame_rate_mhz={} glitches={=u32}",
+            telemetry.steering,
+            telemetry.throttle,
+            telemetry.update_rate_hz,
+            telemetry.watchdog_expired,
+            light_mode,
+            led_max_fps,
+            self.frame_rate.sample(telemetry.diagnostics, now),
+            receiver.glitch_count()
+        );
+        debug!("{}", telemetry);
+    }
+}
+
+/// What the lights are showing.
+#[cfg(feature = "receiver")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(not(feature = "lights"), allow(dead_code))] // Nothing shows it without the lights
+enum LightMode {
+    /// Playing the welcome or farewell, see `WelcomeSequence`.
+    Welcome,
+    /// Sweeping the channels until the first frame, see `NoSignalAtBoot`.
+    TestPattern,
+    /// The failsafe alarm.
+    Failsafe,
+    /// The slow "not ready" blink, until the throttle has been seen at neutral, see [`Receiver::is_armed`].
+    NotReady,
+    /// The side-to-side warning, while the steering moves too fast to be real, see `SteeringGuard`.
+    ErraticSteering,
+    /// Walking through the channels on request, see `LightsTest`.
+    LightsTest,
+    /// Flashing because the signal was just acquired.
+    AcquireFlash,
+    /// Playing `STROBE_PATTERN`.
+    Strobe,
+    /// The hazards.
+    Hazard,
+    /// Following the steering and throttle.
+    Drive,
+}
+
+/// Everything [`select_mode`] chooses between, sampled once per update.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct ModeInputs {
+    welcome: bool,
+    failsafe: bool,
+    /// No frame has arrived since boot and `NO_SIGNAL_AT_BOOT` asks for the test pattern.
+    boot_test_pattern: bool,
+    /// The throttle hasn't been seen at neutral since boot.
+    not_ready: bool,
+    erratic_steering: bool,
+    lights_test: bool,
+    acquire_flash: bool,
+    strobe: bool,
+    hazard: bool,
+}
+
+/// Picks the mode to show, highest priority first.
+///
+/// The welcome and farewell beat everything, as they only play for a moment
+/// after arming or disarming, and the farewell is for the signal going away.
+/// Then failsafe beats everything else. Before the first frame it shows as the test
+/// pattern if `NO_SIGNAL_AT_BOOT` asks for that, since that is just how
+/// failsafe looks at boot. Next is the "not ready" blink, so nothing else
+/// shows until the throttle has been centred once, and then the erratic
+/// steering warning, which no effect can hide. Then come the lights test,
+/// the acquire flash and the strobe, each of which takes the car's lights
+/// over completely, then the hazards, and finally normal driving.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const fn select_mode(inputs: ModeInputs) -> LightMode {
+    if inputs.welcome {
+        LightMode::Welcome
+    } else if inputs.failsafe {
+        if inputs.boot_test_pattern {
+            LightMode::TestPattern
+        } else {
+            LightMode::Failsafe
+        }
+    } else if inputs.not_ready {
+        LightMode::NotReady
+    } else if inputs.erratic_steering {
+        LightMode::ErraticSteering
+    } else if inputs.lights_test {
+        LightMode::LightsTest
+    } else if inputs.acquire_flash {
+        LightMode::AcquireFlash
+    } else if inputs.strobe {
+        LightMode::Strobe
+    } else if inputs.hazard {
+        LightMode::Hazard
+    } else {
+        LightMode::Drive
+    }
+}
+
+// With every input set, each mode in turn wins once the ones above it are cleared
+#[cfg(all(feature = "lights", feature = "receiver"))]
+const _: () = {
+    const ALL: ModeInputs = ModeInputs {
+        welcome: true,
+        failsafe: true,
+        boot_test_pattern: false,
+        not_ready: true,
+        erratic_steering: true,
+        lights_test: true,
+        acquire_flash: true,
+        strobe: true,
+        hazard: true,
+    };
+    const NONE: ModeInputs = ModeInputs {
+        welcome: false,
+        failsafe: false,
+        boot_test_pattern: false,
+        not_ready: false,
+        erratic_steering: false,
+        lights_test: false,
+        acquire_flash: false,
+        strobe: false,
+        hazard: false,
+    };
+    core::assert!(matches!(select_mode(ALL), LightMode::Welcome));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            ..ALL
+        }),
+        LightMode::Failsafe
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            boot_test_pattern: true,
+            ..ALL
+        }),
+        LightMode::TestPattern
+    ));
+    // The test pattern is only how failsafe looks, so never shows without it
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            boot_test_pattern: true,
+            ..NONE
+        }),
+        LightMode::Drive
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            failsafe: false,
+            ..ALL
+        }),
+        LightMode::NotReady
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            failsafe: false,
+            not_ready: false,
+            ..ALL
+        }),
+        LightMode::ErraticSteering
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            failsafe: false,
+            not_ready: false,
+            erratic_steering: false,
+            ..ALL
+        }),
+        LightMode::LightsTest
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            welcome: false,
+            failsafe: false,
+            not_ready: false,
+            erratic_steering: false,
+            lights_test: false,
+            ..ALL
+        }),
+        LightMode::AcquireFlash
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            strobe: true,
+            hazard: true,
+            ..NONE
+        }),
+        LightMode::Strobe
+    ));
+    core::assert!(matches!(
+        select_mode(ModeInputs {
+            hazard: true,
+            ..NONE
+        }),
+        LightMode::Hazard
+    ));
+    core::assert!(matches!(select_mode(NONE), LightMode::Drive));
+};
+
+#[cfg(all(feature = "lights", feature = "receiver"))]
+impl LightController {
+    fn new() -> Self {
+        Self {
+            drive: DriveTracker::new(BRAKE_HOLD),
+            brake: BrakeLights::new(BRAKE_EXPAND_DURATION),
+            brake_flash: BrakeFlash::new(
+                BRAKE_FLASH_COUNT,
+                BRAKE_FLASH_HALF_PERIOD,
+                HARD_BRAKE_INTENSITY,
+            ),
+            flash: AcquireFlash::new(ACQUIRE_FLASH_MODE, ACQUIRE_FLASH_DURATION),
+            welcome: WelcomeSequence::new(WELCOME_SEQUENCE, WELCOME_DURATION, WELCOME_DEBOUNCE),
+            blinker: BlinkController::new(
+                BLINK_PERIOD,
+                TURN_SIGNAL_MIN_BLINKS,
+                TURN_SIGNAL_CANCEL_AFTER,
+            ),
+            steering_guard: SteeringGuard::new(MAX_STEERING_RATE, STEERING_SETTLE),
+            strobe: PatternPlayer::new(STROBE_PERIOD),
+            headlights: Headlights::new(LOW_BEAM_WITH_HIGH, HEADLIGHT_RAMP),
+            tach: TachPulse::new(),
+            reverse_flash: ReverseFlash::new(),
+            lights_test: LightsTest::new(),
+            aux_hold: AuxHold { held: None },
+            aux_switch: SwitchDebouncer::new(AUX_SWITCH_DEBOUNCE),
+            breathe: Breathe::new(BREATHE_PERIOD, BREATHE_MIN, BREATHE_MAX),
+            idle_since: None,
+            brake_peak: 0,
+            mode: LightMode::Drive,
+            failsafe_pattern: FAILSAFE_PATTERN,
+            recovery: RecoveryGate::new(RECOVERY_FRAMES, RECOVERY_FADE, RECOVERY_FLAP_WINDOW),
+        }
+    }
+
+    /// What the last frame showed.
+    fn current_mode(&self) -> LightMode {
+        self.mode
+    }
+
+    /// Picks the mode from the receiver's state at `now`, and returns the frame to show
+    /// for this half of the blink cycle.
+    fn update(&mut self, receiver: &Receiver, state: SafetyState, on: bool, now: Instant) -> Leds {
+        // Held on past the receiver's own failsafe until the returning signal is stable
+        let diagnostics = receiver.diagnostics();
+        let failsafe = self.recovery.update(
+            state == SafetyState::Failsafe,
+            diagnostics.frames,
+            diagnostics.glitches,
+            now,
+        );
+        let aux_switch = self.aux_switch.update(receiver.aux_switch_position(), now);
+        let aux_high = aux_switch == Some(SwitchPos::High);
+        if LIGHTS_TEST_TRIGGER == LightsTestTrigger::AuxHeldHigh
+            && self.aux_hold.update(aux_high, now)
+        {
+            self.lights_test.toggle(now);
+        }
+
+        let welcome = self
+            .welcome
+            .update(!failsafe && state == SafetyState::Armed, now);
+        let mode = select_mode(ModeInputs {
+            welcome: welcome.is_some(),
+            failsafe,
+            boot_test_pattern: NO_SIGNAL_AT_BOOT == NoSignalAtBoot::TestPattern
+                && !receiver.has_seen_signal(),
+            not_ready: !receiver.is_armed(),
+            erratic_steering: self.steering_guard.update(receiver.steering_percent(), now),
+            lights_test: self.lights_test.is_running(),
+            // Sampled every update, as it watches for the edge out of failsafe
+            acquire_flash: self.flash.update(!failsafe, now),
+            strobe: STROBE_PATTERN.is_some(),
+            hazard: HAZARD_TRIGGER == HazardTrigger::AuxHigh && aux_high,
+        });
+        if mode != self.mode {
+            info!("Light mode {}", mode);
+            self.mode = mode;
+        }
+
+        let idle = state == SafetyState::Armed
+            && park_controls(receiver)
+                .is_some_and(|(throttle, steering)| is_idle(throttle, steering));
+        self.idle_since = if idle {
+            Some(self.idle_since.unwrap_or(now))
+        } else {
+            None
+        };
+        if BREATHE_AFTER
+            .is_some_and(|after| self.idle_since.is_some_and(|since| now - since >= after))
+        {
+            self.breathe.start(now);
+        } else {
+            self.breathe.stop(now);
+        }
+
+        let mut leds = match mode {
+            LightMode::Welcome => welcome.unwrap_or_default(),
+            LightMode::TestPattern => test_pattern_frame(now),
+            LightMode::LightsTest => self.lights_test.update(now).unwrap_or_default(),
+            LightMode::AcquireFlash => ACQUIRE_FLASH_FRAME,
+            LightMode::ErraticSteering => erratic_steering_leds(now),
+            LightMode::Strobe => STROBE_PATTERN
+                .map(|pattern| self.strobe.update(pattern, now))
+                .unwrap_or_default(),
+            LightMode::Failsafe | LightMode::NotReady => {
+                // Keeps the rear effects winding down, so they start afresh afterwards
+                self.rear_lights(receiver, true, now);
+                let mut leds = if mode == LightMode::Failsafe {
+                    failsafe_leds(self.failsafe_pattern, now)
+                } else {
+                    not_ready_leds(now)
+                };
+                self.overlay_indicator(&mut leds, state, on);
+                leds
+            }
+            LightMode::Hazard | LightMode::Drive => {
+                let hazard = mode == LightMode::Hazard;
+                let mut leds = self.drive_frame(receiver, aux_switch, hazard, now);
+                if receiver.link_health() == LinkHealth::Degraded && link_warning_lit(now) {
+                    for rear in [&mut leds.rear_left, &mut leds.rear_right] {
+                        rear.red = rear.red.max(LINK_WARNING_LEVEL);
+                    }
+                }
+                self.overlay_indicator(&mut leds, state, on);
+                leds
+            }
         };
+        let recovery = self.recovery.level(now);
+        if recovery < u8::MAX {
+            leds = leds.scaled(recovery);
+        }
+        // Present in every frame or none, so the indicator never moves along the chain
+        leds.center = CENTER_BRAKE_LAMP.then_some(leds.center.unwrap_or(0));
+        leds.enabled = LED_CORNERS;
+        leds
+    }
+
+    /// The turn signals, headlights and rear lights while driving.
+    fn drive_frame(
+        &mut self,
+        receiver: &Receiver,
+        aux_switch: Option<SwitchPos>,
+        hazard: bool,
+        now: Instant,
+    ) -> Leds {
+        let (red, white) = self.rear_lights(receiver, false, now);
+        // Wild readings are left out, so they can't start or cancel a turn
+        let steering = self.steering_guard.filter(receiver.steering_percent(), now);
+        let turn = self.blinker.update(steering, hazard, now);
+        if HEADLIGHT_TRIGGER == HeadlightTrigger::AuxSwitch {
+            if let Some(position) = aux_switch {
+                self.headlights.set(match position {
+                    SwitchPos::Low => Beam::Off,
+                    SwitchPos::Mid => Beam::Low,
+                    SwitchPos::High => Beam::High,
+                });
+            }
+        }
+        let mut leds = indicator_frame(turn.left, turn.right);
+        self.headlights.apply(&mut leds.front_left, now);
+        self.headlights.apply(&mut leds.front_right, now);
+        let breath = self.breathe.tick(now);
+        for front in [&mut leds.front_left, &mut leds.front_right] {
+            front.low_beam = scale_channel(front.low_beam, breath);
+        }
+        leds.rear_left.red = red;
+        leds.rear_right.red = red;
+        leds.rear_left.white = white;
+        leds.rear_right.white = white;
+        leds.center = Some(red);
+        leds
+    }
+
+    /// The rear `(red, white)` levels from the brake, reverse and tach effects.
+    fn rear_lights(&mut self, receiver: &Receiver, failsafe: bool, now: Instant) -> (u8, u8) {
+        let lights = if failsafe {
+            DriveLights::default()
+        } else {
+            self.drive.update(receiver.throttle_state(), now)
+        };
+        // The peak is held so the light doesn't dim as soon as the throttle stops moving
+        self.brake_peak = if lights.brake {
+            self.brake_peak.max(receiver.brake_intensity())
+        } else {
+            0
+        };
+        let brake_level = self.brake_peak.max(MIN_BRAKE_LEVEL);
+        // Each corner is still a single pixel on the wire, so show the middle of the bar
+        let red = scale_channel(
+            self.brake.update(lights.brake, now)[REAR_LEDS_PER_CORNER / 2],
+            brake_level,
+        );
+        let red = match self.brake_flash.update(lights.brake, self.brake_peak, now) {
+            Some(true) => u8::MAX,
+            Some(false) => 0,
+            None => red,
+        };
+        // Ticked even while reversing, so it picks up smoothly afterwards
+        let tach = self.tach.tick(
+            receiver
+                .try_throttle_smoothed_percent()
+                .filter(|_| TACH_PULSE && !failsafe),
+            now,
+        );
+        let reverse = match REVERSE_LIGHTS {
+            ReverseLights::Steady if lights.reverse => u8::MAX,
+            ReverseLights::Steady => 0,
+            ReverseLights::Flash => self.reverse_flash.update(lights.reverse, now),
+        };
+        (red, if lights.reverse { reverse } else { tach })
+    }
+
+    fn overlay_indicator(&self, leds: &mut Leds, state: SafetyState, on: bool) {
+        if EXTERNAL_INDICATOR == ExternalIndicator::Pixel {
+            leds.indicator = indicator_pixel(state, on);
+        }
+    }
+}
+
+/// The external indicator pixel for this half of the blink cycle.
+#[cfg(all(feature = "lights", feature = "receiver"))]
+fn indicator_pixel(state: SafetyState, on: bool) -> IndicatorLed {
+    match state {
+        SafetyState::Armed => IndicatorLed {
+            red: 0,
+            green: 255,
+            blue: 0,
+        },
+        SafetyState::Disarmed => IndicatorLed {
+            red: 255,
+            green: 128,
+            blue: 0,
+        },
+        SafetyState::Failsafe => IndicatorLed {
+            red: if on { 255 } else { 0 },
+            green: 0,
+            blue: 0,
+        },
+    }
+}
+
+/// Whether the external GPIO indicator is lit for this half of the blink cycle.
+#[cfg(feature = "receiver")]
+fn indicator_gpio_on(state: SafetyState, on: bool) -> bool {
+    match state {
+        SafetyState::Armed => true,
+        SafetyState::Disarmed => false,
+        SafetyState::Failsafe => on,
+    }
+}
+
+/// The turn indicators on each side on or off, everything else dark.
+#[cfg(feature = "lights")]
+fn indicator_frame(left: bool, right: bool) -> Leds {
+    let left = if left { INDICATOR_LEVEL } else { 0 };
+    let right = if right { INDICATOR_LEVEL } else { 0 };
+
+    Leds {
+        front_right: FrontLeds {
+            yellow: right,
+            low_beam: 0,
+            high_beam: 0,
+        },
+        front_left: FrontLeds {
+            yellow: left,
+            low_beam: 0,
+            high_beam: 0,
+        },
+        rear_left: RearLeds {
+            yellow: left,
+            white: 0,
+            red: 0,
+        },
+        rear_right: RearLeds {
+            yellow: right,
+            white: 0,
+            red: 0,
+        },
+        indicator: IndicatorLed::default(),
+        center: None,
+        enabled: CornerMask::ALL,
+    }
+}
 
-        leds.write(&mut tx);
+/// The strip's colour order: the saved one if there is one, otherwise `LED_COLOR_ORDER`.
+#[cfg(feature = "lights")]
+fn led_color_order(config: &Config) -> ColorOrder {
+    config
+        .color_order
+        .and_then(ColorOrder::from_index)
+        .unwrap_or(LED_COLOR_ORDER)
+}
 
-        println!(
-            "{} {} {}",
-            receiver.steering(),
-            receiver.throttle(),
-            receiver.has_watchdog_expired()
+/// The ADC and the readings taken through it. Only one owner can hold the
+/// ADC, so the ambient and battery readings borrow it on each update.
+#[cfg(feature = "lights")]
+struct AnalogInputs {
+    /// Present if either reading is on.
+    adc: Option<hal::Adc>,
+    /// Scales the brightness down at night, with `AMBIENT_DIMMING`.
+    ambient: Option<AmbientLight>,
+    /// Triple-blinks the yellows when the pack runs low, with `BATTERY_MONITOR`.
+    battery: Option<BatteryMonitor>,
+}
+
+/// Everything one update runs through, from the receiver to the frame on the
+/// strip and the status LEDs.
+///
+/// Both the bare-metal `System` and the RTIC timer task hold one and call
+/// [`Pipeline::tick`] on every update, so the two builds only differ in how
+/// the update is scheduled and what runs between updates.
+struct Pipeline {
+    /// The on-board LED: solid when armed, blinking while waiting for the gesture, dark in failsafe.
+    status: StatusLed,
+    /// The saved settings, kept so a finished calibration can save over them.
+    #[cfg_attr(not(any(feature = "lights", feature = "receiver")), allow(dead_code))]
+    // Nothing reads it back
+    config: Config,
+    /// The order the strip was set up with, from the config or `LED_COLOR_ORDER`.
+    #[cfg(feature = "lights")]
+    color_order: ColorOrder,
+    #[cfg(feature = "receiver")]
+    receiver: Receiver,
+    #[cfg(feature = "receiver")]
+    arming: Arming,
+    #[cfg(feature = "receiver")]
+    calibration: BootCalibration,
+    #[cfg(feature = "receiver")]
+    telemetry: TelemetryLog,
+    #[cfg(feature = "receiver")]
+    external_indicator: Option<StatusLed>,
+    #[cfg(feature = "lights")]
+    output: LedOutput,
+    #[cfg(feature = "lights")]
+    slew: SlewLimiter,
+    #[cfg(feature = "lights")]
+    animator: Animator,
+    #[cfg(feature = "lights")]
+    frame_limit: FrameLimiter,
+    #[cfg(feature = "lights")]
+    analog: AnalogInputs,
+    #[cfg(all(feature = "lights", not(feature = "receiver")))]
+    strobe: PatternPlayer,
+    #[cfg(all(feature = "lights", feature = "receiver"))]
+    dimmer: MasterDimmer,
+    /// Dims the lights once the car has sat idle for `IDLE_DIM_AFTER`.
+    #[cfg(all(feature = "lights", feature = "receiver"))]
+    park: Option<ParkDimmer>,
+    #[cfg(all(feature = "lights", feature = "receiver"))]
+    lights: LightController,
+}
+
+impl Pipeline {
+    /// Builds the pipeline, applying any endpoints saved in `config` to `receiver`.
+    fn new(
+        status: StatusLed,
+        config: Config,
+        #[cfg(feature = "receiver")] mut receiver: Receiver,
+        #[cfg(feature = "receiver")] external_indicator: Option<StatusLed>,
+        #[cfg(feature = "lights")] output: LedOutput,
+        #[cfg(feature = "lights")] analog: AnalogInputs,
+    ) -> Self {
+        #[cfg(feature = "receiver")]
+        receiver.set_endpoints(
+            config
+                .steering_us
+                .map_or(receiver.steering_endpoints(), Endpoints::from),
+            config
+                .throttle_us
+                .map_or(receiver.throttle_endpoints(), Endpoints::from),
         );
+        #[cfg(all(feature = "lights", feature = "receiver"))]
+        let mut lights = LightController::new();
+        #[cfg(all(feature = "lights", feature = "receiver"))]
+        if let Some(ms) = config.blink_ms {
+            lights
+                .blinker
+                .set_period(MillisDurationU64::millis(ms as u64));
+        }
+        #[cfg(all(feature = "lights", feature = "receiver"))]
+        if let Some(pattern) = config
+            .failsafe_pattern
+            .and_then(FailsafePattern::from_index)
+        {
+            lights.failsafe_pattern = pattern;
+        }
+        Self {
+            status,
+            config,
+            #[cfg(feature = "lights")]
+            color_order: led_color_order(&config),
+            #[cfg(feature = "receiver")]
+            receiver,
+            #[cfg(feature = "receiver")]
+            arming: Arming::new(ARMING_GESTURE),
+            #[cfg(feature = "receiver")]
+            calibration: BootCalibration::new(CALIBRATION_GESTURE),
+            #[cfg(feature = "receiver")]
+            telemetry: TelemetryLog::new(),
+            #[cfg(feature = "receiver")]
+            external_indicator,
+            #[cfg(feature = "lights")]
+            output,
+            #[cfg(feature = "lights")]
+            slew: SlewLimiter::new(LED_MAX_DELTA),
+            #[cfg(feature = "lights")]
+            animator: Animator::new(LED_TRANSITION),
+            #[cfg(feature = "lights")]
+            frame_limit: LED_FRAME_LIMIT,
+            #[cfg(feature = "lights")]
+            analog,
+            #[cfg(all(feature = "lights", not(feature = "receiver")))]
+            strobe: PatternPlayer::new(STROBE_PERIOD),
+            #[cfg(all(feature = "lights", feature = "receiver"))]
+            dimmer: MasterDimmer::new(MIN_BRIGHTNESS),
+            #[cfg(all(feature = "lights", feature = "receiver"))]
+            park: IDLE_DIM_AFTER
+                .map(|after| ParkDimmer::new(after, PARKED_BRIGHTNESS, IDLE_DIM_FADE)),
+            #[cfg(all(feature = "lights", feature = "receiver"))]
+            lights,
+        }
+    }
+
+    /// Carries out a console command, replying on `cli`.
+    #[cfg(all(feature = "cli", not(feature = "rtic")))]
+    fn run_command(&mut self, command: Command, cli: &mut Cli, now: Instant) {
+        match command {
+            Command::Bright(level) => {
+                self.config.brightness = Some(level);
+                cli.reply(format_args!("brightness up to {}", level));
+            }
+            Command::Blink(ms) => {
+                self.config.blink_ms = Some(ms);
+                self.lights
+                    .blinker
+                    .set_period(MillisDurationU64::millis(ms as u64));
+                cli.reply(format_args!("blink every {} ms", ms));
+            }
+            Command::Watchdog(ms) => {
+                self.receiver.set_watchdog_timeout(ms);
+                cli.reply(format_args!("receiver watchdog {} ms", ms));
+            }
+            Command::Beam => {
+                let beam = self.lights.headlights.advance();
+                cli.reply(format_args!("headlights {:?}", beam));
+            }
+            Command::Test => {
+                self.lights.lights_test.toggle(now);
+                cli.reply(format_args!("lights test toggled, see the defmt log"));
+            }
+            Command::Cal => {
+                self.calibration.start(&mut self.receiver, now);
+                cli.reply(format_args!(
+                    "calibrating: move steering and throttle to both ends"
+                ));
+            }
+            Command::Dump => {
+                cli.reply(format_args!(
+                    "{:?}",
+                    Telemetry::new(&self.receiver, self.arming.is_armed())
+                ));
+                cli.reply(format_args!("{:?}", self.config));
+                let mut pulses = [0; PULSE_HISTORY];
+                let count = self.receiver.recent_steering(&mut pulses);
+                cli.reply(format_args!("steering pulses {:?}", &pulses[..count]));
+                let count = self.receiver.recent_throttle(&mut pulses);
+                cli.reply(format_args!("throttle pulses {:?}", &pulses[..count]));
+            }
+            Command::Reset => {
+                self.receiver.reset_diagnostics();
+                self.receiver.reset_extremes();
+                cli.reply(format_args!("diagnostics and extremes cleared"));
+            }
+        }
+    }
+
+    /// Runs one update for `now`: calibration and config from the update
+    /// line, arming, the light mode, then the fades, warmth, gamma and
+    /// brightness, and the frame out to the strip if it can take one.
+    fn tick(&mut self, now: Instant) {
+        let on = blink_on(now);
 
-        delay.delay_ms(500);
-
-        let leds = Leds {
-            front_right: FrontLeds {
-                yellow: 0,
-                low_beam: 0,
-                high_beam: 0,
-            },
-            front_left: FrontLeds {
-                yellow: 0,
-                low_beam: 0,
-                high_beam: 0,
-            },
-            rear_left: RearLeds {
-                yellow: 0,
-                white: 0,
-                red: 0,
-            },
-            rear_right: RearLeds {
-                yellow: 0,
-                white: 0,
-                red: 0,
-            },
+        #[cfg(feature = "receiver")]
+        if let Some(result) = self.calibration.update(&mut self.receiver, now) {
+            // An uncalibrated channel is saved as unset, so it keeps following `RECEIVER_CONFIG`
+            self.config = Config {
+                steering_us: result
+                    .steering
+                    .then(|| self.receiver.steering_endpoints().into()),
+                throttle_us: result
+                    .throttle
+                    .then(|| self.receiver.throttle_endpoints().into()),
+                ..self.config
+            };
+            self.save_config();
+        }
+        #[cfg(feature = "receiver")]
+        if let Some(command) = self.receiver.take_wire_command() {
+            info!("Config from the update line: {}", command);
+            // Applied as a whole, the way a console command would be
+            self.config = match command {
+                WireCommand::Brightness(level) => Config {
+                    brightness: Some(level),
+                    ..self.config
+                },
+                WireCommand::BlinkMs(ms) => Config {
+                    blink_ms: Some(ms),
+                    ..self.config
+                },
+            };
+            #[cfg(feature = "lights")]
+            if let WireCommand::BlinkMs(ms) = command {
+                self.lights
+                    .blinker
+                    .set_period(MillisDurationU64::millis(ms as u64));
+            }
+            self.save_config();
+        }
+        #[cfg(feature = "receiver")]
+        let failsafe = self.receiver.in_failsafe();
+        // Without a receiver there is nothing to lose, so never show the alarm
+        #[cfg(not(feature = "receiver"))]
+        let failsafe = false;
+
+        #[cfg(feature = "receiver")]
+        let armed = self.arming.update(self.receiver.throttle(), failsafe, now);
+        #[cfg(not(feature = "receiver"))]
+        let armed = true;
+
+        #[cfg(feature = "receiver")]
+        let state = SafetyState::new(failsafe, armed);
+        #[cfg(feature = "receiver")]
+        if let Some(indicator) = &mut self.external_indicator {
+            indicator.set(indicator_gpio_on(state, on));
+        }
+
+        #[cfg(all(feature = "lights", feature = "receiver"))]
+        let target = self.lights.update(&self.receiver, state, on, now);
+        // Without a receiver there is no steering, so just blink the left side as a demo
+        #[cfg(all(feature = "lights", not(feature = "receiver")))]
+        let target = Leds {
+            enabled: LED_CORNERS,
+            ..match STROBE_PATTERN {
+                Some(pattern) => self.strobe.update(pattern, now),
+                None => indicator_frame(on, false),
+            }
         };
 
-        leds.write(&mut tx);
+        #[cfg(feature = "lights")]
+        {
+            let battery_low = match (&mut self.analog.battery, &mut self.analog.adc) {
+                (Some(battery), Some(adc)) => battery.update(adc, now),
+                _ => false,
+            };
+            let target = if battery_low {
+                battery_warning(target, now)
+            } else {
+                target
+            };
+            self.animator.set_target(target, now);
+            let mut leds = self.slew.apply(&self.animator.tick(now));
+            leds.set_white_warmth(WHITE_WARMTH);
+            if GAMMA_CORRECTION {
+                leds = leds.gamma_corrected();
+            }
+            #[cfg(feature = "receiver")]
+            let brightness = self
+                .dimmer
+                .update(self.receiver.aux())
+                .min(self.config.brightness.unwrap_or(u8::MAX));
+            #[cfg(not(feature = "receiver"))]
+            let brightness = self.config.brightness.unwrap_or(MASTER_BRIGHTNESS);
+            let brightness = match (&mut self.analog.ambient, &mut self.analog.adc) {
+                (Some(ambient), Some(adc)) => scale_channel(brightness, ambient.update(adc, now)),
+                _ => brightness,
+            };
+            #[cfg(feature = "receiver")]
+            let brightness = match &mut self.park {
+                Some(park) => {
+                    scale_channel(brightness, park.update(park_controls(&self.receiver), now))
+                }
+                None => brightness,
+            };
+            if cfg!(feature = "telemetry") {
+                debug!(
+                    "frame {} at {}",
+                    leds.debug_hex(self.color_order, LED_PIXEL_FORMAT),
+                    brightness
+                );
+            }
+            if self.frame_limit.ready(now) {
+                self.output.show(&leds.scaled(brightness));
+            }
+        }
+
+        self.status.set(!failsafe && (armed || on));
+    }
+
+    /// Writes `config` to flash, with core1 parked first if it is feeding the strip.
+    #[cfg(feature = "receiver")]
+    fn save_config(&mut self) {
+        let config = self.config;
+        #[cfg(feature = "lights")]
+        self.output.with_flash_access(|| config.save());
+        #[cfg(not(feature = "lights"))]
+        config.save();
+    }
+
+    /// Logs the telemetry if `TELEMETRY_INTERVAL` has passed. Cheap to call more often.
+    #[cfg(feature = "receiver")]
+    fn log_telemetry(&mut self, now: Instant) {
+        #[cfg(feature = "lights")]
+        let light_mode = Some(self.lights.current_mode());
+        #[cfg(not(feature = "lights"))]
+        let light_mode = None;
+        self.telemetry
+            .log(&self.receiver, self.arming.is_armed(), light_mode, now);
+    }
+}
 
-        delay.delay_ms(500);
+#[cfg(not(feature = "rtic"))]
+#[entry]
+fn main() -> ! {
+    debug!("Program start");
+    let Some(pac) = pac::Peripherals::take() else {
+        error!("Peripherals already taken");
+        // `take` only fails once they've been handed out, and nothing runs after this
+        #[allow(unsafe_code)]
+        let mut pac = unsafe { pac::Peripherals::steal() };
+        fatal_blink(fatal_status_pin(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            pac.SIO,
+            &mut pac.RESETS,
+        ))
+    };
+    #[cfg(feature = "lights")]
+    let Some(core) = pac::CorePeripherals::take() else {
+        error!("Core peripherals already taken");
+        let mut pac = pac;
+        fatal_blink(fatal_status_pin(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            pac.SIO,
+            &mut pac.RESETS,
+        ))
+    };
+    let mut system = System::new(
+        pac,
+        #[cfg(feature = "lights")]
+        core,
+    );
+    loop {
+        system.tick();
     }
 }
 
@@ -0,0 +1,283 @@
+//! The LEDs' channel levels, and how they pack into the words the WS2812
+//! program shifts out.
+
+/// One pixel's three channels, by their position in the packed word rather than what they light.
+///
+/// `r` is the low byte, then `g` and `b`. Each LED type converts to and from
+/// `Color`, so a pattern can be built as plain colors and the packing lives in
+/// one place.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// The pixel word for this color. The top byte is left clear; only the low 24 bits go out.
+    pub const fn packed(self) -> u32 {
+        (self.b as u32) << 16 | (self.g as u32) << 8 | self.r as u32
+    }
+}
+
+impl From<Color> for u32 {
+    fn from(value: Color) -> Self {
+        value.packed()
+    }
+}
+
+/// One RGBW pixel's four channels, for strips in `PixelFormat::Rgbw`.
+///
+/// Like `Color`, the fields are positions in the word: `r` is the low byte,
+/// then `g`, `b` and `w`. The corners convert to it by what they light, with
+/// yellow mixed from red and green and the whites on the white element.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RgbwColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+impl From<RgbwColor> for u32 {
+    /// All four bytes go out, so there is nothing left over for a prefix.
+    fn from(value: RgbwColor) -> Self {
+        u32::from_le_bytes([value.r, value.g, value.b, value.w])
+    }
+}
+
+/// The red and green that mix to a yellow (amber) at `level`.
+fn amber(level: u8) -> (u8, u8) {
+    (level, level / 2)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrontLeds {
+    pub yellow: u8,
+    pub low_beam: u8,
+    pub high_beam: u8,
+}
+
+impl FrontLeds {
+    /// Which channel of the pixel each LED is wired to.
+    pub const fn color(self) -> Color {
+        Color {
+            r: self.yellow,
+            g: self.low_beam,
+            b: self.high_beam,
+        }
+    }
+}
+
+impl From<FrontLeds> for Color {
+    fn from(value: FrontLeds) -> Self {
+        value.color()
+    }
+}
+
+impl From<Color> for FrontLeds {
+    fn from(value: Color) -> Self {
+        FrontLeds {
+            yellow: value.r,
+            low_beam: value.g,
+            high_beam: value.b,
+        }
+    }
+}
+
+impl From<FrontLeds> for u32 {
+    fn from(value: FrontLeds) -> Self {
+        value.color().packed()
+    }
+}
+
+impl From<FrontLeds> for RgbwColor {
+    /// Both beams share the white element, at whichever is brighter.
+    fn from(value: FrontLeds) -> Self {
+        let (r, g) = amber(value.yellow);
+        RgbwColor {
+            r,
+            g,
+            b: 0,
+            w: value.low_beam.max(value.high_beam),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RearLeds {
+    pub yellow: u8,
+    pub white: u8,
+    pub red: u8,
+}
+
+impl RearLeds {
+    /// Which channel of the pixel each LED is wired to.
+    pub const fn color(self) -> Color {
+        Color {
+            r: self.yellow,
+            g: self.white,
+            b: self.red,
+        }
+    }
+}
+
+impl From<RearLeds> for Color {
+    fn from(value: RearLeds) -> Self {
+        value.color()
+    }
+}
+
+impl From<Color> for RearLeds {
+    fn from(value: Color) -> Self {
+        RearLeds {
+            yellow: value.r,
+            white: value.g,
+            red: value.b,
+        }
+    }
+}
+
+impl From<RearLeds> for u32 {
+    fn from(value: RearLeds) -> Self {
+        value.color().packed()
+    }
+}
+
+impl From<RearLeds> for RgbwColor {
+    /// Red and yellow share the red element, at whichever is brighter.
+    fn from(value: RearLeds) -> Self {
+        let (yellow_r, g) = amber(value.yellow);
+        RgbwColor {
+            r: yellow_r.max(value.red),
+            g,
+            b: 0,
+            w: value.white,
+        }
+    }
+}
+
+/// An optional external state indicator, chained after the rear left corner
+/// (and after the centre lamp, in builds with one).
+///
+/// The channels are packed in the same positions as the corners' (`red` where
+/// they have yellow), so the same LED part can be used. The word is always
+/// sent; with nothing wired after the last corner it simply falls off the end
+/// of the chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndicatorLed {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl From<IndicatorLed> for Color {
+    fn from(value: IndicatorLed) -> Self {
+        Color {
+            r: value.red,
+            g: value.green,
+            b: value.blue,
+        }
+    }
+}
+
+impl From<Color> for IndicatorLed {
+    fn from(value: Color) -> Self {
+        IndicatorLed {
+            red: value.r,
+            green: value.g,
+            blue: value.b,
+        }
+    }
+}
+
+impl From<IndicatorLed> for u32 {
+    fn from(value: IndicatorLed) -> Self {
+        Color::from(value).into()
+    }
+}
+
+impl From<IndicatorLed> for RgbwColor {
+    fn from(value: IndicatorLed) -> Self {
+        RgbwColor {
+            r: value.red,
+            g: value.green,
+            b: value.blue,
+            w: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn front(yellow: u8, low_beam: u8, high_beam: u8) -> u32 {
+        FrontLeds {
+            yellow,
+            low_beam,
+            high_beam,
+        }
+        .into()
+    }
+
+    fn rear(yellow: u8, white: u8, red: u8) -> u32 {
+        RearLeds { yellow, white, red }.into()
+    }
+
+    // Each channel alone, so two swapped shifts can't cancel out
+    #[test]
+    fn front_channels_alone() {
+        assert_eq!(front(0, 0, 0), 0);
+        assert_eq!(front(0xa1, 0, 0), 0x0000_00a1);
+        assert_eq!(front(0, 0xb2, 0), 0x0000_b200);
+        assert_eq!(front(0, 0, 0xc3), 0x00c3_0000);
+    }
+
+    #[test]
+    fn rear_channels_alone() {
+        assert_eq!(rear(0, 0, 0), 0);
+        assert_eq!(rear(0xa1, 0, 0), 0x0000_00a1);
+        assert_eq!(rear(0, 0xb2, 0), 0x0000_b200);
+        assert_eq!(rear(0, 0, 0xc3), 0x00c3_0000);
+    }
+
+    // The front's high beam and the rear's red share the third byte
+    #[test]
+    fn corners_all_together() {
+        assert_eq!(front(0xa1, 0xb2, 0xc3), 0x00c3_b2a1);
+        assert_eq!(rear(0xa1, 0xb2, 0xc3), 0x00c3_b2a1);
+    }
+
+    // Frames used to mark each word with a 0xFF000000 prefix. They start with
+    // a pixel count now, so the top byte of a 24-bit word is always clear.
+    #[test]
+    fn top_byte_clear() {
+        assert_eq!(front(0xff, 0xff, 0xff), 0x00ff_ffff);
+        assert_eq!(rear(0xff, 0xff, 0xff), 0x00ff_ffff);
+        let indicator = IndicatorLed {
+            red: 0xff,
+            green: 0xff,
+            blue: 0xff,
+        };
+        assert_eq!(u32::from(indicator), 0x00ff_ffff);
+    }
+
+    #[test]
+    fn indicator_channels_alone() {
+        let word = |red, green, blue| u32::from(IndicatorLed { red, green, blue });
+        assert_eq!(word(0xa1, 0, 0), 0x0000_00a1);
+        assert_eq!(word(0, 0xb2, 0), 0x0000_b200);
+        assert_eq!(word(0, 0, 0xc3), 0x00c3_0000);
+    }
+
+    // An RGBW word uses all four bytes, white at the top
+    #[test]
+    fn rgbw_uses_every_byte() {
+        let word = |r, g, b, w| u32::from(RgbwColor { r, g, b, w });
+        assert_eq!(word(0xa1, 0, 0, 0), 0x0000_00a1);
+        assert_eq!(word(0, 0xb2, 0, 0), 0x0000_b200);
+        assert_eq!(word(0, 0, 0xc3, 0), 0x00c3_0000);
+        assert_eq!(word(0, 0, 0, 0xd4), 0xd400_0000);
+    }
+}
@@ -1,36 +1,96 @@
-use core::{cell::RefCell, sync::atomic::AtomicU16};
+use core::{
+    cell::{Cell, RefCell},
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicU8},
+};
 
 use critical_section::Mutex;
-use fugit::MillisDurationU64;
+use fugit::{HertzU32, MicrosDurationU64, MillisDurationU64};
+#[cfg(not(feature = "rtic"))]
+use rp2040_hal::pac::{self, interrupt};
 use rp2040_hal::{
-    clocks::ClocksManager,
     gpio::{
-        bank0::{Gpio3, Gpio4, Gpio5},
-        FunctionNull, FunctionSioInput,
+        bank0::{Gpio3, Gpio4, Gpio5, Gpio7},
+        DynPinId, FunctionNull, FunctionSioInput,
         Interrupt::EdgeLow,
-        Pin, PullDown, PullNone,
+        Pin, PinId, PullDown, PullNone, ValidFunction,
     },
-    pac,
-    pac::{interrupt, PWM, RESETS, TIMER},
-    pwm::{InputHighRunning, Pwm1, Pwm2, Slice, Slices},
+    pac::{PWM, RESETS},
+    pwm::{InputHighRunning, Pwm1, Pwm2, Pwm3, Slice, Slices, ValidPwmInputPin},
     timer::Instant,
     Timer,
 };
 
+/// CRSF (Crossfire and ExpressLRS) input, as an alternative to the PWM capture below.
+#[cfg(not(feature = "rtic"))]
+pub mod crsf;
+
+/// Single-wire PPM input, as an alternative to the PWM capture below.
+#[cfg(not(feature = "rtic"))]
+pub mod ppm;
+#[cfg(not(feature = "rtic"))]
+use ppm::PpmIrq;
+
+/// SBUS input, as an alternative to the PWM capture below.
+#[cfg(not(feature = "rtic"))]
+pub mod sbus;
+
+pub use picotrx4m::scaling::apply_dead_band;
+use picotrx4m::scaling::{
+    apply_invert, in_dead_band, is_plausible, mix_differential, scale_pulse, ticks_to_micros,
+};
+use picotrx4m::watchdog::{gap_exceeds, is_stale_on, TimeSource};
+
+mod wire_config;
+pub use wire_config::WireCommand;
+use wire_config::{WireDecoder, WireEdge};
+
+/// The capture pins, type-erased so any valid [`ReceiverPins`] fits, and the slices they feed.
 struct Globals {
-    steering_pin: Pin<Gpio3, FunctionSioInput, PullNone>,
+    steering_pin: Pin<DynPinId, FunctionSioInput, PullNone>,
     steering_pwm: Slice<Pwm1, InputHighRunning>,
-    throttle_pin: Pin<Gpio5, FunctionSioInput, PullNone>,
+    throttle_pin: Pin<DynPinId, FunctionSioInput, PullNone>,
     throttle_pwm: Slice<Pwm2, InputHighRunning>,
-    update_pin: Pin<Gpio4, FunctionSioInput, PullNone>,
+    update_pin: Pin<DynPinId, FunctionSioInput, PullNone>,
+    aux_pin: Pin<DynPinId, FunctionSioInput, PullNone>,
+    aux_pwm: Slice<Pwm3, InputHighRunning>,
 }
 
-static STEERING: AtomicU16 = AtomicU16::new(0);
-static THROTTLE: AtomicU16 = AtomicU16::new(0);
+/// Integer divider from the system clock to the capture slices.
+///
+/// At the default 125 MHz system clock this makes one tick one µs. Other
+/// clocks still work, as the counts are converted with the real clock, but
+/// lose resolution below 125 MHz.
+const PWM_DIVIDER: u8 = 125;
+
+/// The system clock `PWM_DIVIDER` was picked for, assumed until an initializer says otherwise.
+const NOMINAL_SYSTEM_HZ: u32 = 125_000_000;
+
+/// Watchdog timeout until `initialize_receiver` installs the configured one.
+const DEFAULT_WATCHDOG_TIMEOUT: MillisDurationU64 = MillisDurationU64::millis(100u64);
+
+/// Pulses outside this range (in µs) are not servo positions, so the channel is treated as faulted.
+const VALID_PULSE_US: RangeInclusive<u16> = 500..=2500;
 
 struct TimerPair {
     timer: Option<Timer>,
     last_update: Instant,
+    /// The update edge before `last_update`, or `None` until there have been two.
+    prev_update: Option<Instant>,
+    last_steering: Instant,
+    last_throttle: Instant,
+    last_aux: Instant,
+    /// How long a signal may go without an edge before it is considered lost.
+    watchdog_timeout: MillisDurationU64,
+}
+
+/// The receiver's `Timer`, as the clock the watchdog reads.
+struct TimerClock<'a>(&'a Timer);
+
+impl TimeSource for TimerClock<'_> {
+    fn now(&self) -> Instant {
+        self.0.get_counter()
+    }
 }
 
 impl TimerPair {
@@ -38,8 +98,20 @@ impl TimerPair {
         Self {
             timer: None,
             last_update: Instant::from_ticks(0),
+            prev_update: None,
+            last_steering: Instant::from_ticks(0),
+            last_throttle: Instant::from_ticks(0),
+            last_aux: Instant::from_ticks(0),
+            watchdog_timeout: DEFAULT_WATCHDOG_TIMEOUT,
         }
     }
+
+    /// Whether more than `watchdog_timeout` has passed since `since`.
+    fn is_stale(&self, since: Instant) -> bool {
+        let clock = self.timer.as_ref().map(TimerClock);
+        let clock = clock.as_ref().map(|clock| clock as &dyn TimeSource);
+        is_stale_on(clock, since, self.watchdog_timeout)
+    }
 }
 
 trait TimerWatchdog {
@@ -50,133 +122,1849 @@ impl TimerWatchdog for Mutex<RefCell<TimerPair>> {
     fn has_watchdog_expired(&self) -> bool {
         critical_section::with(|cs| {
             let pair = self.borrow(cs).borrow();
-            if let Some(timer) = &pair.timer {
-                let current = timer.get_counter();
-                let delta = current - pair.last_update;
-                delta > MillisDurationU64::millis(100u64)
-            } else {
-                true
+            pair.is_stale(pair.last_update)
+        })
+    }
+}
+
+/// Everything one run of the ISR saw, published to `SHARED` in one go.
+///
+/// A channel is `Some` when its edge completed a measurement this run.
+#[derive(Clone, Copy, Default)]
+struct Edges {
+    steering: Option<u16>,
+    throttle: Option<u16>,
+    aux: Option<u16>,
+    update: bool,
+}
+
+/// Which control channels currently look broken.
+///
+/// A channel is faulted if it has not produced a pulse within the watchdog
+/// timeout, or its last pulse was outside the range a servo signal can take.
+/// Pulses outside `ReceiverConfig`'s plausible window are dropped rather than
+/// stored, so a channel sending nothing but those faults once it goes stale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ChannelFaults {
+    pub steering: bool,
+    pub throttle: bool,
+}
+
+/// How the capture hardware turns a channel's signal into a value.
+///
+/// Most receivers send one 1–2 ms pulse per channel per frame, repeated every
+/// 10–20 ms. That is `PulseWidth`, the default. Some receivers (and many
+/// brushed ESC-style outputs labelled "PWM") instead drive a continuous square
+/// wave whose duty cycle carries the position, with no gap between pulses. If
+/// the channel values jump around or sit near a few hundred µs on a scope that
+/// shows a steady square wave, use `DutyCycle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // A receiver sends one kind of signal, so a build captures one way
+pub enum CaptureMode {
+    /// Each falling edge ends a pulse; the value is its high time in µs.
+    PulseWidth,
+    /// High time is accumulated over `DUTY_WINDOW` and mapped from 0–100 % onto
+    /// `DUTY_MIN_US..=DUTY_MAX_US`, so 50 % reads as 1500 µs.
+    DutyCycle,
+}
+
+/// How long `CaptureMode::DutyCycle` averages over before publishing a value.
+///
+/// The window closes on the first falling edge after this much time, so it
+/// always covers whole periods of the input.
+const DUTY_WINDOW: MillisDurationU64 = MillisDurationU64::millis(20u64);
+
+/// Pulse-equivalent values (in µs) that 0 % and 100 % duty map to.
+const DUTY_MIN_US: u16 = 1000;
+const DUTY_MAX_US: u16 = 2000;
+
+/// Per-channel capture state, kept by the ISR.
+struct Capture {
+    mode: CaptureMode,
+    window_start: Instant,
+}
+
+impl Capture {
+    /// Called on each falling edge with the counter's high-time count.
+    ///
+    /// Returns the channel value if this edge ends a measurement, in which case
+    /// the caller must reset the counter. `None` means keep accumulating.
+    fn on_falling_edge(&mut self, count: u16, now: Instant) -> Option<u16> {
+        match self.mode {
+            CaptureMode::PulseWidth => Some(count),
+            CaptureMode::DutyCycle => {
+                let elapsed = now - self.window_start;
+                if elapsed < DUTY_WINDOW {
+                    return None;
+                }
+                self.window_start = now;
+
+                let elapsed_us = elapsed.to_micros();
+                if elapsed_us > u16::MAX as u64 {
+                    // The counter may have wrapped, so the count means nothing.
+                    // 0 is outside `VALID_PULSE_US` and reads as a fault.
+                    return Some(0);
+                }
+
+                let high_us = (count as u64).min(elapsed_us);
+                let span = (DUTY_MAX_US - DUTY_MIN_US) as u64;
+                Some(DUTY_MIN_US + (high_us * span / elapsed_us) as u16)
             }
+        }
+    }
+}
+
+/// Radio link quality, as reported by receivers that send it over their serial link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LinkStats {
+    /// Signal strength at the receiver's active antenna, in dBm.
+    pub rssi_dbm: i16,
+    /// Share of packets received, in percent.
+    pub link_quality: u8,
+}
+
+/// Where [`LinkHealth`] changes, as link quality percentages.
+///
+/// Each state is entered once the quality drops below its threshold, and only
+/// left once it has climbed `hysteresis` above it, so a link hovering on a
+/// boundary doesn't chatter between states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LinkThresholds {
+    /// Below this the link is [`LinkHealth::Degraded`]. 0 never warns.
+    pub warn_below: u8,
+    /// Below this the link is [`LinkHealth::Lost`], which is failsafe. 0
+    /// leaves failsafe to the frame watchdog alone.
+    pub failsafe_below: u8,
+    pub hysteresis: u8,
+}
+
+impl LinkThresholds {
+    /// Never warns or fails safe on link quality.
+    const DISABLED: LinkThresholds = LinkThresholds {
+        warn_below: 0,
+        failsafe_below: 0,
+        hysteresis: 0,
+    };
+}
+
+/// How the radio link is doing, judged from [`LinkStats::link_quality`] against [`LinkThresholds`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LinkHealth {
+    Good,
+    /// Still in control, but below the warning threshold.
+    Degraded,
+    /// Below the failsafe threshold.
+    Lost,
+}
+
+/// The health after a `quality` reading, starting from `health`.
+const fn next_link_health(
+    health: LinkHealth,
+    quality: u8,
+    thresholds: LinkThresholds,
+) -> LinkHealth {
+    const fn below(quality: u8, threshold: u8, inside: bool, hysteresis: u8) -> bool {
+        let threshold = if inside {
+            threshold as u16 + hysteresis as u16
+        } else {
+            threshold as u16
+        };
+        (quality as u16) < threshold
+    }
+
+    let lost = matches!(health, LinkHealth::Lost);
+    let degraded = !matches!(health, LinkHealth::Good);
+    if below(
+        quality,
+        thresholds.failsafe_below,
+        lost,
+        thresholds.hysteresis,
+    ) {
+        LinkHealth::Lost
+    } else if below(
+        quality,
+        thresholds.warn_below,
+        degraded,
+        thresholds.hysteresis,
+    ) {
+        LinkHealth::Degraded
+    } else {
+        LinkHealth::Good
+    }
+}
+
+// Each boundary crossed both ways, the hysteresis holding a state, and a jump
+// across both bands.
+const _: () = {
+    const THRESHOLDS: LinkThresholds = LinkThresholds {
+        warn_below: 50,
+        failsafe_below: 20,
+        hysteresis: 10,
+    };
+    const fn is(health: LinkHealth, quality: u8, expected: LinkHealth) -> bool {
+        let next = next_link_health(health, quality, THRESHOLDS);
+        next as u8 == expected as u8
+    }
+    use LinkHealth::{Degraded, Good, Lost};
+    assert!(is(Good, 100, Good));
+    assert!(is(Good, 50, Good));
+    assert!(is(Good, 49, Degraded));
+    assert!(is(Degraded, 55, Degraded));
+    assert!(is(Degraded, 59, Degraded));
+    assert!(is(Degraded, 60, Good));
+    assert!(is(Degraded, 20, Degraded));
+    assert!(is(Degraded, 19, Lost));
+    assert!(is(Lost, 25, Lost));
+    assert!(is(Lost, 30, Degraded));
+    assert!(is(Lost, 60, Good));
+    assert!(is(Good, 0, Lost));
+    // Disabled thresholds never leave Good
+    assert!(matches!(
+        next_link_health(Good, 0, LinkThresholds::DISABLED),
+        Good
+    ));
+};
+
+/// One channel in a [`Snapshot`]: the pulse as captured and as a percentage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ChannelReading {
+    /// The last pulse in µs, or `None` until one has been captured.
+    pub raw: Option<u16>,
+    /// `raw` as -100..=100 %, with the endpoints and inversion applied.
+    pub percent: Option<i16>,
+}
+
+/// Steering, throttle, aux and the watchdog as they all stood at one instant.
+///
+/// From [`Receiver::snapshot`]. The individual getters each read on their
+/// own, so an interrupt can land between two of them; these can't straddle one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Snapshot {
+    pub steering: ChannelReading,
+    pub throttle: ChannelReading,
+    /// As [`Receiver::aux`]: `None` if it is unplugged or out of range.
+    pub aux: Option<u16>,
+    pub watchdog_expired: bool,
+}
+
+/// Event counts since boot or the last [`Receiver::reset_diagnostics`].
+///
+/// Counters wrap on overflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Diagnostics {
+    /// Update edges, one per received frame.
+    pub frames: u32,
+    /// Steering and throttle pulses dropped for being outside the plausible
+    /// window. See [`Receiver::glitch_count`].
+    pub glitches: u32,
+}
+
+impl Diagnostics {
+    const fn default() -> Self {
+        Self {
+            frames: 0,
+            glitches: 0,
+        }
+    }
+}
+
+/// Rates above this (in mHz) can't come from a real receiver, so they mean the counter was reset.
+const MAX_PLAUSIBLE_FRAME_RATE_MHZ: u64 = 1_000_000;
+
+/// How fast (in µs per second) the smoothed throttle has to fall to read as full braking.
+///
+/// 5000 is the whole 1000 µs travel in 200 ms, a hard stab from full forward.
+const FULL_BRAKE_RATE_US_PER_S: u64 = 5_000;
+
+/// Brake intensity, 0..=255, from the smoothed throttle falling from `previous`
+/// to `current` over `elapsed`. A rising or steady throttle, or no elapsed
+/// time to divide by, reads as 0.
+fn brake_intensity(previous: u16, current: u16, elapsed: MicrosDurationU64) -> u8 {
+    let drop = previous.saturating_sub(current) as u64;
+    let elapsed_us = elapsed.ticks();
+    if drop == 0 || elapsed_us == 0 {
+        return 0;
+    }
+
+    let rate = drop * 1_000_000 / elapsed_us;
+    (rate * u8::MAX as u64 / FULL_BRAKE_RATE_US_PER_S).min(u8::MAX as u64) as u8
+}
+
+/// The rate in Hz that one frame every `interval` works out to, rounded.
+///
+/// A zero interval gives `None`; anything faster than `u16::MAX` Hz saturates,
+/// though no receiver gets anywhere near that.
+fn update_rate_hz(interval: MicrosDurationU64) -> Option<u16> {
+    let interval_us = interval.ticks();
+    if interval_us == 0 {
+        return None;
+    }
+
+    let rate = (1_000_000 + interval_us / 2) / interval_us;
+    Some(rate.min(u16::MAX as u64) as u16)
+}
+
+/// Frame rate in mHz from two readings of [`Diagnostics::frames`] taken `elapsed` apart.
+///
+/// The frame count is subtracted with wrapping, so one rollover of the `u32`
+/// counter between the readings still gives the right answer. More than 2^32
+/// frames between readings can't be detected, but at 100 Hz that is over 497
+/// days without a sample. A zero `elapsed`, or a result no receiver could
+/// produce (the counter was reset in between), gives `None`.
+const fn frame_rate_mhz(
+    frames: u32,
+    previous_frames: u32,
+    elapsed: MicrosDurationU64,
+) -> Option<u32> {
+    let elapsed_us = elapsed.ticks();
+    if elapsed_us == 0 {
+        return None;
+    }
+
+    // At most 2^32 * 10^9, which fits in a u64
+    let delta = frames.wrapping_sub(previous_frames) as u64;
+    let rate = delta * 1_000_000_000 / elapsed_us;
+    if rate > MAX_PLAUSIBLE_FRAME_RATE_MHZ {
+        None
+    } else {
+        Some(rate as u32)
+    }
+}
+
+// A counter that wraps between readings gives the same rate as the plain
+// difference, and one reset in between gives none.
+const _: () = {
+    const SECOND: MicrosDurationU64 = MicrosDurationU64::secs(1);
+    const fn is(rate: Option<u32>, expected: u32) -> bool {
+        matches!(rate, Some(rate) if rate == expected)
+    }
+    assert!(is(frame_rate_mhz(10, 0, SECOND), 10_000));
+    assert!(is(frame_rate_mhz(5, u32::MAX - 4, SECOND), 10_000));
+    assert!(is(
+        frame_rate_mhz(0, u32::MAX, MicrosDurationU64::millis(100)),
+        10_000
+    ));
+    assert!(is(frame_rate_mhz(1000, 0, SECOND), 1_000_000));
+    assert!(frame_rate_mhz(1001, 0, SECOND).is_none());
+    assert!(frame_rate_mhz(3, 1000, SECOND).is_none());
+    assert!(frame_rate_mhz(10, 0, MicrosDurationU64::micros(0)).is_none());
+};
+
+/// Measures the frame rate between successive diagnostic samples.
+///
+/// The timer is a 64-bit µs counter, which does not wrap for over 500,000
+/// years, so the only wrap that matters in practice is the frame counter's
+/// (see `frame_rate_mhz`). Time is measured with a checked difference anyway,
+/// so a timestamp that goes backwards yields `None` rather than a huge
+/// interval.
+pub struct FrameRateMeter {
+    previous: Option<(u32, Instant)>,
+}
+
+impl FrameRateMeter {
+    pub const fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// Records a sample and returns the rate in mHz since the previous one, if known.
+    pub fn sample(&mut self, diagnostics: Diagnostics, now: Instant) -> Option<u32> {
+        let previous = self.previous.replace((diagnostics.frames, now));
+        let (previous_frames, previous_time) = previous?;
+        let elapsed = now.checked_duration_since(previous_time)?;
+        frame_rate_mhz(diagnostics.frames, previous_frames, elapsed)
+    }
+}
+
+/// Largest `ReceiverConfig::smoothing_shift` that is honoured; larger values are clamped.
+const MAX_SMOOTHING_SHIFT: u8 = 8;
+
+/// Integer-only exponential moving average over one channel's pulses.
+#[derive(Clone, Copy)]
+struct Smoother {
+    /// The average in 8.8 fixed point, so small steps aren't lost to rounding.
+    /// `None` until the first sample.
+    average: Option<i32>,
+}
+
+impl Smoother {
+    const fn new() -> Self {
+        Self { average: None }
+    }
+
+    /// Moves the average `1 / 2^shift` of the way to `sample` and returns it,
+    /// or restarts it at `sample` if `restart` is set.
+    fn update(&mut self, sample: u16, shift: u8, restart: bool) -> u16 {
+        let sample = (sample as i32) << 8;
+        let average = match self.average {
+            Some(average) if !restart => average + ((sample - average) >> shift),
+            _ => sample,
+        };
+        self.average = Some(average);
+        ((average + 0x80) >> 8) as u16
+    }
+}
+
+/// The ISR's smoothing state for the control channels.
+struct Smoothing {
+    shift: u8,
+    steering: Smoother,
+    throttle: Smoother,
+}
+
+impl Smoothing {
+    fn new(shift: u8) -> Self {
+        Self {
+            shift: shift.min(MAX_SMOOTHING_SHIFT),
+            steering: Smoother::new(),
+            throttle: Smoother::new(),
+        }
+    }
+}
+
+/// Per-receiver tuning passed to `initialize_receiver`.
+///
+/// The pulse widths are in µs and turn raw pulses into percentages.
+/// `neutral_us` maps to 0 %, `min_us` to -100 % and `max_us` to +100 %. The
+/// two halves are scaled separately, so the neutral point doesn't have to sit
+/// in the middle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReceiverConfig {
+    pub neutral_us: u16,
+    pub min_us: u16,
+    pub max_us: u16,
+    /// Half-width of the dead band around `neutral_us` that reads as
+    /// [`ThrottleState::Neutral`].
+    pub neutral_band_us: u16,
+    /// How long frames may stop before the receiver reports failsafe. 100 ms
+    /// suits most receivers; 50 Hz ones that drop the odd frame want 250 ms
+    /// or so. Can be changed later with [`Receiver::set_watchdog_timeout`].
+    pub watchdog_timeout: MillisDurationU64,
+    /// How hard to smooth the values behind [`Receiver::steering_smoothed`] and
+    /// [`Receiver::throttle_smoothed`]. Each pulse moves them `1 / 2^shift` of the
+    /// way towards it, so 0 is no smoothing and each step up doubles how many
+    /// frames they take to settle. Up to `MAX_SMOOTHING_SHIFT`.
+    pub smoothing_shift: u8,
+    /// Steering and throttle pulses outside `plausible_min_us..=plausible_max_us`
+    /// are electrical noise, not stick movements. They are counted and dropped,
+    /// so the channel keeps its previous value.
+    pub plausible_min_us: u16,
+    pub plausible_max_us: u16,
+    /// Steering within this many percent of centre reads as exactly 0 %, in
+    /// [`Receiver::steering_percent`] and so for the turn signals too. Keeps a
+    /// wiggle while driving straight from blinking an indicator.
+    pub steering_dead_band_percent: u8,
+    /// Flips the sign of the steering percentages, for a servo that runs the
+    /// wrong way, which also swaps which side's indicators blink. The raw
+    /// pulse readings are left as captured.
+    pub invert_steering: bool,
+    /// Flips the sign of the throttle percentages, which swaps forward with
+    /// reverse and what counts as braking. The raw pulse readings are left as
+    /// captured.
+    pub invert_throttle: bool,
+    /// Picks config commands out of the update line while it is otherwise
+    /// quiet, see [`Receiver::take_wire_command`]. Off, every update edge is a
+    /// frame.
+    pub wire_config: bool,
+}
+
+impl ReceiverConfig {
+    fn plausible_us(&self) -> RangeInclusive<u16> {
+        self.plausible_min_us..=self.plausible_max_us
+    }
+
+    /// The throttle pulses that read as [`ThrottleState::Neutral`].
+    fn neutral_band(&self) -> RangeInclusive<u16> {
+        self.neutral_us.saturating_sub(self.neutral_band_us)
+            ..=self.neutral_us.saturating_add(self.neutral_band_us)
+    }
+
+    fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            min_us: self.min_us,
+            neutral_us: self.neutral_us,
+            max_us: self.max_us,
+        }
+    }
+}
+
+/// The pulse widths, in µs, that one channel's percentages are measured against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Endpoints {
+    pub min_us: u16,
+    pub neutral_us: u16,
+    pub max_us: u16,
+}
+
+impl From<[u16; 3]> for Endpoints {
+    /// From `[min, neutral, max]`, the way `Config` stores them.
+    fn from([min_us, neutral_us, max_us]: [u16; 3]) -> Self {
+        Endpoints {
+            min_us,
+            neutral_us,
+            max_us,
+        }
+    }
+}
+
+impl From<Endpoints> for [u16; 3] {
+    fn from(endpoints: Endpoints) -> Self {
+        [endpoints.min_us, endpoints.neutral_us, endpoints.max_us]
+    }
+}
+
+/// How far (in µs) a calibration run must see a channel travel each side of
+/// neutral for its endpoints to be used.
+const MIN_CALIBRATED_TRAVEL_US: u16 = 100;
+
+/// The shortest and longest valid pulses seen on one channel, during a calibration run or since boot.
+#[derive(Clone, Copy, Debug, Default)]
+struct PulseRange(Option<(u16, u16)>);
+
+impl PulseRange {
+    fn include(&mut self, pulse: Option<u16>) {
+        let Some(pulse) = pulse.filter(|pulse| VALID_PULSE_US.contains(pulse)) else {
+            return;
+        };
+        self.0 = Some(match self.0 {
+            Some((min, max)) => (min.min(pulse), max.max(pulse)),
+            None => (pulse, pulse),
+        });
+    }
+
+    /// The endpoints this range calibrates to, keeping the configured neutral.
+    ///
+    /// `None` unless the channel moved at least `MIN_CALIBRATED_TRAVEL_US` each
+    /// side of neutral, so a channel that was stuck, unplugged or simply not
+    /// moved keeps its configured endpoints rather than getting a range too
+    /// narrow to map.
+    fn endpoints(self, neutral_us: u16) -> Option<Endpoints> {
+        let (min_us, max_us) = self.0?;
+        let travelled = min_us.saturating_add(MIN_CALIBRATED_TRAVEL_US) <= neutral_us
+            && neutral_us.saturating_add(MIN_CALIBRATED_TRAVEL_US) <= max_us;
+        travelled.then_some(Endpoints {
+            min_us,
+            neutral_us,
+            max_us,
         })
     }
 }
 
-static LAST_UPDATE: Mutex<RefCell<TimerPair>> = Mutex::new(RefCell::new(TimerPair::default()));
+/// The ranges recorded by a calibration run in progress, or since boot for
+/// [`Receiver::steering_extremes`].
+#[derive(Clone, Copy, Debug, Default)]
+struct CalibrationRanges {
+    steering: PulseRange,
+    throttle: PulseRange,
+}
 
-static GLOBAL_PINS: Mutex<RefCell<Option<Globals>>> = Mutex::new(RefCell::new(None));
+/// How many of the latest pulses [`Receiver::recent_steering`] and [`Receiver::recent_throttle`] keep.
+pub const PULSE_HISTORY: usize = 16;
 
-#[interrupt]
-fn IO_IRQ_BANK0() {
-    static mut GLOBALS: Option<Globals> = None;
+/// The last `PULSE_HISTORY` pulses captured on one channel, overwriting the oldest.
+///
+/// Pushing is a store and two increments, so the ISR can afford it on every pulse.
+#[derive(Clone, Copy)]
+struct PulseRing {
+    pulses: [u16; PULSE_HISTORY],
+    /// Where the next pulse goes.
+    next: usize,
+    /// How many of `pulses` hold one, up to `PULSE_HISTORY`.
+    len: usize,
+}
+
+impl PulseRing {
+    const fn new() -> Self {
+        Self {
+            pulses: [0; PULSE_HISTORY],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, pulse: u16) {
+        self.pulses[self.next] = pulse;
+        self.next = (self.next + 1) % PULSE_HISTORY;
+        self.len = (self.len + 1).min(PULSE_HISTORY);
+    }
+
+    /// Copies the latest pulses into `out`, oldest first, and returns how many.
+    ///
+    /// A shorter `out` gets the most recent ones that fit.
+    fn copy_to(&self, out: &mut [u16]) -> usize {
+        let count = self.len.min(out.len());
+        let first = (self.next + PULSE_HISTORY - count) % PULSE_HISTORY;
+        for (i, slot) in out[..count].iter_mut().enumerate() {
+            *slot = self.pulses[(first + i) % PULSE_HISTORY];
+        }
+        count
+    }
+}
+
+/// The recent pulses on each control channel.
+#[derive(Clone, Copy)]
+struct PulseHistory {
+    steering: PulseRing,
+    throttle: PulseRing,
+}
+
+/// Which channels took up the endpoints recorded by [`Receiver::end_calibration`].
+///
+/// A channel that didn't travel far enough keeps the endpoints from `ReceiverConfig`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct CalibrationResult {
+    pub steering: bool,
+    pub throttle: bool,
+}
+
+/// Which way the throttle is pushed, relative to the neutral dead band.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ThrottleState {
+    Forward,
+    Neutral,
+    Reverse,
+}
+
+/// Classifies a throttle pulse, honouring `invert_throttle`. No pulse yet (0) reads as neutral.
+fn throttle_state(pulse: u16, config: &ReceiverConfig) -> ThrottleState {
+    if in_dead_band(pulse, config.neutral_us, config.neutral_band_us) {
+        ThrottleState::Neutral
+    } else if (pulse > config.neutral_us) != config.invert_throttle {
+        ThrottleState::Forward
+    } else {
+        ThrottleState::Reverse
+    }
+}
+
+/// Where a 3-position switch on the aux channel is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SwitchPos {
+    Low,
+    Mid,
+    High,
+}
+
+/// Buckets a switch pulse into thirds of the calibrated travel, so the middle
+/// position doesn't need to sit exactly on neutral.
+fn switch_position(pulse: u16, config: &ReceiverConfig) -> Option<SwitchPos> {
+    pulse_percent(pulse, &config.endpoints(), false).map(|percent| match percent {
+        ..=-34 => SwitchPos::Low,
+        34.. => SwitchPos::High,
+        _ => SwitchPos::Mid,
+    })
+}
+
+/// Holds a switch position back until it has been read steadily for `hold`.
+///
+/// Flipping a 3-position switch between its ends sweeps the pulse through the
+/// middle, and a read taken in that moment sees Mid. Mid never lasts `hold`
+/// on the way past, so it is never reported, and a flick from Low to High
+/// goes straight to High once High has settled. Losing the channel (`None`)
+/// is debounced the same way, so a single dropped pulse doesn't count as a
+/// change either.
+#[cfg(feature = "lights")] // Only the light modes follow the switch
+pub struct SwitchDebouncer {
+    hold: MillisDurationU64,
+    stable: Option<SwitchPos>,
+    /// A different position being read, and since when.
+    pending: Option<(Option<SwitchPos>, Instant)>,
+}
+
+#[cfg(feature = "lights")]
+impl SwitchDebouncer {
+    /// Starts with no position. A zero `hold` passes every read straight through.
+    pub fn new(hold: MillisDurationU64) -> Self {
+        Self {
+            hold,
+            stable: None,
+            pending: None,
+        }
+    }
+
+    /// Feeds the instantaneous position read at `now`, and returns the debounced one.
+    pub fn update(&mut self, position: Option<SwitchPos>, now: Instant) -> Option<SwitchPos> {
+        self.pending = if position == self.stable {
+            None
+        } else {
+            let since = match self.pending {
+                Some((pending, since)) if pending == position => since,
+                _ => now,
+            };
+            if now - since >= self.hold {
+                self.stable = position;
+                None
+            } else {
+                Some((position, since))
+            }
+        };
+        self.stable
+    }
+}
 
-    if GLOBALS.is_none() {
+/// Maps a pulse onto -100..=100 using `endpoints`, clamping outside them, and
+/// flips the sign if the channel is `inverted`.
+///
+/// A reading of 0 means no pulse has been captured yet, so it gives `None`.
+fn pulse_percent(pulse: u16, endpoints: &Endpoints, inverted: bool) -> Option<i16> {
+    scale_pulse(
+        pulse,
+        endpoints.min_us,
+        endpoints.neutral_us,
+        endpoints.max_us,
+    )
+    .map(|percent| apply_invert(percent, inverted))
+}
+
+/// What to do when steering and throttle are both faulted while frames are still arriving.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)] // main's COMBINED_FAULT_POLICY picks one for the whole run
+pub enum CombinedFaultPolicy {
+    /// Leave the decision to the frame watchdog alone.
+    Ignore,
+    /// Treat it as a hard fault and enter failsafe as if the watchdog had expired.
+    Failsafe,
+}
+
+/// Everything shared between `IO_IRQ_BANK0` and the main loop.
+///
+/// Fields fall into two groups, and new fields should pick one explicitly:
+///
+/// * Single-word values the ISR publishes (`steering`, `throttle`, `aux`,
+///   `signal_seen`) are atomics.
+///   The ISR is the only writer and stores with `Release`; readers load with
+///   `Acquire`. No critical section is needed.
+/// * Anything wider than a word, or that must be read and written together
+///   (`timing`, `diagnostics`, `link_stats`, `history`, `extremes`, `calibration`, `pins`), lives in a `Mutex<RefCell<..>>` and is only touched
+///   inside `critical_section::with`.
+///
+/// `pins` is a one-shot handoff: `initialize_receiver` stores the hardware,
+/// and the ISR takes it on its first run. Nothing else touches it afterwards.
+/// With the `rtic` feature the framework owns the pins instead, so the field
+/// does not exist.
+struct SharedState {
+    steering: AtomicU16,
+    throttle: AtomicU16,
+    steering_smoothed: AtomicU16,
+    throttle_smoothed: AtomicU16,
+    /// From the last two smoothed throttle values. See `brake_intensity`.
+    brake_intensity: AtomicU8,
+    /// The same for a rising throttle, which is braking on an inverted channel.
+    brake_intensity_inverted: AtomicU8,
+    aux: AtomicU16,
+    /// Set on the first captured steering and throttle pulse respectively, and never cleared.
+    steering_seen: AtomicBool,
+    throttle_seen: AtomicBool,
+    /// Set on the first update edge after boot and never cleared.
+    signal_seen: AtomicBool,
+    /// Set the first time a throttle pulse lands in the neutral band, and never cleared.
+    armed: AtomicBool,
+    timing: Mutex<RefCell<TimerPair>>,
+    /// Kept under a lock rather than as separate atomics so a reset clears
+    /// every counter at the same instant relative to the ISR.
+    diagnostics: Mutex<RefCell<Diagnostics>>,
+    /// Only serial receivers that report it fill this in.
+    link_stats: Mutex<RefCell<Option<LinkStats>>>,
+    /// Every steering and throttle pulse captured, glitches included, so a
+    /// rejection can be seen alongside the pulses that led up to it.
+    history: Mutex<RefCell<PulseHistory>>,
+    /// The plausible pulses seen since boot or the last [`Receiver::reset_extremes`].
+    extremes: Mutex<RefCell<CalibrationRanges>>,
+    /// `Some` while a calibration run is recording.
+    calibration: Mutex<RefCell<Option<CalibrationRanges>>>,
+    /// The last command decoded from the update line, until it is taken.
+    wire_command: Mutex<RefCell<Option<WireCommand>>>,
+    #[cfg(not(feature = "rtic"))]
+    pins: Mutex<RefCell<Option<ReceiverIrq>>>,
+}
+
+impl SharedState {
+    const fn new() -> Self {
+        Self {
+            steering: AtomicU16::new(0),
+            throttle: AtomicU16::new(0),
+            steering_smoothed: AtomicU16::new(0),
+            throttle_smoothed: AtomicU16::new(0),
+            brake_intensity: AtomicU8::new(0),
+            brake_intensity_inverted: AtomicU8::new(0),
+            aux: AtomicU16::new(0),
+            steering_seen: AtomicBool::new(false),
+            throttle_seen: AtomicBool::new(false),
+            signal_seen: AtomicBool::new(false),
+            armed: AtomicBool::new(false),
+            timing: Mutex::new(RefCell::new(TimerPair::default())),
+            diagnostics: Mutex::new(RefCell::new(Diagnostics::default())),
+            link_stats: Mutex::new(RefCell::new(None)),
+            history: Mutex::new(RefCell::new(PulseHistory {
+                steering: PulseRing::new(),
+                throttle: PulseRing::new(),
+            })),
+            extremes: Mutex::new(RefCell::new(CalibrationRanges {
+                steering: PulseRange(None),
+                throttle: PulseRange(None),
+            })),
+            calibration: Mutex::new(RefCell::new(None)),
+            wire_command: Mutex::new(RefCell::new(None)),
+            #[cfg(not(feature = "rtic"))]
+            pins: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    fn install_timer(&self, timer: Timer, watchdog_timeout: MillisDurationU64) {
         critical_section::with(|cs| {
-            *GLOBALS = GLOBAL_PINS.borrow(cs).take();
+            self.timing.borrow(cs).replace(TimerPair {
+                timer: Some(timer),
+                watchdog_timeout,
+                ..TimerPair::default()
+            });
         });
     }
 
-    if let Some(globals) = GLOBALS {
+    fn set_watchdog_timeout(&self, watchdog_timeout: MillisDurationU64) {
+        critical_section::with(|cs| {
+            self.timing.borrow(cs).borrow_mut().watchdog_timeout = watchdog_timeout;
+        });
+    }
+
+    /// Hands the pins to the ISR. Must run before the interrupt is unmasked.
+    #[cfg(not(feature = "rtic"))]
+    fn install_pins(&self, irq: ReceiverIrq) {
+        critical_section::with(|cs| {
+            self.pins.borrow(cs).replace(Some(irq));
+        });
+    }
+
+    /// Takes ownership of the pins. Only the ISR calls this.
+    #[cfg(not(feature = "rtic"))]
+    fn take_pins(&self) -> Option<ReceiverIrq> {
+        critical_section::with(|cs| self.pins.borrow(cs).take())
+    }
+
+    /// Stores the value before the seen flag, so whoever sees the flag also sees a real pulse.
+    fn store_steering(&self, value: u16) {
+        self.steering
+            .store(value, core::sync::atomic::Ordering::Release);
+        self.steering_seen
+            .store(true, core::sync::atomic::Ordering::Release)
+    }
+
+    fn steering(&self) -> u16 {
+        self.steering.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn steering_checked(&self) -> Option<u16> {
+        self.steering_seen
+            .load(core::sync::atomic::Ordering::Acquire)
+            .then(|| self.steering())
+    }
+
+    /// Stores the value before the seen flag, like `store_steering`.
+    fn store_throttle(&self, value: u16) {
+        self.throttle
+            .store(value, core::sync::atomic::Ordering::Release);
+        self.throttle_seen
+            .store(true, core::sync::atomic::Ordering::Release)
+    }
+
+    fn throttle(&self) -> u16 {
+        self.throttle.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn throttle_checked(&self) -> Option<u16> {
+        self.throttle_seen
+            .load(core::sync::atomic::Ordering::Acquire)
+            .then(|| self.throttle())
+    }
+
+    fn store_smoothed(&self, steering: Option<u16>, throttle: Option<(u16, u8, u8)>) {
+        if let Some(value) = steering {
+            self.steering_smoothed
+                .store(value, core::sync::atomic::Ordering::Release)
+        }
+        if let Some((value, brake, brake_inverted)) = throttle {
+            self.throttle_smoothed
+                .store(value, core::sync::atomic::Ordering::Release);
+            self.brake_intensity
+                .store(brake, core::sync::atomic::Ordering::Release);
+            self.brake_intensity_inverted
+                .store(brake_inverted, core::sync::atomic::Ordering::Release)
+        }
+    }
+
+    fn brake_intensity(&self, inverted: bool) -> u8 {
+        if inverted {
+            &self.brake_intensity_inverted
+        } else {
+            &self.brake_intensity
+        }
+        .load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn steering_smoothed(&self) -> u16 {
+        self.steering_smoothed
+            .load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn throttle_smoothed(&self) -> u16 {
+        self.throttle_smoothed
+            .load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn store_aux(&self, value: u16) {
+        self.aux.store(value, core::sync::atomic::Ordering::Release)
+    }
+
+    fn aux(&self) -> u16 {
+        self.aux.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn mark_signal_seen(&self) {
+        self.signal_seen
+            .store(true, core::sync::atomic::Ordering::Release)
+    }
+
+    fn signal_seen(&self) -> bool {
+        self.signal_seen.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn mark_armed(&self) {
+        self.armed
+            .store(true, core::sync::atomic::Ordering::Release)
+    }
+
+    fn armed(&self) -> bool {
+        self.armed.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Publishes one ISR run's edges, all stamped with `now`.
+    ///
+    /// The values go out through the atomics first, then the timestamps and
+    /// counters under a single critical section however many edges there were.
+    /// Taking the critical section from inside the ISR is safe: it nests
+    /// (restoring the interrupt state it found), and the main loop only holds
+    /// it with interrupts masked, so it can never be held by the code this ISR
+    /// preempted. It is also needed, as `Instant` is wider than a word and the
+    /// main loop must not see half of an update.
+    ///
+    /// The smoothed values are worked out inside the same critical section,
+    /// because a channel that was stale before this pulse restarts its average
+    /// at the pulse rather than blending in the value from before it was lost.
+    ///
+    /// Steering and throttle pulses outside `plausible_us` are dropped before
+    /// any of that, as if they had never arrived.
+    fn record(
+        &self,
+        edges: Edges,
+        now: Instant,
+        smoothing: &mut Smoothing,
+        plausible_us: &RangeInclusive<u16>,
+        neutral_us: &RangeInclusive<u16>,
+    ) {
+        // Read before this run marks it, so the first edge doesn't pair with the boot default
+        let first_update = !self.signal_seen();
+        // Kept from before the glitches are dropped, for the pulse history
+        let captured = edges;
+
+        let mut glitches = 0;
+        let mut plausible = |value: Option<u16>| {
+            let rejected = value.is_some_and(|value| {
+                !is_plausible(value, *plausible_us.start(), *plausible_us.end())
+            });
+            glitches += rejected as u32;
+            value.filter(|_| !rejected)
+        };
+        let edges = Edges {
+            steering: plausible(edges.steering),
+            throttle: plausible(edges.throttle),
+            ..edges
+        };
+
+        if let Some(value) = edges.steering {
+            self.store_steering(value);
+        }
+        if let Some(value) = edges.throttle {
+            self.store_throttle(value);
+            // Only a plausible pulse gets this far, so a glitch can't arm it
+            if neutral_us.contains(&value) {
+                self.mark_armed();
+            }
+        }
+        if let Some(value) = edges.aux {
+            self.store_aux(value);
+        }
+        if edges.update {
+            self.mark_signal_seen();
+        }
+
+        let (steering, throttle) = critical_section::with(|cs| {
+            let mut pair = self.timing.borrow(cs).borrow_mut();
+            let timeout = pair.watchdog_timeout;
+            let steering = edges.steering.map(|value| {
+                let restart = gap_exceeds(now, pair.last_steering, timeout);
+                pair.last_steering = now;
+                smoothing.steering.update(value, smoothing.shift, restart)
+            });
+            let throttle = edges.throttle.map(|value| {
+                let since = pair.last_throttle;
+                let restart = gap_exceeds(now, since, timeout);
+                pair.last_throttle = now;
+                let smoothed = smoothing.throttle.update(value, smoothing.shift, restart);
+                // A restarted average has no previous value to compare against.
+                // Both directions are kept, so inverting the channel stays out of here.
+                let (brake, brake_inverted) = if restart {
+                    (0, 0)
+                } else {
+                    let previous = self.throttle_smoothed();
+                    (
+                        brake_intensity(previous, smoothed, now - since),
+                        brake_intensity(smoothed, previous, now - since),
+                    )
+                };
+                (smoothed, brake, brake_inverted)
+            });
+            if edges.aux.is_some() {
+                pair.last_aux = now;
+            }
+            if edges.update {
+                pair.prev_update = (!first_update).then_some(pair.last_update);
+                pair.last_update = now;
+            }
+
+            let mut history = self.history.borrow(cs).borrow_mut();
+            if let Some(value) = captured.steering {
+                history.steering.push(value);
+            }
+            if let Some(value) = captured.throttle {
+                history.throttle.push(value);
+            }
+
+            // After the glitch filter, so an outlier can't stretch them
+            let mut extremes = self.extremes.borrow(cs).borrow_mut();
+            extremes.steering.include(edges.steering);
+            extremes.throttle.include(edges.throttle);
+
+            if let Some(ranges) = self.calibration.borrow(cs).borrow_mut().as_mut() {
+                ranges.steering.include(edges.steering);
+                ranges.throttle.include(edges.throttle);
+            }
+
+            let mut diagnostics = self.diagnostics.borrow(cs).borrow_mut();
+            diagnostics.glitches = diagnostics.glitches.wrapping_add(glitches);
+            if edges.update {
+                diagnostics.frames = diagnostics.frames.wrapping_add(1);
+            }
+
+            (steering, throttle)
+        });
+        self.store_smoothed(steering, throttle);
+    }
+
+    fn update_rate_hz(&self) -> Option<u16> {
+        critical_section::with(|cs| {
+            let pair = self.timing.borrow(cs).borrow();
+            if pair.is_stale(pair.last_update) {
+                return None;
+            }
+            update_rate_hz(pair.last_update - pair.prev_update?)
+        })
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        critical_section::with(|cs| *self.diagnostics.borrow(cs).borrow())
+    }
+
+    /// Copies one channel's recent pulses out under the lock. At most
+    /// `PULSE_HISTORY` words are copied, so the ISR is never held up for long.
+    fn recent(&self, out: &mut [u16], channel: fn(&PulseHistory) -> &PulseRing) -> usize {
+        critical_section::with(|cs| channel(&self.history.borrow(cs).borrow()).copy_to(out))
+    }
+
+    #[cfg(not(feature = "rtic"))]
+    fn store_link_stats(&self, stats: LinkStats) {
+        critical_section::with(|cs| {
+            self.link_stats.borrow(cs).replace(Some(stats));
+        });
+    }
+
+    fn link_stats(&self) -> Option<LinkStats> {
+        critical_section::with(|cs| *self.link_stats.borrow(cs).borrow())
+    }
+
+    fn begin_calibration(&self) {
+        critical_section::with(|cs| {
+            self.calibration
+                .borrow(cs)
+                .replace(Some(CalibrationRanges::default()));
+        });
+    }
+
+    fn end_calibration(&self) -> Option<CalibrationRanges> {
+        critical_section::with(|cs| self.calibration.borrow(cs).take())
+    }
+
+    fn store_wire_command(&self, command: WireCommand) {
+        critical_section::with(|cs| {
+            self.wire_command.borrow(cs).replace(Some(command));
+        });
+    }
+
+    fn take_wire_command(&self) -> Option<WireCommand> {
+        critical_section::with(|cs| self.wire_command.borrow(cs).take())
+    }
+
+    fn extremes(&self) -> CalibrationRanges {
+        critical_section::with(|cs| *self.extremes.borrow(cs).borrow())
+    }
+
+    fn reset_extremes(&self) {
+        critical_section::with(|cs| {
+            self.extremes
+                .borrow(cs)
+                .replace(CalibrationRanges::default());
+        });
+    }
+
+    fn reset_diagnostics(&self) {
+        critical_section::with(|cs| {
+            self.diagnostics.borrow(cs).replace(Diagnostics::default());
+        });
+    }
+
+    fn has_watchdog_expired(&self) -> bool {
+        self.timing.has_watchdog_expired()
+    }
+
+    /// The aux pulse, or `None` if it is out of range or has stopped arriving.
+    fn valid_aux(&self) -> Option<u16> {
+        let aux = self.aux();
+        let stale = critical_section::with(|cs| {
+            let pair = self.timing.borrow(cs).borrow();
+            pair.is_stale(pair.last_aux)
+        });
+
+        if stale || !VALID_PULSE_US.contains(&aux) {
+            None
+        } else {
+            Some(aux)
+        }
+    }
+
+    /// Steering, throttle, aux and whether the watchdog has expired, read
+    /// under one critical section so the ISR can't publish between them.
+    ///
+    /// Only the loads and the staleness checks happen inside, so the receiver
+    /// interrupts are held off for no longer than a timer read or two.
+    fn raw_snapshot(&self) -> (Option<u16>, Option<u16>, Option<u16>, bool) {
+        critical_section::with(|cs| {
+            let pair = self.timing.borrow(cs).borrow();
+            let aux = Some(self.aux())
+                .filter(|aux| !pair.is_stale(pair.last_aux) && VALID_PULSE_US.contains(aux));
+            (
+                self.steering_checked(),
+                self.throttle_checked(),
+                aux,
+                pair.is_stale(pair.last_update),
+            )
+        })
+    }
+
+    fn channel_faults(&self) -> ChannelFaults {
+        let steering_valid = VALID_PULSE_US.contains(&self.steering());
+        let throttle_valid = VALID_PULSE_US.contains(&self.throttle());
+
+        critical_section::with(|cs| {
+            let pair = self.timing.borrow(cs).borrow();
+            ChannelFaults {
+                steering: !steering_valid || pair.is_stale(pair.last_steering),
+                throttle: !throttle_valid || pair.is_stale(pair.last_throttle),
+            }
+        })
+    }
+}
+
+static SHARED: SharedState = SharedState::new();
+
+/// The interrupt half of the receiver: the capture pins and PWM slices.
+///
+/// The default build hands this to the `IO_IRQ_BANK0` handler in this module.
+/// Frameworks that own the vector table (the `rtic` feature) keep it as a task
+/// resource and call [`ReceiverIrq::service`] from their own `IO_IRQ_BANK0` task.
+pub struct ReceiverIrq {
+    globals: Globals,
+    timer: Timer,
+    steering_capture: Capture,
+    throttle_capture: Capture,
+    aux_capture: Capture,
+    smoothing: Smoothing,
+    plausible_us: RangeInclusive<u16>,
+    neutral_us: RangeInclusive<u16>,
+    /// The system clock feeding the slices, for converting their counts to µs.
+    system_hz: u32,
+    /// `None` unless `ReceiverConfig::wire_config` is set.
+    wire: Option<WireDecoder>,
+}
+
+impl ReceiverIrq {
+    /// Handles any pending receiver edges. Call this from `IO_IRQ_BANK0`.
+    ///
+    /// Edges that arrive together are all handled in one run and share one
+    /// timestamp, read once on entry. The spread between them is a few µs,
+    /// which is noise next to the watchdog timeout.
+    pub fn service(&mut self) {
+        let globals = &mut self.globals;
+        let now = self.timer.get_counter();
+        let mut edges = Edges::default();
+        // Everything past the capture works in µs, whatever the tick length
+        let system_hz = self.system_hz;
+        let micros =
+            |count| ticks_to_micros(count, system_hz, PWM_DIVIDER).min(u16::MAX as u32) as u16;
+
         if globals.steering_pin.interrupt_status(EdgeLow) {
-            let count = globals.steering_pwm.get_counter();
-            globals.steering_pwm.set_counter(0);
+            let count = micros(globals.steering_pwm.get_counter());
+            let sample = self.steering_capture.on_falling_edge(count, now);
+            if sample.is_some() {
+                globals.steering_pwm.set_counter(0);
+            }
             globals.steering_pin.clear_interrupt(EdgeLow);
-            STEERING.store(count, core::sync::atomic::Ordering::Release)
+            edges.steering = sample;
         }
 
         if globals.throttle_pin.interrupt_status(EdgeLow) {
-            let count = globals.throttle_pwm.get_counter();
-            globals.throttle_pwm.set_counter(0);
+            let count = micros(globals.throttle_pwm.get_counter());
+            let sample = self.throttle_capture.on_falling_edge(count, now);
+            if sample.is_some() {
+                globals.throttle_pwm.set_counter(0);
+            }
             globals.throttle_pin.clear_interrupt(EdgeLow);
-            THROTTLE.store(count, core::sync::atomic::Ordering::Release)
+            edges.throttle = sample;
         }
 
-        if globals.update_pin.interrupt_status(EdgeLow) {
-            critical_section::with(|cs| {
-                let mut pair = LAST_UPDATE.borrow(cs).borrow_mut();
-                if let Some(timer) = &pair.timer {
-                    pair.last_update = timer.get_counter();
-                }
-            });
+        if globals.aux_pin.interrupt_status(EdgeLow) {
+            let count = micros(globals.aux_pwm.get_counter());
+            let sample = self.aux_capture.on_falling_edge(count, now);
+            if sample.is_some() {
+                globals.aux_pwm.set_counter(0);
+            }
+            globals.aux_pin.clear_interrupt(EdgeLow);
+            edges.aux = sample;
+        }
 
+        if globals.update_pin.interrupt_status(EdgeLow) {
+            // The edges of a message aren't frames, so they don't feed the watchdog
+            match self.wire.as_mut().map(|wire| wire.on_edge(now)) {
+                None | Some(WireEdge::Frame) => edges.update = true,
+                Some(WireEdge::Message) => {}
+                Some(WireEdge::Command(command)) => SHARED.store_wire_command(command),
+            }
             globals.update_pin.clear_interrupt(EdgeLow);
         }
+
+        SHARED.record(
+            edges,
+            now,
+            &mut self.smoothing,
+            &self.plausible_us,
+            &self.neutral_us,
+        );
+    }
+}
+
+#[cfg(not(feature = "rtic"))]
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    static mut GLOBALS: Option<ReceiverIrq> = None;
+    static mut PPM: Option<PpmIrq> = None;
+
+    if GLOBALS.is_none() && PPM.is_none() {
+        *GLOBALS = SHARED.take_pins();
+        *PPM = ppm::take_irq();
+    }
+
+    if let Some(globals) = GLOBALS {
+        globals.service();
+    } else if let Some(ppm) = PPM {
+        ppm.service();
+    } else {
+        // Without the pins the edge flags can't be cleared, so returning would
+        // re-enter immediately. `initialize_receiver` and
+        // `initialize_ppm_receiver` only unmask after the handoff, so this
+        // means something else unmasked early. Mask ourselves until one of
+        // them unmasks again.
+        pac::NVIC::mask(pac::Interrupt::IO_IRQ_BANK0);
     }
 }
 
-pub struct Receiver {}
+pub struct Receiver {
+    config: ReceiverConfig,
+    combined_fault_policy: CombinedFaultPolicy,
+    steering_endpoints: Endpoints,
+    throttle_endpoints: Endpoints,
+    system_hz: u32,
+    link_thresholds: LinkThresholds,
+    /// Updated whenever it is read, so it follows the latest link statistics.
+    link_health: Cell<LinkHealth>,
+}
 
 impl Receiver {
+    fn new(config: ReceiverConfig) -> Self {
+        Self {
+            config,
+            combined_fault_policy: CombinedFaultPolicy::Failsafe,
+            steering_endpoints: config.endpoints(),
+            throttle_endpoints: config.endpoints(),
+            system_hz: NOMINAL_SYSTEM_HZ,
+            link_thresholds: LinkThresholds::DISABLED,
+            link_health: Cell::new(LinkHealth::Good),
+        }
+    }
+
+    /// Converts a raw PWM capture count to µs, using the divider and system clock it was set up with.
+    ///
+    /// Only the PWM input counts in ticks. The other inputs already report
+    /// µs, and for them this assumes the nominal 125 MHz clock.
+    pub fn ticks_to_micros(&self, raw: u16) -> u32 {
+        ticks_to_micros(raw, self.system_hz, PWM_DIVIDER)
+    }
+
+    /// Starts recording the shortest and longest steering and throttle pulses.
+    ///
+    /// The interrupt path tracks them as pulses arrive, so nothing is missed
+    /// between calls. Move both sticks to their ends, then call
+    /// [`Receiver::end_calibration`]. Starting again throws away a run in progress.
+    pub fn begin_calibration(&self) {
+        SHARED.begin_calibration();
+    }
+
+    /// The shortest and longest steering pulses, in µs, captured since boot
+    /// or the last [`Receiver::reset_extremes`].
+    ///
+    /// A lighter way to pick `ReceiverConfig` endpoints than a calibration
+    /// run: move the stick to both ends and read these. Pulses the glitch
+    /// filter drops, or outside the valid range, never count. `(0, 0)` until
+    /// the first pulse.
+    pub fn steering_extremes(&self) -> (u16, u16) {
+        SHARED.extremes().steering.0.unwrap_or((0, 0))
+    }
+
+    /// The same as [`Receiver::steering_extremes`], for the throttle.
+    pub fn throttle_extremes(&self) -> (u16, u16) {
+        SHARED.extremes().throttle.0.unwrap_or((0, 0))
+    }
+
+    /// Forgets the extremes, so the next measurement starts afresh.
+    #[cfg_attr(not(all(feature = "cli", not(feature = "rtic"))), allow(dead_code))] // Only the console's reset calls it
+    pub fn reset_extremes(&self) {
+        SHARED.reset_extremes();
+    }
+
+    /// Stops recording and uses what was seen as the endpoints for the percentages.
+    ///
+    /// A channel only takes the recorded range if it moved well clear of
+    /// neutral each way. Otherwise, say if it was stuck at 0 or never moved, it
+    /// goes back to the endpoints in `ReceiverConfig`. Neutral always stays as
+    /// configured. Without a run in progress this changes nothing.
+    pub fn end_calibration(&mut self) -> CalibrationResult {
+        let Some(ranges) = SHARED.end_calibration() else {
+            return CalibrationResult {
+                steering: false,
+                throttle: false,
+            };
+        };
+
+        let defaults = self.config.endpoints();
+        let steering = ranges.steering.endpoints(self.config.neutral_us);
+        let throttle = ranges.throttle.endpoints(self.config.neutral_us);
+        self.steering_endpoints = steering.unwrap_or(defaults);
+        self.throttle_endpoints = throttle.unwrap_or(defaults);
+        CalibrationResult {
+            steering: steering.is_some(),
+            throttle: throttle.is_some(),
+        }
+    }
+
+    /// Replaces the endpoints percentages are measured against, e.g. with ones saved by an earlier calibration.
+    pub fn set_endpoints(&mut self, steering: Endpoints, throttle: Endpoints) {
+        self.steering_endpoints = steering;
+        self.throttle_endpoints = throttle;
+    }
+
+    /// The endpoints steering percentages are currently measured against.
+    pub fn steering_endpoints(&self) -> Endpoints {
+        self.steering_endpoints
+    }
+
+    /// The endpoints throttle percentages are currently measured against.
+    pub fn throttle_endpoints(&self) -> Endpoints {
+        self.throttle_endpoints
+    }
+
     pub fn has_watchdog_expired(&self) -> bool {
-        LAST_UPDATE.has_watchdog_expired()
+        SHARED.has_watchdog_expired()
+    }
+
+    /// Steering and throttle, raw and scaled, with aux and the watchdog, all from the same instant.
+    ///
+    /// The shared values are read under a single short critical section, and
+    /// the scaling is done after it ends, so logging or telemetry can take a
+    /// snapshot every update without noticeably delaying the receiver interrupts.
+    pub fn snapshot(&self) -> Snapshot {
+        let (steering, throttle, aux, watchdog_expired) = SHARED.raw_snapshot();
+        Snapshot {
+            steering: ChannelReading {
+                raw: steering,
+                percent: steering.and_then(|pulse| self.pulse_steering_percent(pulse)),
+            },
+            throttle: ChannelReading {
+                raw: throttle,
+                percent: throttle.and_then(|pulse| {
+                    pulse_percent(pulse, &self.throttle_endpoints, self.config.invert_throttle)
+                }),
+            },
+            aux,
+            watchdog_expired,
+        }
+    }
+
+    /// Whether a frame has arrived at any point since boot.
+    pub fn has_seen_signal(&self) -> bool {
+        SHARED.signal_seen()
+    }
+
+    /// Whether the throttle has read neutral at any point since boot.
+    ///
+    /// Set from the interrupt path on the first throttle pulse inside the
+    /// neutral band around `ReceiverConfig::neutral_us`, and never cleared, not
+    /// even by a signal loss. Until then the transmitter may be off or its
+    /// throttle held open, so nothing that implies motion should follow it.
+    /// Unlike the arming gesture in `Arming`, this needs no action from the
+    /// driver beyond a centred throttle.
+    pub fn is_armed(&self) -> bool {
+        SHARED.armed()
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        SHARED.diagnostics()
+    }
+
+    /// Copies up to the last `PULSE_HISTORY` steering pulses into `out`,
+    /// oldest first, and returns how many there were.
+    ///
+    /// Every captured pulse is kept, including those dropped as glitches, so
+    /// this shows what led up to a rejection. A shorter `out` gets the most
+    /// recent pulses that fit.
+    #[cfg_attr(not(all(feature = "cli", not(feature = "rtic"))), allow(dead_code))] // Only the console's dump reads it out
+    pub fn recent_steering(&self, out: &mut [u16]) -> usize {
+        SHARED.recent(out, |history| &history.steering)
+    }
+
+    /// The same as [`Receiver::recent_steering`], for the throttle.
+    #[cfg_attr(not(all(feature = "cli", not(feature = "rtic"))), allow(dead_code))] // Only the console's dump reads it out
+    pub fn recent_throttle(&self, out: &mut [u16]) -> usize {
+        SHARED.recent(out, |history| &history.throttle)
+    }
+
+    /// How many steering and throttle pulses have been dropped as glitches.
+    ///
+    /// The same counter as [`Diagnostics::glitches`], so it wraps and
+    /// [`Receiver::reset_diagnostics`] clears it.
+    pub fn glitch_count(&self) -> u32 {
+        self.diagnostics().glitches
+    }
+
+    /// How fast frames are arriving, from the gap between the last two update edges.
+    ///
+    /// `None` before the second frame and while the watchdog has expired. This
+    /// is one interval, so it jitters with the receiver; [`FrameRateMeter`]
+    /// averages over longer.
+    pub fn update_rate_hz(&self) -> Option<u16> {
+        SHARED.update_rate_hz()
+    }
+
+    /// The latest link statistics from a receiver that reports them, such as a CRSF one.
+    ///
+    /// `None` if none have arrived, and while the watchdog has expired, so a
+    /// link that has gone quiet doesn't keep showing its last good reading.
+    pub fn link_stats(&self) -> Option<LinkStats> {
+        if self.has_watchdog_expired() {
+            return None;
+        }
+        SHARED.link_stats()
+    }
+
+    /// Takes the last config command sent over the update line, if one has
+    /// come in since the last call.
+    ///
+    /// Only a PWM receiver with `ReceiverConfig::wire_config` set has an update
+    /// line to decode, so anything else always gets `None`. A command that
+    /// isn't taken before the next one arrives is replaced by it.
+    pub fn take_wire_command(&mut self) -> Option<WireCommand> {
+        SHARED.take_wire_command()
+    }
+
+    /// The latest [`LinkStats::link_quality`], as a percentage. `None` whenever
+    /// [`Receiver::link_stats`] is.
+    pub fn link_quality(&self) -> Option<u8> {
+        self.link_stats().map(|stats| stats.link_quality)
+    }
+
+    /// Sets where link quality turns into a warning and into failsafe.
+    ///
+    /// Until this is called link quality is ignored, as it is for inputs that
+    /// don't report it.
+    pub fn set_link_thresholds(&mut self, thresholds: LinkThresholds) {
+        self.link_thresholds = thresholds;
+    }
+
+    /// How the link is doing by the latest link quality and the configured [`LinkThresholds`].
+    ///
+    /// Without a link quality reading this is [`LinkHealth::Good`], leaving a
+    /// silent link to the frame watchdog.
+    pub fn link_health(&self) -> LinkHealth {
+        let health = match self.link_quality() {
+            Some(quality) => {
+                next_link_health(self.link_health.get(), quality, self.link_thresholds)
+            }
+            None => LinkHealth::Good,
+        };
+        if health != self.link_health.replace(health) {
+            defmt::info!("Link {}", health);
+        }
+        health
+    }
+
+    /// Zeroes every diagnostic counter at once, e.g. at the start of a test run.
+    ///
+    /// Runs in a single critical section, so each ISR increment lands either
+    /// before the reset (and is cleared) or after it (and is kept).
+    #[cfg_attr(not(all(feature = "cli", not(feature = "rtic"))), allow(dead_code))] // Only the console's reset calls it
+    pub fn reset_diagnostics(&self) {
+        SHARED.reset_diagnostics();
+    }
+
+    /// Changes the frame watchdog timeout, which also applies to per-channel staleness.
+    #[cfg_attr(not(all(feature = "cli", not(feature = "rtic"))), allow(dead_code))] // Only the console's watchdog command calls it
+    pub fn set_watchdog_timeout(&self, ms: u32) {
+        SHARED.set_watchdog_timeout(MillisDurationU64::millis(ms as u64));
+    }
+
+    pub fn set_combined_fault_policy(&mut self, policy: CombinedFaultPolicy) {
+        self.combined_fault_policy = policy;
+    }
+
+    pub fn channel_faults(&self) -> ChannelFaults {
+        SHARED.channel_faults()
+    }
+
+    /// Whether outputs should be in their failsafe state.
+    ///
+    /// An expired frame watchdog always means failsafe, whatever the policy.
+    /// While frames are still arriving, [`CombinedFaultPolicy::Failsafe`] also
+    /// enters failsafe when steering and throttle are faulted at the same time.
+    /// A single faulted channel never does on its own. A link quality below
+    /// [`LinkThresholds::failsafe_below`] always does, whatever the policy.
+    pub fn in_failsafe(&self) -> bool {
+        if self.has_watchdog_expired() || self.link_health() == LinkHealth::Lost {
+            return true;
+        }
+
+        match self.combined_fault_policy {
+            CombinedFaultPolicy::Ignore => false,
+            CombinedFaultPolicy::Failsafe => {
+                let faults = self.channel_faults();
+                faults.steering && faults.throttle
+            }
+        }
     }
 
+    /// The last steering pulse in µs, or 0 before the first one. See [`Receiver::steering_checked`].
+    #[allow(dead_code)] // Telemetry reads it through `snapshot` now, but it stays for callers that only want steering
     pub fn steering(&self) -> u16 {
-        STEERING.load(core::sync::atomic::Ordering::Acquire)
+        SHARED.steering()
+    }
+
+    /// Steering in µs, averaged over recent pulses as set by `ReceiverConfig::smoothing_shift`.
+    ///
+    /// Reads 0 before the first pulse, like [`Receiver::steering`]. After a
+    /// signal loss the average restarts at the first new pulse.
+    pub fn steering_smoothed(&self) -> u16 {
+        SHARED.steering_smoothed()
+    }
+
+    /// Throttle in µs, smoothed the same way as [`Receiver::steering_smoothed`].
+    pub fn throttle_smoothed(&self) -> u16 {
+        SHARED.throttle_smoothed()
+    }
+
+    /// [`Receiver::throttle_smoothed`] as -100..=100 %, or `None` before the first pulse.
+    #[cfg(feature = "lights")] // Only the tach pulse uses it
+    pub fn try_throttle_smoothed_percent(&self) -> Option<i16> {
+        pulse_percent(
+            self.throttle_smoothed(),
+            &self.throttle_endpoints,
+            self.config.invert_throttle,
+        )
+    }
+
+    /// How hard the throttle is being backed off, from 0 to 255.
+    ///
+    /// Worked out from how fast the smoothed throttle fell between its last two
+    /// pulses (rose, with `invert_throttle`), so it is 0 when the throttle is
+    /// being opened or held steady, on the first pulse after a signal loss, and
+    /// while the throttle channel is faulted.
+    pub fn brake_intensity(&self) -> u8 {
+        if self.channel_faults().throttle {
+            0
+        } else {
+            SHARED.brake_intensity(self.config.invert_throttle)
+        }
+    }
+
+    /// The last steering pulse in µs, or `None` until one has been captured.
+    pub fn steering_checked(&self) -> Option<u16> {
+        SHARED.steering_checked()
+    }
+
+    /// The last throttle pulse in µs, or `None` until one has been captured.
+    pub fn throttle_checked(&self) -> Option<u16> {
+        SHARED.throttle_checked()
+    }
+
+    /// The aux channel's pulse in µs, or `None` if it is unplugged or out of range.
+    ///
+    /// Aux is not a control channel, so it never feeds into [`ChannelFaults`]
+    /// or failsafe.
+    pub fn aux(&self) -> Option<u16> {
+        SHARED.valid_aux()
+    }
+
+    /// The aux channel read as a 3-position switch, using the same endpoints as the other channels.
+    ///
+    /// This is the instantaneous reading, which can catch the middle position
+    /// as the switch moves between its ends. Pass it through a
+    /// [`SwitchDebouncer`] before acting on it.
+    pub fn aux_switch_position(&self) -> Option<SwitchPos> {
+        self.aux()
+            .and_then(|pulse| switch_position(pulse, &self.config))
     }
 
     pub fn throttle(&self) -> u16 {
-        THROTTLE.load(core::sync::atomic::Ordering::Acquire)
+        SHARED.throttle()
+    }
+
+    /// Steering as -100..=100 %, or `None` before the first pulse.
+    pub fn try_steering_percent(&self) -> Option<i16> {
+        self.steering_checked()
+            .and_then(|pulse| self.pulse_steering_percent(pulse))
+    }
+
+    /// A steering pulse as a percentage, through the endpoints, inversion and dead band.
+    fn pulse_steering_percent(&self, pulse: u16) -> Option<i16> {
+        pulse_percent(pulse, &self.steering_endpoints, self.config.invert_steering)
+            .map(|percent| apply_dead_band(percent, self.config.steering_dead_band_percent))
+    }
+
+    /// Throttle as -100..=100 %, or `None` before the first pulse.
+    pub fn try_throttle_percent(&self) -> Option<i16> {
+        self.throttle_checked().and_then(|pulse| {
+            pulse_percent(pulse, &self.throttle_endpoints, self.config.invert_throttle)
+        })
     }
+
+    pub fn throttle_state(&self) -> ThrottleState {
+        throttle_state(self.throttle(), &self.config)
+    }
+
+    /// Steering as -100..=100 %. Reads as neutral (0) before the first pulse.
+    pub fn steering_percent(&self) -> i16 {
+        self.try_steering_percent().unwrap_or(0)
+    }
+
+    /// Throttle as -100..=100 %. Reads as neutral (0) before the first pulse.
+    pub fn throttle_percent(&self) -> i16 {
+        self.try_throttle_percent().unwrap_or(0)
+    }
+
+    /// Throttle and steering mixed into `(left, right)` percentages, as for a
+    /// tank or differential drive. See `mix_differential` for the clamping.
+    ///
+    /// Each channel reads as neutral before its first pulse, as with
+    /// [`Receiver::throttle_percent`] and [`Receiver::steering_percent`].
+    pub fn differential(&self) -> (i16, i16) {
+        mix_differential(self.throttle_percent(), self.steering_percent())
+    }
+}
+
+/// The input pins the receiver captures from, as they come out of `Pins::new`.
+///
+/// Steering, throttle and aux are timed by PWM slices 1, 2 and 3, so each has
+/// to sit on the B input of its slice. Only two pins feed each, which is why
+/// they are all odd GPIOs:
+///
+/// | Channel  | Slice | Pins                            |
+/// |----------|-------|---------------------------------|
+/// | steering | 1     | GP3 (default), GP19             |
+/// | throttle | 2     | GP5 (default), GP21             |
+/// | aux      | 3     | GP7 (default), GP23 (not on a Pico's header) |
+///
+/// The update pin only needs an edge interrupt, so any free GPIO will do; it
+/// defaults to GP4. The types default to the first column, and any other pin
+/// fails to build, as the HAL's `ValidPwmInputPin` bound on
+/// [`initialize_receiver_parts`] won't accept it. So GP19, GP21 and GP20
+/// (with aux left on GP7) is one alternative for boards that use GP3 to GP5
+/// for something else.
+pub struct ReceiverPins<S = Gpio3, T = Gpio5, A = Gpio7, U = Gpio4>
+where
+    S: PinId,
+    T: PinId,
+    A: PinId,
+    U: PinId,
+{
+    pub steering: Pin<S, FunctionNull, PullDown>,
+    pub throttle: Pin<T, FunctionNull, PullDown>,
+    pub update: Pin<U, FunctionNull, PullDown>,
+    pub aux: Pin<A, FunctionNull, PullDown>,
 }
 
-pub fn initialize_receiver(
-    timer: TIMER,
+/// Sets up the receiver and its built-in `IO_IRQ_BANK0` handler, then unmasks the interrupt.
+#[cfg(not(feature = "rtic"))]
+pub fn initialize_receiver<S, T, A, U>(
+    timer: Timer,
     resets: &mut RESETS,
-    clocks: &ClocksManager,
     pwm: PWM,
-    steering_pin: Pin<Gpio3, FunctionNull, PullDown>,
-    throttle_pin: Pin<Gpio5, FunctionNull, PullDown>,
-    update_pin: Pin<Gpio4, FunctionNull, PullDown>,
-) -> Receiver {
-    let timer = rp2040_hal::Timer::new(timer, resets, clocks);
+    config: ReceiverConfig,
+    capture_mode: CaptureMode,
+    pins: ReceiverPins<S, T, A, U>,
+    system_clock: HertzU32,
+) -> Receiver
+where
+    S: ValidPwmInputPin<Pwm1> + ValidFunction<FunctionSioInput>,
+    T: ValidPwmInputPin<Pwm2> + ValidFunction<FunctionSioInput>,
+    A: ValidPwmInputPin<Pwm3> + ValidFunction<FunctionSioInput>,
+    U: PinId + ValidFunction<FunctionSioInput>,
+{
+    let (receiver, irq) =
+        initialize_receiver_parts(timer, resets, pwm, config, capture_mode, pins, system_clock);
+
+    // Order matters. The pin edge interrupts are already enabled, so an edge
+    // may be pending in the NVIC by now. The handoff has to complete before
+    // unmasking, otherwise the first ISR run finds no pins and cannot clear
+    // the flag it was raised for.
+    SHARED.install_pins(irq);
+
+    #[allow(unsafe_code)] // We've computed that our interrupt enabling is safe
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+
+    receiver
+}
 
+/// Sets up the capture hardware without touching the interrupt controller.
+///
+/// The caller owns the returned [`ReceiverIrq`] and must call
+/// [`ReceiverIrq::service`] from `IO_IRQ_BANK0`, and unmask that interrupt
+/// once it is ready to do so.
+pub fn initialize_receiver_parts<S, T, A, U>(
+    timer: Timer,
+    resets: &mut RESETS,
+    pwm: PWM,
+    config: ReceiverConfig,
+    capture_mode: CaptureMode,
+    pins: ReceiverPins<S, T, A, U>,
+    system_clock: HertzU32,
+) -> (Receiver, ReceiverIrq)
+where
+    S: ValidPwmInputPin<Pwm1> + ValidFunction<FunctionSioInput>,
+    T: ValidPwmInputPin<Pwm2> + ValidFunction<FunctionSioInput>,
+    A: ValidPwmInputPin<Pwm3> + ValidFunction<FunctionSioInput>,
+    U: PinId + ValidFunction<FunctionSioInput>,
+{
     let slices = Slices::new(pwm, resets);
     let mut steering_pwm = slices.pwm1.into_mode::<InputHighRunning>();
-    steering_pwm.set_div_int(125);
+    steering_pwm.set_div_int(PWM_DIVIDER);
     #[allow(unsafe_code)] // Workaround to HAL issue. Safe because we only read from here
     let steering_pin = unsafe {
         steering_pwm
-            .input_from(steering_pin.into_floating_input())
+            .input_from(pins.steering.into_floating_input())
             .into_unchecked::<FunctionSioInput, PullNone>()
+            .into_dyn_pin()
     };
 
     let mut throttle_pwm = slices.pwm2.into_mode::<InputHighRunning>();
-    throttle_pwm.set_div_int(125);
+    throttle_pwm.set_div_int(PWM_DIVIDER);
     #[allow(unsafe_code)] // Workaround to HAL issue. Safe because we only read from here
     let throttle_pin = unsafe {
         throttle_pwm
-            .input_from(throttle_pin.into_floating_input())
+            .input_from(pins.throttle.into_floating_input())
+            .into_unchecked::<FunctionSioInput, PullNone>()
+            .into_dyn_pin()
+    };
+
+    let mut aux_pwm = slices.pwm3.into_mode::<InputHighRunning>();
+    aux_pwm.set_div_int(PWM_DIVIDER);
+    #[allow(unsafe_code)] // Workaround to HAL issue. Safe because we only read from here
+    let aux_pin = unsafe {
+        aux_pwm
+            .input_from(pins.aux.into_floating_input())
             .into_unchecked::<FunctionSioInput, PullNone>()
+            .into_dyn_pin()
     };
 
-    let update_pin = update_pin.into_floating_input();
+    let update_pin = pins.update.into_floating_input().into_dyn_pin();
 
     steering_pwm.enable();
     throttle_pwm.enable();
+    aux_pwm.enable();
 
     steering_pin.set_interrupt_enabled(EdgeLow, true);
     throttle_pin.set_interrupt_enabled(EdgeLow, true);
+    aux_pin.set_interrupt_enabled(EdgeLow, true);
     update_pin.set_interrupt_enabled(EdgeLow, true);
 
-    critical_section::with(|cs| {
-        LAST_UPDATE.borrow(cs).replace(TimerPair {
-            timer: Some(timer),
-            last_update: Instant::from_ticks(0),
-        });
+    SHARED.install_timer(timer, config.watchdog_timeout);
+    let window_start = timer.get_counter();
 
-        GLOBAL_PINS.borrow(cs).replace(Some(Globals {
-            steering_pin,
-            steering_pwm,
-            throttle_pin,
-            throttle_pwm,
-            update_pin,
-        }))
-    });
-
-    #[allow(unsafe_code)] // We've computed that our interrupt enabling is safe
-    unsafe {
-        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    let system_hz = system_clock.to_Hz();
+    let receiver = Receiver {
+        system_hz,
+        ..Receiver::new(config)
+    };
+    if system_hz != NOMINAL_SYSTEM_HZ {
+        defmt::warn!(
+            "System clock {} Hz: 1000 capture ticks are {} us",
+            system_hz,
+            receiver.ticks_to_micros(1000)
+        );
     }
 
-    Receiver {}
+    (
+        receiver,
+        ReceiverIrq {
+            globals: Globals {
+                steering_pin,
+                steering_pwm,
+                throttle_pin,
+                throttle_pwm,
+                update_pin,
+                aux_pin,
+                aux_pwm,
+            },
+            timer,
+            steering_capture: Capture {
+                mode: capture_mode,
+                window_start,
+            },
+            throttle_capture: Capture {
+                mode: capture_mode,
+                window_start,
+            },
+            aux_capture: Capture {
+                mode: capture_mode,
+                window_start,
+            },
+            smoothing: Smoothing::new(config.smoothing_shift),
+            plausible_us: config.plausible_us(),
+            neutral_us: config.neutral_band(),
+            system_hz,
+            wire: config.wire_config.then(WireDecoder::new),
+        },
+    )
 }
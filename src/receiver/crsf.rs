@@ -0,0 +1,270 @@
+use core::{cell::RefCell, ops::RangeInclusive};
+
+use critical_section::Mutex;
+use fugit::HertzU32;
+use rp2040_hal::{
+    gpio::{
+        bank0::{Gpio4, Gpio5},
+        FunctionNull, FunctionUart, Pin, PullDown,
+    },
+    pac::{self, interrupt, RESETS, UART1},
+    uart::{DataBits, Enabled, StopBits, UartConfig, UartPeripheral},
+    Timer,
+};
+
+use super::{
+    sbus::{channel_us, unpack_channels},
+    Edges, LinkStats, Receiver, ReceiverConfig, Smoothing, SHARED,
+};
+
+/// CRSF receivers talk to the flight controller at 420 kbaud, 8N1.
+const CRSF_BAUD_HZ: u32 = 420_000;
+
+/// The longest frame allowed, counting the address, length and CRC bytes.
+const MAX_FRAME_LEN: usize = 64;
+
+/// Frames start with the address of the device they are for. Receivers
+/// address the flight controller, but some send the broadcast or receiver
+/// address instead, so all three are accepted.
+const ADDRESSES: [u8; 3] = [0xC8, 0x00, 0xEC];
+
+const FRAME_TYPE_LINK_STATISTICS: u8 = 0x14;
+const FRAME_TYPE_RC_CHANNELS_PACKED: u8 = 0x16;
+
+/// The generator polynomial of the CRSF CRC8, which covers the type and payload bytes.
+const CRC8_POLY: u8 = 0xD5;
+
+/// CRSF channels, counted from 0, that stand in for the PWM steering, throttle and aux inputs.
+const STEERING_CHANNEL: usize = 0;
+const THROTTLE_CHANNEL: usize = 1;
+const AUX_CHANNEL: usize = 2;
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ CRC8_POLY
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A CRSF frame this firmware understands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrsfFrame {
+    /// The 16 proportional channels, 11 bits each.
+    Channels([u16; 16]),
+    LinkStats(LinkStats),
+}
+
+/// Decodes a frame's type and payload, once its CRC has checked out.
+///
+/// Frame types other than channels and link statistics, and frames too short
+/// for their type, are ignored.
+fn decode(frame_type: u8, payload: &[u8]) -> Option<CrsfFrame> {
+    match frame_type {
+        FRAME_TYPE_RC_CHANNELS_PACKED => {
+            let packed = payload.get(..22)?.try_into().ok()?;
+            Some(CrsfFrame::Channels(unpack_channels(packed)))
+        }
+        FRAME_TYPE_LINK_STATISTICS => {
+            // Uplink RSSI for antennas 1 and 2 (as positive dBm), uplink link
+            // quality, uplink SNR, then which antenna is active
+            let stats = payload.get(..5)?;
+            let rssi = if stats[4] == 0 { stats[0] } else { stats[1] };
+            Some(CrsfFrame::LinkStats(LinkStats {
+                rssi_dbm: -(rssi as i16),
+                link_quality: stats[2],
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Reassembles CRSF frames from the UART's byte stream.
+pub struct CrsfParser {
+    buffer: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl CrsfParser {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Feeds one byte, and returns the frame it completes, if any.
+    ///
+    /// Bytes are dropped until an address byte arrives. A length that can't
+    /// be right, or a CRC that doesn't match, throws the frame away and waits
+    /// for the next address byte.
+    pub fn push(&mut self, byte: u8) -> Option<CrsfFrame> {
+        if self.len == 0 && !ADDRESSES.contains(&byte) {
+            return None;
+        }
+        if self.len == 1 && !(2..=(MAX_FRAME_LEN - 2) as u8).contains(&byte) {
+            self.len = 0;
+            return None;
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        // The length byte counts the type, payload and CRC
+        if self.len < 2 || self.len < self.buffer[1] as usize + 2 {
+            return None;
+        }
+        let frame = &self.buffer[..self.len];
+        self.len = 0;
+
+        let (crc, checked) = frame[2..].split_last()?;
+        if crc8(checked) != *crc {
+            return None;
+        }
+        decode(checked[0], &checked[1..])
+    }
+
+    /// Drops any partial frame, e.g. after the UART reports lost bytes.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+}
+
+type CrsfUart = UartPeripheral<
+    Enabled,
+    UART1,
+    (
+        Pin<Gpio4, FunctionUart, PullDown>,
+        Pin<Gpio5, FunctionUart, PullDown>,
+    ),
+>;
+
+/// The interrupt half of a CRSF receiver: the UART and the frame parser.
+pub struct CrsfIrq {
+    uart: CrsfUart,
+    timer: Timer,
+    parser: CrsfParser,
+    smoothing: Smoothing,
+    plausible_us: RangeInclusive<u16>,
+    neutral_us: RangeInclusive<u16>,
+}
+
+impl CrsfIrq {
+    /// Drains the UART and publishes every complete frame. Call this from `UART1_IRQ`.
+    pub fn service(&mut self) {
+        let mut buffer = [0u8; 32];
+        while self.uart.uart_is_readable() {
+            match self.uart.read_raw(&mut buffer) {
+                Ok(bytes) => {
+                    for &byte in bytes.iter() {
+                        if let Some(frame) = self.parser.push(byte) {
+                            self.publish(frame);
+                        }
+                    }
+                }
+                // Framing and overrun errors both mean bytes went missing
+                Err(_) => self.parser.reset(),
+            }
+        }
+    }
+
+    /// Hands a frame to the same shared state the PWM capture feeds.
+    ///
+    /// Only channel frames count as updates for the watchdog. ExpressLRS
+    /// stops sending them when the link drops, so a stale link goes to
+    /// failsafe exactly like a PWM receiver that stops pulsing.
+    fn publish(&mut self, frame: CrsfFrame) {
+        match frame {
+            CrsfFrame::Channels(channels) => {
+                let edges = Edges {
+                    steering: Some(channel_us(channels[STEERING_CHANNEL])),
+                    throttle: Some(channel_us(channels[THROTTLE_CHANNEL])),
+                    aux: Some(channel_us(channels[AUX_CHANNEL])),
+                    update: true,
+                };
+                SHARED.record(
+                    edges,
+                    self.timer.get_counter(),
+                    &mut self.smoothing,
+                    &self.plausible_us,
+                    &self.neutral_us,
+                );
+            }
+            CrsfFrame::LinkStats(stats) => SHARED.store_link_stats(stats),
+        }
+    }
+}
+
+static CRSF_IRQ: Mutex<RefCell<Option<CrsfIrq>>> = Mutex::new(RefCell::new(None));
+
+/// Sets up CRSF input on GP5 and its built-in `UART1_IRQ` handler, then unmasks the interrupt.
+///
+/// Wire the receiver's TX to GP5. GP4 is claimed as the UART's TX but
+/// nothing is sent back to the receiver yet, so it can be left unconnected.
+/// Otherwise this works like `initialize_sbus_receiver`.
+pub fn initialize_crsf_receiver(
+    timer: Timer,
+    resets: &mut RESETS,
+    uart: UART1,
+    tx: Pin<Gpio4, FunctionNull, PullDown>,
+    rx: Pin<Gpio5, FunctionNull, PullDown>,
+    config: ReceiverConfig,
+    peripheral_clock: HertzU32,
+) -> Receiver {
+    let pins = (
+        tx.into_function::<FunctionUart>(),
+        rx.into_function::<FunctionUart>(),
+    );
+
+    let mut uart = UartPeripheral::new(uart, pins, resets)
+        .enable(
+            UartConfig::new(
+                HertzU32::Hz(CRSF_BAUD_HZ),
+                DataBits::Eight,
+                None,
+                StopBits::One,
+            ),
+            peripheral_clock,
+        )
+        .unwrap();
+    uart.enable_rx_interrupt();
+
+    SHARED.install_timer(timer, config.watchdog_timeout);
+    critical_section::with(|cs| {
+        CRSF_IRQ.borrow(cs).replace(Some(CrsfIrq {
+            uart,
+            timer,
+            parser: CrsfParser::new(),
+            smoothing: Smoothing::new(config.smoothing_shift),
+            plausible_us: config.plausible_us(),
+            neutral_us: config.neutral_band(),
+        }));
+    });
+
+    // As with PWM, the handoff has to complete before the interrupt can run
+    #[allow(unsafe_code)] // We've computed that our interrupt enabling is safe
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::UART1_IRQ);
+    }
+
+    Receiver::new(config)
+}
+
+#[interrupt]
+fn UART1_IRQ() {
+    static mut IRQ: Option<CrsfIrq> = None;
+
+    if IRQ.is_none() {
+        *IRQ = critical_section::with(|cs| CRSF_IRQ.borrow(cs).take());
+    }
+
+    if let Some(irq) = IRQ {
+        irq.service();
+    } else {
+        // Nothing can drain the FIFO, so mask until `initialize_crsf_receiver` unmasks again
+        pac::NVIC::mask(pac::Interrupt::UART1_IRQ);
+    }
+}
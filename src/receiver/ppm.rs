@@ -0,0 +1,174 @@
+use core::{cell::RefCell, ops::RangeInclusive};
+
+use critical_section::Mutex;
+use rp2040_hal::{
+    gpio::{
+        bank0::Gpio3, FunctionNull, FunctionSioInput, Interrupt::EdgeLow, Pin, PullDown, PullNone,
+    },
+    pac,
+    timer::Instant,
+    Timer,
+};
+
+use super::{Edges, Receiver, ReceiverConfig, Smoothing, SHARED};
+
+/// The channel counts a PPM frame may have.
+const CHANNEL_COUNTS: RangeInclusive<usize> = 4..=8;
+
+const MAX_CHANNELS: usize = 8;
+
+/// Gaps between edges longer than this (in µs) are the sync gap between frames.
+const SYNC_GAP_US: u64 = 3000;
+
+/// Gaps between edges that can be a channel, in µs. Anything else inside a
+/// frame is noise or a lost edge, and the frame is dropped.
+const CHANNEL_US: RangeInclusive<u64> = 500..=2500;
+
+/// PPM channels, counted from 0, that stand in for the PWM steering, throttle and aux inputs.
+const STEERING_CHANNEL: usize = 0;
+const THROTTLE_CHANNEL: usize = 1;
+const AUX_CHANNEL: usize = 2;
+
+/// One decoded PPM frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpmFrame {
+    /// Channel values in µs. Only the first `count` are meaningful.
+    pub channels: [u16; MAX_CHANNELS],
+    pub count: usize,
+}
+
+/// Turns the times of a PPM stream's falling edges into frames.
+///
+/// Each channel is the time from one edge to the next, so the pulse polarity
+/// doesn't matter. A frame is complete when the sync gap arrives. The channel
+/// count is learned from the stream: a frame with a different count from the
+/// one before it is dropped, so a glitch can't shuffle the channels and a
+/// receiver that really changes count is picked up on its second frame.
+pub struct PpmDecoder {
+    last_edge: Option<Instant>,
+    channels: [u16; MAX_CHANNELS],
+    count: usize,
+    /// Whether a sync gap has been seen and no bad interval since.
+    in_frame: bool,
+    expected_count: Option<usize>,
+}
+
+impl PpmDecoder {
+    pub const fn new() -> Self {
+        Self {
+            last_edge: None,
+            channels: [0; MAX_CHANNELS],
+            count: 0,
+            in_frame: false,
+            expected_count: None,
+        }
+    }
+
+    /// Feeds the time of an edge, and returns the frame it completes, if any.
+    pub fn on_edge(&mut self, now: Instant) -> Option<PpmFrame> {
+        let interval = (now - self.last_edge.replace(now)?).to_micros();
+
+        if interval > SYNC_GAP_US {
+            let frame = self.in_frame.then_some(PpmFrame {
+                channels: self.channels,
+                count: self.count,
+            });
+            self.in_frame = true;
+            self.count = 0;
+
+            let frame = frame.filter(|frame| CHANNEL_COUNTS.contains(&frame.count))?;
+            let consistent = self.expected_count == Some(frame.count);
+            self.expected_count = Some(frame.count);
+            return consistent.then_some(frame);
+        }
+
+        if self.in_frame {
+            if CHANNEL_US.contains(&interval) && self.count < MAX_CHANNELS {
+                self.channels[self.count] = interval as u16;
+                self.count += 1;
+            } else {
+                self.in_frame = false;
+            }
+        }
+        None
+    }
+}
+
+/// The interrupt half of a PPM receiver: the input pin and the decoder.
+pub struct PpmIrq {
+    pin: Pin<Gpio3, FunctionSioInput, PullNone>,
+    timer: Timer,
+    decoder: PpmDecoder,
+    smoothing: Smoothing,
+    plausible_us: RangeInclusive<u16>,
+    neutral_us: RangeInclusive<u16>,
+}
+
+impl PpmIrq {
+    /// Handles a pending PPM edge. `IO_IRQ_BANK0` calls this when PPM is in use.
+    pub fn service(&mut self) {
+        if !self.pin.interrupt_status(EdgeLow) {
+            return;
+        }
+        let now = self.timer.get_counter();
+        self.pin.clear_interrupt(EdgeLow);
+
+        if let Some(frame) = self.decoder.on_edge(now) {
+            let edges = Edges {
+                steering: Some(frame.channels[STEERING_CHANNEL]),
+                throttle: Some(frame.channels[THROTTLE_CHANNEL]),
+                aux: Some(frame.channels[AUX_CHANNEL]),
+                update: true,
+            };
+            SHARED.record(
+                edges,
+                now,
+                &mut self.smoothing,
+                &self.plausible_us,
+                &self.neutral_us,
+            );
+        }
+    }
+}
+
+static PPM_IRQ: Mutex<RefCell<Option<PpmIrq>>> = Mutex::new(RefCell::new(None));
+
+/// Takes the PPM state for the `IO_IRQ_BANK0` handler, if PPM has been set up.
+pub(super) fn take_irq() -> Option<PpmIrq> {
+    critical_section::with(|cs| PPM_IRQ.borrow(cs).take())
+}
+
+/// Sets up PPM input on GP3 and unmasks `IO_IRQ_BANK0`.
+///
+/// This shares `IO_IRQ_BANK0` with the PWM capture, so only one of
+/// `initialize_receiver` and this may be called. The `Receiver` is the same
+/// one the PWM path returns. `capture_mode` has no meaning here, and each
+/// good frame counts as an update.
+pub fn initialize_ppm_receiver(
+    timer: Timer,
+    pin: Pin<Gpio3, FunctionNull, PullDown>,
+    config: ReceiverConfig,
+) -> Receiver {
+    let pin = pin.into_floating_input();
+    pin.set_interrupt_enabled(EdgeLow, true);
+
+    SHARED.install_timer(timer, config.watchdog_timeout);
+    critical_section::with(|cs| {
+        PPM_IRQ.borrow(cs).replace(Some(PpmIrq {
+            pin,
+            timer,
+            decoder: PpmDecoder::new(),
+            smoothing: Smoothing::new(config.smoothing_shift),
+            plausible_us: config.plausible_us(),
+            neutral_us: config.neutral_band(),
+        }));
+    });
+
+    // As with PWM, the handoff has to complete before the interrupt can run
+    #[allow(unsafe_code)] // We've computed that our interrupt enabling is safe
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+
+    Receiver::new(config)
+}
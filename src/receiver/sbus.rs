@@ -0,0 +1,285 @@
+use core::{cell::RefCell, ops::RangeInclusive};
+
+use critical_section::Mutex;
+use fugit::HertzU32;
+use rp2040_hal::{
+    gpio::{
+        bank0::{Gpio0, Gpio1},
+        FunctionNull, FunctionUart, InputOverride, Pin, PullDown,
+    },
+    pac::{self, interrupt, RESETS, UART0},
+    uart::{DataBits, Enabled, Parity, StopBits, UartConfig, UartPeripheral},
+    Timer,
+};
+
+use super::{Edges, Receiver, ReceiverConfig, Smoothing, SHARED};
+
+/// SBUS runs at a non-standard 100 kbaud, 8 data bits, even parity, 2 stop bits.
+const SBUS_BAUD_HZ: u32 = 100_000;
+
+/// Bytes in a frame: the start byte, 22 bytes of packed channels, the flags and the end byte.
+const FRAME_LEN: usize = 25;
+
+const START_BYTE: u8 = 0x0F;
+
+/// Flag bits in the second to last byte.
+const FLAG_FRAME_LOST: u8 = 1 << 2;
+const FLAG_FAILSAFE: u8 = 1 << 3;
+
+/// SBUS channels, counted from 0, that stand in for the PWM steering, throttle and aux inputs.
+const STEERING_CHANNEL: usize = 0;
+const THROTTLE_CHANNEL: usize = 1;
+const AUX_CHANNEL: usize = 2;
+
+/// Whether `byte` can end a frame.
+///
+/// Plain SBUS ends with 0x00. SBUS2 receivers count telemetry slots in the
+/// high nibble and send 0x04, 0x14, 0x24 or 0x34 instead.
+fn is_end_byte(byte: u8) -> bool {
+    byte == 0x00 || byte & 0x0F == 0x04
+}
+
+/// One decoded SBUS frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SbusFrame {
+    /// The 16 proportional channels, 11 bits each.
+    pub channels: [u16; 16],
+    /// The receiver missed this frame from the transmitter and is repeating the last one.
+    pub frame_lost: bool,
+    /// The receiver has lost the transmitter and is sending its failsafe positions.
+    pub failsafe: bool,
+}
+
+/// The servo pulse width, in µs, that an 11-bit SBUS value stands for.
+///
+/// This is the usual FrSky scaling: 992 is 1500 µs, and the 172..=1811 a
+/// transmitter sends at ±100 % comes out at roughly 988..=2012 µs. CRSF uses
+/// the same values.
+pub fn channel_us(value: u16) -> u16 {
+    ((value as i32 - 992) * 5 / 8 + 1500) as u16
+}
+
+/// Unpacks 16 11-bit channels from the 22 bytes they are packed into.
+///
+/// CRSF packs its channels the same way.
+pub(super) fn unpack_channels(packed: &[u8; 22]) -> [u16; 16] {
+    let mut channels = [0u16; 16];
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut channel = 0;
+    // Channels are packed least significant bit first, straight across the byte boundaries
+    for &byte in packed {
+        bits |= (byte as u32) << bit_count;
+        bit_count += 8;
+        while bit_count >= 11 {
+            channels[channel] = (bits & 0x7FF) as u16;
+            bits >>= 11;
+            bit_count -= 11;
+            channel += 1;
+        }
+    }
+    channels
+}
+
+/// Unpacks the channels and flags from a frame that has passed the start and end byte checks.
+fn decode(frame: &[u8; FRAME_LEN]) -> SbusFrame {
+    let mut packed = [0u8; 22];
+    packed.copy_from_slice(&frame[1..23]);
+
+    let flags = frame[23];
+    SbusFrame {
+        channels: unpack_channels(&packed),
+        frame_lost: flags & FLAG_FRAME_LOST != 0,
+        failsafe: flags & FLAG_FAILSAFE != 0,
+    }
+}
+
+/// Reassembles SBUS frames from the UART's byte stream.
+pub struct SbusParser {
+    buffer: [u8; FRAME_LEN],
+    len: usize,
+}
+
+impl SbusParser {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Feeds one byte, and returns the frame it completes, if any.
+    ///
+    /// Bytes are dropped until a start byte arrives, so a parser that comes up
+    /// part way through a frame syncs on the next one. 0x0F can also turn up
+    /// in channel data, so if a frame ends in the wrong byte it is thrown away
+    /// and parsing carries on from the next 0x0F inside it.
+    pub fn push(&mut self, byte: u8) -> Option<SbusFrame> {
+        if self.len == 0 && byte != START_BYTE {
+            return None;
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        if self.len < FRAME_LEN {
+            return None;
+        }
+
+        if is_end_byte(byte) {
+            self.len = 0;
+            return Some(decode(&self.buffer));
+        }
+
+        self.len = match self.buffer[1..].iter().position(|&b| b == START_BYTE) {
+            Some(offset) => {
+                let start = offset + 1;
+                self.buffer.copy_within(start.., 0);
+                FRAME_LEN - start
+            }
+            None => 0,
+        };
+        None
+    }
+
+    /// Drops any partial frame, e.g. after the UART reports lost bytes.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+}
+
+type SbusUart = UartPeripheral<
+    Enabled,
+    UART0,
+    (
+        Pin<Gpio0, FunctionUart, PullDown>,
+        Pin<Gpio1, FunctionUart, PullDown>,
+    ),
+>;
+
+/// The interrupt half of an SBUS receiver: the UART and the frame parser.
+pub struct SbusIrq {
+    uart: SbusUart,
+    timer: Timer,
+    parser: SbusParser,
+    smoothing: Smoothing,
+    plausible_us: RangeInclusive<u16>,
+    neutral_us: RangeInclusive<u16>,
+}
+
+impl SbusIrq {
+    /// Drains the UART and publishes every complete frame. Call this from `UART0_IRQ`.
+    pub fn service(&mut self) {
+        let mut buffer = [0u8; 32];
+        while self.uart.uart_is_readable() {
+            match self.uart.read_raw(&mut buffer) {
+                Ok(bytes) => {
+                    for &byte in bytes.iter() {
+                        if let Some(frame) = self.parser.push(byte) {
+                            self.publish(frame);
+                        }
+                    }
+                }
+                // Parity, framing and overrun errors all mean bytes went missing
+                Err(_) => self.parser.reset(),
+            }
+        }
+    }
+
+    /// Hands a frame's channels to the same shared state the PWM capture feeds.
+    ///
+    /// A lost frame repeats old data and a failsafe frame carries the
+    /// receiver's own failsafe positions, so neither counts as a fresh frame.
+    /// Leaving them out lets the watchdog decide on failsafe, the same as it
+    /// does for PWM.
+    fn publish(&mut self, frame: SbusFrame) {
+        if frame.failsafe || frame.frame_lost {
+            return;
+        }
+
+        let edges = Edges {
+            steering: Some(channel_us(frame.channels[STEERING_CHANNEL])),
+            throttle: Some(channel_us(frame.channels[THROTTLE_CHANNEL])),
+            aux: Some(channel_us(frame.channels[AUX_CHANNEL])),
+            update: true,
+        };
+        SHARED.record(
+            edges,
+            self.timer.get_counter(),
+            &mut self.smoothing,
+            &self.plausible_us,
+            &self.neutral_us,
+        );
+    }
+}
+
+static SBUS_IRQ: Mutex<RefCell<Option<SbusIrq>>> = Mutex::new(RefCell::new(None));
+
+/// Sets up SBUS input on GP1 and its built-in `UART0_IRQ` handler, then unmasks the interrupt.
+///
+/// SBUS idles low, the inverse of a UART, so GP1's input is inverted in the
+/// pad and needs no external inverter. GP0 is claimed as the UART's TX but
+/// never driven. The `Receiver` is the same one the PWM path returns, so
+/// nothing downstream can tell which input is in use. `capture_mode` has no
+/// meaning here, and neither does the update pin: each good frame counts as
+/// an update.
+pub fn initialize_sbus_receiver(
+    timer: Timer,
+    resets: &mut RESETS,
+    uart: UART0,
+    tx: Pin<Gpio0, FunctionNull, PullDown>,
+    rx: Pin<Gpio1, FunctionNull, PullDown>,
+    config: ReceiverConfig,
+    peripheral_clock: HertzU32,
+) -> Receiver {
+    let tx = tx.into_function::<FunctionUart>();
+    let mut rx = rx.into_function::<FunctionUart>();
+    rx.set_input_override(InputOverride::Invert);
+
+    let mut uart = UartPeripheral::new(uart, (tx, rx), resets)
+        .enable(
+            UartConfig::new(
+                HertzU32::Hz(SBUS_BAUD_HZ),
+                DataBits::Eight,
+                Some(Parity::Even),
+                StopBits::Two,
+            ),
+            peripheral_clock,
+        )
+        .unwrap();
+    uart.enable_rx_interrupt();
+
+    SHARED.install_timer(timer, config.watchdog_timeout);
+    critical_section::with(|cs| {
+        SBUS_IRQ.borrow(cs).replace(Some(SbusIrq {
+            uart,
+            timer,
+            parser: SbusParser::new(),
+            smoothing: Smoothing::new(config.smoothing_shift),
+            plausible_us: config.plausible_us(),
+            neutral_us: config.neutral_band(),
+        }));
+    });
+
+    // As with PWM, the handoff has to complete before the interrupt can run
+    #[allow(unsafe_code)] // We've computed that our interrupt enabling is safe
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::UART0_IRQ);
+    }
+
+    Receiver::new(config)
+}
+
+#[interrupt]
+fn UART0_IRQ() {
+    static mut IRQ: Option<SbusIrq> = None;
+
+    if IRQ.is_none() {
+        *IRQ = critical_section::with(|cs| SBUS_IRQ.borrow(cs).take());
+    }
+
+    if let Some(irq) = IRQ {
+        irq.service();
+    } else {
+        // Nothing can drain the FIFO, so mask until `initialize_sbus_receiver` unmasks again
+        pac::NVIC::mask(pac::Interrupt::UART0_IRQ);
+    }
+}
@@ -0,0 +1,160 @@
+//! Config commands sent as timed pulses on the update line, for tweaking a
+//! car in the field without a serial connection.
+//!
+//! A message only starts after `SYNC_GAP` of silence on the line, which never
+//! happens while a receiver is sending frames. It is then a burst of edges
+//! `SHORT_MIN..=SHORT_MAX` apart, closed by one edge `END_MIN..=END_MAX`
+//! later, and the same burst again as a check:
+//!
+//! ```text
+//! silence ≥ 500 ms | N gaps of 0.5–1.5 ms | 8–12 ms | N gaps of 0.5–1.5 ms | 8–12 ms
+//! ```
+//!
+//! `N` picks the command, see [`WireCommand::from_count`]. No RC receiver
+//! sends frames anywhere near 1.5 ms apart, and a message needs both the
+//! silence and two matching bursts, so normal driving can't be mistaken for
+//! one. Anything that breaks the pattern drops the message, and the edge that
+//! broke it is taken as an ordinary frame.
+
+use fugit::MicrosDurationU64;
+use rp2040_hal::timer::Instant;
+
+/// How long the line has to be quiet before a message can start.
+const SYNC_GAP: MicrosDurationU64 = MicrosDurationU64::millis(500);
+
+/// The gaps between the edges of a burst, each of which counts one.
+const SHORT_MIN: MicrosDurationU64 = MicrosDurationU64::micros(500);
+const SHORT_MAX: MicrosDurationU64 = MicrosDurationU64::micros(1500);
+
+/// The gaps that close a burst.
+const END_MIN: MicrosDurationU64 = MicrosDurationU64::millis(8);
+const END_MAX: MicrosDurationU64 = MicrosDurationU64::millis(12);
+
+/// The longest burst there is a command for.
+const MAX_COUNT: u8 = 24;
+
+/// A config change decoded from the update line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum WireCommand {
+    /// The master brightness, as `Config::brightness`.
+    Brightness(u8),
+    /// The turn signal blink, as `Config::blink_ms`.
+    BlinkMs(u16),
+}
+
+impl WireCommand {
+    /// The command a burst of `count` gaps stands for: 1 to 16 sets the
+    /// brightness in sixteenths, up to full, and 17 to 24 the blink in steps
+    /// of 100 ms, from 100 to 800 ms.
+    pub const fn from_count(count: u8) -> Option<WireCommand> {
+        match count {
+            1..=16 => Some(WireCommand::Brightness((count as u16 * 255 / 16) as u8)),
+            17..=MAX_COUNT => Some(WireCommand::BlinkMs((count - 16) as u16 * 100)),
+            _ => None,
+        }
+    }
+}
+
+// Every command at both ends of its range, and nothing either side
+const _: () = {
+    assert!(WireCommand::from_count(0).is_none());
+    assert!(matches!(
+        WireCommand::from_count(1),
+        Some(WireCommand::Brightness(15))
+    ));
+    assert!(matches!(
+        WireCommand::from_count(8),
+        Some(WireCommand::Brightness(127))
+    ));
+    assert!(matches!(
+        WireCommand::from_count(16),
+        Some(WireCommand::Brightness(255))
+    ));
+    assert!(matches!(
+        WireCommand::from_count(17),
+        Some(WireCommand::BlinkMs(100))
+    ));
+    assert!(matches!(
+        WireCommand::from_count(MAX_COUNT),
+        Some(WireCommand::BlinkMs(800))
+    ));
+    assert!(WireCommand::from_count(MAX_COUNT + 1).is_none());
+    // A burst only closes once it has gaps, and the check can't outgrow it
+    assert!(SHORT_MAX.ticks() < END_MIN.ticks());
+    assert!(END_MAX.ticks() < SYNC_GAP.ticks());
+};
+
+/// What an update edge turned out to be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireEdge {
+    /// An ordinary frame. The first edge after the silence is one of these
+    /// too, as it can't be told from a returning signal until the next.
+    Frame,
+    /// Part of a message still coming in.
+    Message,
+    /// The last edge of a message that checked out.
+    Command(WireCommand),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Not in a message, until the line goes quiet.
+    Idle,
+    /// Counting the first burst.
+    First(u8),
+    /// Counting the check burst against the first.
+    Check { first: u8, count: u8 },
+}
+
+/// Picks messages out of the update edges, see the module docs. Runs in the ISR.
+pub struct WireDecoder {
+    last_edge: Option<Instant>,
+    state: State,
+}
+
+impl WireDecoder {
+    pub const fn new() -> Self {
+        Self {
+            last_edge: None,
+            state: State::Idle,
+        }
+    }
+
+    /// Takes the update edge at `now`, and says what it was.
+    pub fn on_edge(&mut self, now: Instant) -> WireEdge {
+        let gap = self.last_edge.replace(now).map(|last| now - last);
+        // The first edge since boot has nothing before it, which is as quiet as it gets
+        if gap.is_none_or(|gap| gap >= SYNC_GAP) {
+            self.state = State::First(0);
+            return WireEdge::Frame;
+        }
+        let short = gap.is_some_and(|gap| SHORT_MIN <= gap && gap <= SHORT_MAX);
+        let end = gap.is_some_and(|gap| END_MIN <= gap && gap <= END_MAX);
+
+        let (state, edge) = match self.state {
+            State::First(count) if short && count < MAX_COUNT => {
+                (State::First(count + 1), WireEdge::Message)
+            }
+            State::First(first) if end && first > 0 => {
+                (State::Check { first, count: 0 }, WireEdge::Message)
+            }
+            State::Check { first, count } if short && count < first => (
+                State::Check {
+                    first,
+                    count: count + 1,
+                },
+                WireEdge::Message,
+            ),
+            State::Check { first, count } if end && count == first => (
+                State::Idle,
+                match WireCommand::from_count(first) {
+                    Some(command) => WireEdge::Command(command),
+                    None => WireEdge::Message,
+                },
+            ),
+            _ => (State::Idle, WireEdge::Frame),
+        };
+        self.state = state;
+        edge
+    }
+}
@@ -0,0 +1,220 @@
+//! The firmware as an RTIC application.
+//!
+//! Enabled with the `rtic` feature. The receiver's edge capture runs as a
+//! hardware task bound to `IO_IRQ_BANK0`, and each update runs as a periodic
+//! task driven by timer alarm 0 instead of a busy loop. The update itself is
+//! the same [`Pipeline::tick`](crate::Pipeline::tick) the bare-metal `System`
+//! calls, so the lights, both status LEDs, the saved config and the dimming all
+//! behave the same. What this build leaves out is the USB console, which
+//! `System` polls between updates, every receiver input but PWM on the default
+//! pins, and split front and rear LED wiring.
+
+#[rtic::app(device = rp2040_hal::pac, peripherals = true)]
+mod app {
+    use defmt::*;
+    use fugit::ExtU32;
+    use rp2040_hal::{
+        self as hal,
+        clocks::Clock,
+        dma::DMAExt,
+        gpio::FunctionPio0,
+        pio::PIOExt,
+        timer::{Alarm, Alarm0},
+        watchdog::Watchdog,
+    };
+
+    use crate::{
+        ambient::AmbientLight,
+        battery::BatteryMonitor,
+        config::Config,
+        fatal_blink, fatal_status_pin, halt_with_fault,
+        hang::HangWatchdog,
+        led_color_order,
+        led_core::{spawn_led_core, LedOutput},
+        lights::{initialize_lights, run_startup_sequence, LedDma, Leds, LED_FREQUENCY_HZ},
+        receiver::{initialize_receiver_parts, ReceiverIrq, ReceiverPins},
+        status::StatusLed,
+        AnalogInputs, ExternalIndicator, LedCore, Pipeline, AMBIENT_CONFIG, AMBIENT_DIMMING,
+        BATTERY_CONFIG, BATTERY_MONITOR, CAPTURE_MODE, COMBINED_FAULT_POLICY, EXTERNAL_INDICATOR,
+        LED_CORE, LED_CORNERS, LED_FRAME_LIMIT, LED_PIXEL_FORMAT, LED_RESET_US, LINK_THRESHOLDS,
+        RECEIVER_CONFIG, RUN_STARTUP_SEQUENCE, STATUS_LED_ACTIVE_LOW, UPDATE_PERIOD_MS,
+        XTAL_FREQ_HZ,
+    };
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        receiver_irq: ReceiverIrq,
+        pipeline: Pipeline,
+        alarm: Alarm0,
+        timer: hal::Timer,
+        watchdog: HangWatchdog,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        debug!("Program start");
+        let mut pac = cx.device;
+        let mut watchdog = Watchdog::new(pac.WATCHDOG);
+
+        let clocks = match hal::clocks::init_clocks_and_plls(
+            XTAL_FREQ_HZ,
+            pac.XOSC,
+            pac.CLOCKS,
+            pac.PLL_SYS,
+            pac.PLL_USB,
+            &mut pac.RESETS,
+            &mut watchdog,
+        ) {
+            Ok(clocks) => clocks,
+            Err(_) => {
+                error!("Clocks failed to start");
+                fatal_blink(fatal_status_pin(
+                    pac.IO_BANK0,
+                    pac.PADS_BANK0,
+                    pac.SIO,
+                    &mut pac.RESETS,
+                ))
+            }
+        };
+
+        let sio = hal::Sio::new(pac.SIO);
+        let pins = hal::gpio::Pins::new(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        );
+
+        let status = StatusLed::new(pins.gpio25.into_push_pull_output().into_dyn_pin())
+            .active_low(STATUS_LED_ACTIVE_LOW);
+
+        let mut timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+        let config = Config::load();
+        debug!("Loaded {}", config);
+
+        // RTIC unmasks IO_IRQ_BANK0 itself once init returns.
+        let (mut receiver, receiver_irq) = initialize_receiver_parts(
+            timer,
+            &mut pac.RESETS,
+            pac.PWM,
+            RECEIVER_CONFIG,
+            CAPTURE_MODE,
+            ReceiverPins {
+                steering: pins.gpio3,
+                throttle: pins.gpio5,
+                update: pins.gpio4,
+                aux: pins.gpio7,
+            },
+            clocks.system_clock.freq(),
+        );
+        receiver.set_combined_fault_policy(COMBINED_FAULT_POLICY);
+        receiver.set_link_thresholds(LINK_THRESHOLDS);
+
+        let pin = pins
+            .gpio8
+            .into_push_pull_output_in_state(hal::gpio::PinState::Low)
+            .into_function::<FunctionPio0>()
+            .into_dyn_pin();
+
+        let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
+        #[cfg(feature = "scope-pin")]
+        crate::lights::set_scope_pin(pins.gpio22.into_push_pull_output().into_dyn_pin());
+        let tx = match initialize_lights(
+            &mut pio,
+            sm0,
+            &clocks,
+            pin,
+            LED_FREQUENCY_HZ,
+            LED_PIXEL_FORMAT.bits_per_pixel(),
+            LED_RESET_US,
+        ) {
+            Ok(tx) => tx,
+            Err(error) => {
+                error!("Lights failed to start: {}", error);
+                let mut status = status;
+                halt_with_fault(&mut status, clocks.system_clock.freq().to_Hz())
+            }
+        };
+        debug!(
+            "LED frame takes {}us, so at most {} fps",
+            Leds::frame_transmit_us(
+                LED_FREQUENCY_HZ,
+                LED_PIXEL_FORMAT.bits_per_pixel(),
+                LED_RESET_US
+            ),
+            LED_FRAME_LIMIT.max_fps()
+        );
+        let dma = pac.DMA.split(&mut pac.RESETS);
+        let mut strip = LedDma::new(dma.ch0, tx, led_color_order(&config), LED_PIXEL_FORMAT);
+        if RUN_STARTUP_SEQUENCE {
+            let mut delay =
+                cortex_m::delay::Delay::new(cx.core.SYST, clocks.system_clock.freq().to_Hz());
+            run_startup_sequence(&mut strip, &mut delay, LED_CORNERS);
+        }
+        let output = match LED_CORE {
+            LedCore::Core0 => LedOutput::Local(strip),
+            LedCore::Core1 => {
+                LedOutput::Core1(spawn_led_core(&mut pac.PSM, &mut pac.PPB, sio.fifo, strip))
+            }
+        };
+
+        defmt::debug!("{}", clocks.system_clock.freq().to_Hz());
+
+        let external_indicator = (EXTERNAL_INDICATOR == ExternalIndicator::Gpio)
+            .then(|| StatusLed::new(pins.gpio15.into_push_pull_output().into_dyn_pin()));
+        let adc =
+            (AMBIENT_DIMMING || BATTERY_MONITOR).then(|| hal::Adc::new(pac.ADC, &mut pac.RESETS));
+        let ambient = AMBIENT_DIMMING
+            .then(|| AmbientLight::new(pins.gpio26.into_floating_input(), AMBIENT_CONFIG));
+        let battery = BATTERY_MONITOR
+            .then(|| BatteryMonitor::new(pins.gpio27.into_floating_input(), BATTERY_CONFIG));
+
+        let mut alarm = timer.alarm_0().unwrap();
+        alarm.schedule(UPDATE_PERIOD_MS.millis()).unwrap();
+        alarm.enable_interrupt();
+
+        (
+            Shared {},
+            Local {
+                receiver_irq,
+                pipeline: Pipeline::new(
+                    status,
+                    config,
+                    receiver,
+                    external_indicator,
+                    output,
+                    AnalogInputs {
+                        adc,
+                        ambient,
+                        battery,
+                    },
+                ),
+                alarm,
+                timer,
+                // Last, so nothing slow at boot counts towards the timeout
+                watchdog: HangWatchdog::start(watchdog),
+            },
+        )
+    }
+
+    #[task(binds = IO_IRQ_BANK0, local = [receiver_irq])]
+    fn receiver_edge(cx: receiver_edge::Context) {
+        cx.local.receiver_irq.service();
+    }
+
+    #[task(binds = TIMER_IRQ_0, local = [pipeline, alarm, timer, watchdog])]
+    fn update_lights(cx: update_lights::Context) {
+        let alarm = cx.local.alarm;
+        alarm.clear_interrupt();
+        alarm.schedule(UPDATE_PERIOD_MS.millis()).unwrap();
+        cx.local.watchdog.pet();
+
+        let now = cx.local.timer.get_counter();
+        cx.local.pipeline.tick(now);
+        cx.local.pipeline.log_telemetry(now);
+    }
+}
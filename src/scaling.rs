@@ -0,0 +1,227 @@
+//! The receiver's pulse arithmetic, on plain integers so it doesn't depend on
+//! any hardware type.
+
+/// Maps a pulse onto -100..=100 around `neutral_us`, clamping outside `min_us` and `max_us`.
+///
+/// The two halves are scaled separately, so the neutral point doesn't have to
+/// sit in the middle. A reading of 0 means no pulse has been captured yet, so
+/// it gives `None`.
+pub const fn scale_pulse(pulse: u16, min_us: u16, neutral_us: u16, max_us: u16) -> Option<i16> {
+    if pulse == 0 {
+        return None;
+    }
+
+    let offset = pulse as i32 - neutral_us as i32;
+    let span = if offset < 0 {
+        neutral_us as i32 - min_us as i32
+    } else {
+        max_us as i32 - neutral_us as i32
+    };
+
+    let percent = if span <= 0 {
+        // Degenerate calibration: anything off neutral is a full deflection
+        offset.signum() * 100
+    } else {
+        offset * 100 / span
+    };
+
+    Some(if percent < -100 {
+        -100
+    } else if percent > 100 {
+        100
+    } else {
+        percent as i16
+    })
+}
+
+/// Flips the sign of a percentage from [`scale_pulse`] for a channel that is `inverted`.
+///
+/// The range is symmetric, so every input has an exact opposite.
+pub const fn apply_invert(percent: i16, inverted: bool) -> i16 {
+    if inverted {
+        -percent
+    } else {
+        percent
+    }
+}
+
+/// Mixes throttle and steering percentages into `(left, right)` sides, as for
+/// tank or differential steering: throttle plus steering on the left, minus it
+/// on the right.
+///
+/// Each side is clamped to -100..=100 on its own, by the same bounds, so full
+/// throttle with full steering pins one side at 100 and leaves the other at 0,
+/// rather than wrapping either of them.
+pub const fn mix_differential(throttle: i16, steering: i16) -> (i16, i16) {
+    const fn clamp(value: i32) -> i16 {
+        if value < -100 {
+            -100
+        } else if value > 100 {
+            100
+        } else {
+            value as i16
+        }
+    }
+    let (throttle, steering) = (throttle as i32, steering as i32);
+    (clamp(throttle + steering), clamp(throttle - steering))
+}
+
+/// Reads a percentage within `band` of 0 as exactly 0, and passes the rest through.
+///
+/// This is the one steering dead band: the percentages and everything that
+/// follows them, such as the turn signals, see the same centred value. Outside
+/// the band nothing is rescaled, so the ends still reach ±100.
+pub const fn apply_dead_band(percent: i16, band: u8) -> i16 {
+    if percent.unsigned_abs() <= band as u16 {
+        0
+    } else {
+        percent
+    }
+}
+
+/// Whether a pulse is within `band_us` of `neutral_us`. No pulse yet (0) counts as inside.
+pub const fn in_dead_band(pulse: u16, neutral_us: u16, band_us: u16) -> bool {
+    pulse == 0 || (pulse as i32 - neutral_us as i32).unsigned_abs() <= band_us as u32
+}
+
+/// Whether a pulse falls in the `min_us..=max_us` window a real stick movement can produce.
+pub const fn is_plausible(pulse: u16, min_us: u16, max_us: u16) -> bool {
+    min_us <= pulse && pulse <= max_us
+}
+
+/// Converts a PWM slice count to µs, for a slice clocked from `system_hz` through an integer `divider`.
+///
+/// Rounds down, and saturates rather than wrapping. A `system_hz` of 0 reads as 0 µs.
+pub const fn ticks_to_micros(ticks: u16, system_hz: u32, divider: u8) -> u32 {
+    if system_hz == 0 {
+        return 0;
+    }
+    let micros = ticks as u64 * divider as u64 * 1_000_000 / system_hz as u64;
+    if micros > u32::MAX as u64 {
+        u32::MAX
+    } else {
+        micros as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale(pulse: u16) -> Option<i16> {
+        scale_pulse(pulse, 1000, 1500, 2000)
+    }
+
+    #[test]
+    fn scale_pulse_matrix() {
+        // Zero is no pulse yet
+        assert_eq!(scale(0), None);
+        // Below min
+        assert_eq!(scale(500), Some(-100));
+        assert_eq!(scale(1000), Some(-100));
+        assert_eq!(scale(1250), Some(-50));
+        // Centre
+        assert_eq!(scale(1500), Some(0));
+        assert_eq!(scale(1750), Some(50));
+        assert_eq!(scale(2000), Some(100));
+        // Above max
+        assert_eq!(scale(2500), Some(100));
+        assert_eq!(scale(u16::MAX), Some(100));
+    }
+
+    #[test]
+    fn scale_pulse_off_centre_neutral() {
+        // Each half keeps its own span
+        assert_eq!(scale_pulse(1200, 1000, 1400, 2000), Some(-50));
+        assert_eq!(scale_pulse(1700, 1000, 1400, 2000), Some(50));
+    }
+
+    #[test]
+    fn scale_pulse_degenerate_endpoints() {
+        assert_eq!(scale_pulse(1400, 1500, 1500, 1500), Some(-100));
+        assert_eq!(scale_pulse(1500, 1500, 1500, 1500), Some(0));
+        assert_eq!(scale_pulse(1600, 1500, 1500, 1500), Some(100));
+    }
+
+    #[test]
+    fn invert() {
+        assert_eq!(apply_invert(80, true), -80);
+        assert_eq!(apply_invert(-80, true), 80);
+        assert_eq!(apply_invert(80, false), 80);
+        assert_eq!(apply_invert(0, true), 0);
+        assert_eq!(apply_invert(100, true), -100);
+        assert_eq!(apply_invert(-100, true), 100);
+        // A pulse that reads +80% reads -80% on an inverted channel
+        assert_eq!(
+            scale(1900).map(|percent| apply_invert(percent, true)),
+            Some(-80)
+        );
+    }
+
+    #[test]
+    fn differential_mix() {
+        assert_eq!(mix_differential(0, 0), (0, 0));
+        assert_eq!(mix_differential(50, 0), (50, 50));
+        assert_eq!(mix_differential(0, 50), (50, -50));
+        assert_eq!(mix_differential(50, 30), (80, 20));
+        assert_eq!(mix_differential(100, 100), (100, 0));
+        assert_eq!(mix_differential(100, -100), (0, 100));
+        assert_eq!(mix_differential(-100, 100), (0, -100));
+        assert_eq!(mix_differential(-100, -100), (-100, 0));
+        assert_eq!(mix_differential(0, -100), (-100, 100));
+        // Far outside the range still clamps rather than overflowing
+        assert_eq!(mix_differential(i16::MAX, i16::MAX), (100, 0));
+        assert_eq!(mix_differential(i16::MIN, i16::MIN), (-100, 0));
+    }
+
+    #[test]
+    fn dead_band_percent() {
+        assert_eq!(apply_dead_band(0, 30), 0);
+        assert_eq!(apply_dead_band(5, 30), 0);
+        assert_eq!(apply_dead_band(-30, 30), 0);
+        assert_eq!(apply_dead_band(30, 30), 0);
+        assert_eq!(apply_dead_band(31, 30), 31);
+        assert_eq!(apply_dead_band(-31, 30), -31);
+        assert_eq!(apply_dead_band(100, 30), 100);
+        assert_eq!(apply_dead_band(-100, 100), 0);
+        assert_eq!(apply_dead_band(i16::MIN, 100), i16::MIN);
+        assert_eq!(apply_dead_band(1, 0), 1);
+    }
+
+    #[test]
+    fn dead_band_pulse() {
+        assert!(in_dead_band(0, 1500, 50));
+        assert!(!in_dead_band(1000, 1500, 50));
+        assert!(!in_dead_band(1449, 1500, 50));
+        assert!(in_dead_band(1450, 1500, 50));
+        assert!(in_dead_band(1500, 1500, 50));
+        assert!(in_dead_band(1550, 1500, 50));
+        assert!(!in_dead_band(1551, 1500, 50));
+        assert!(!in_dead_band(2500, 1500, 50));
+    }
+
+    #[test]
+    fn glitch_window() {
+        assert!(!is_plausible(0, 800, 2200));
+        assert!(!is_plausible(799, 800, 2200));
+        assert!(is_plausible(800, 800, 2200));
+        assert!(is_plausible(1500, 800, 2200));
+        assert!(is_plausible(2200, 800, 2200));
+        assert!(!is_plausible(2201, 800, 2200));
+    }
+
+    #[test]
+    fn pwm_ticks() {
+        // 125 MHz through ÷125 is exactly one tick per µs
+        assert_eq!(ticks_to_micros(0, 125_000_000, 125), 0);
+        assert_eq!(ticks_to_micros(1500, 125_000_000, 125), 1500);
+        assert_eq!(ticks_to_micros(u16::MAX, 125_000_000, 125), u16::MAX as u32);
+        // Overclocked to 250 MHz, the same divider ticks every half µs
+        assert_eq!(ticks_to_micros(3000, 250_000_000, 125), 1500);
+        assert_eq!(ticks_to_micros(3001, 250_000_000, 125), 1500);
+        // 48 MHz through ÷48 is back to one per µs, and through ÷125 it's slower
+        assert_eq!(ticks_to_micros(1500, 48_000_000, 48), 1500);
+        assert_eq!(ticks_to_micros(576, 48_000_000, 125), 1500);
+        assert_eq!(ticks_to_micros(1500, 0, 125), 0);
+    }
+}
@@ -0,0 +1,253 @@
+use fugit::MillisDurationU64;
+use rp2040_hal::timer::Instant;
+
+/// Which indicators are blinking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+enum Blink {
+    Left,
+    Right,
+    /// All four together.
+    Hazard,
+}
+
+/// Which turn indicators should be lit right now.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct TurnSignals {
+    pub left: bool,
+    pub right: bool,
+}
+
+/// Which blink the controls ask for. Any steering off centre is a turn, as the
+/// dead band has already read the small movements as centred.
+const fn requested_blink(steering: i16, hazard: bool) -> Option<Blink> {
+    if hazard {
+        Some(Blink::Hazard)
+    } else if steering < 0 {
+        Some(Blink::Left)
+    } else if steering > 0 {
+        Some(Blink::Right)
+    } else {
+        None
+    }
+}
+
+// A wiggle inside the steering dead band never arms an indicator, and a turn
+// past it always does.
+const _: () = {
+    use crate::receiver::apply_dead_band;
+    const BAND: u8 = 30;
+    const fn arms(steering: i16) -> bool {
+        requested_blink(apply_dead_band(steering, BAND), false).is_some()
+    }
+    let mut wiggle = -(BAND as i16);
+    while wiggle <= BAND as i16 {
+        assert!(!arms(wiggle));
+        wiggle += 1;
+    }
+    assert!(matches!(
+        requested_blink(apply_dead_band(-31, BAND), false),
+        Some(Blink::Left)
+    ));
+    assert!(matches!(
+        requested_blink(apply_dead_band(31, BAND), false),
+        Some(Blink::Right)
+    ));
+    assert!(matches!(requested_blink(0, true), Some(Blink::Hazard)));
+};
+
+/// Blinks the turn indicators from the steering position.
+///
+/// Steering to the left (negative) blinks the left indicators, and to the
+/// right (positive) the right. It expects the percentage from
+/// [`Receiver::steering_percent`](crate::receiver::Receiver::steering_percent),
+/// whose dead band (`ReceiverConfig::steering_dead_band_percent`) decides how
+/// far the steering has to turn, so there is no second threshold here. The phase is
+/// counted from when the blinking started rather than toggled per call, so it
+/// doesn't matter how often [`BlinkController::update`] runs.
+///
+/// A turn latches on, like a real indicator stalk, and cancels itself once the
+/// steering has come back to centre. The steering has to stay off the turn for
+/// `cancel_after` before that counts, so a quick counter-steer mid-turn doesn't
+/// cancel it, and the turn always shows `min_blinks` whole blinks, so a
+/// lane-change flick doesn't cut off part way through the first. Held over to
+/// the other side for `cancel_after` instead, it starts that side's blink from
+/// the beginning straight away.
+///
+/// Hazards override the turn signals and are one more state of the same
+/// machine, sharing its single phase. They follow their switch rather than
+/// latching, and switching them off lets a lit blink finish before going dark.
+/// So switching between them only ever restarts or finishes one blink, and no
+/// corner can be left lit by a blink that nothing is tracking any more.
+pub struct BlinkController {
+    period: MillisDurationU64,
+    min_blinks: u8,
+    cancel_after: MillisDurationU64,
+    blinking: Option<(Blink, Instant)>,
+    /// When the controls stopped asking for the blink that's latched.
+    left_at: Option<Instant>,
+}
+
+impl BlinkController {
+    /// `period` is how long the indicators stay on, and then off, in each blink.
+    /// `min_blinks` and `cancel_after` set when a turn cancels itself, as above.
+    pub fn new(period: MillisDurationU64, min_blinks: u8, cancel_after: MillisDurationU64) -> Self {
+        Self {
+            period,
+            min_blinks,
+            cancel_after,
+            blinking: None,
+            left_at: None,
+        }
+    }
+
+    /// Changes the blink period. A blink in progress keeps its phase origin, so it may jump to its other half.
+    pub fn set_period(&mut self, period: MillisDurationU64) {
+        self.period = period;
+    }
+
+    /// Feeds the latest steering percentage and hazard switch, and returns the indicators to show.
+    pub fn update(&mut self, steering: i16, hazard: bool, now: Instant) -> TurnSignals {
+        let requested = requested_blink(steering, hazard);
+        let left_at = match self.blinking {
+            Some((blink, _)) if Some(blink) != requested => Some(self.left_at.unwrap_or(now)),
+            _ => None,
+        };
+        let released = left_at.is_some_and(|at| now - at >= self.cancel_after);
+
+        self.blinking = match (self.blinking, requested) {
+            (Some((blink, since)), Some(wanted)) if blink == wanted => Some((blink, since)),
+            (None, Some(wanted)) | (_, Some(wanted @ Blink::Hazard)) => Some((wanted, now)),
+            (Some((Blink::Hazard, _)), Some(wanted)) => Some((wanted, now)),
+            (Some((Blink::Hazard, since)), None) => {
+                self.is_lit(since, now).then_some((Blink::Hazard, since))
+            }
+            (Some((turn, since)), _) if !released => Some((turn, since)),
+            (Some(_), Some(wanted)) => Some((wanted, now)),
+            (Some((turn, since)), None)
+                if self.is_lit(since, now) || !self.shown_min_blinks(since, now) =>
+            {
+                Some((turn, since))
+            }
+            (_, None) => None,
+        };
+        self.left_at = left_at;
+
+        match self.blinking {
+            Some((blink, since)) if self.is_lit(since, now) => TurnSignals {
+                left: blink != Blink::Right,
+                right: blink != Blink::Left,
+            },
+            _ => TurnSignals::default(),
+        }
+    }
+
+    /// Whether a turn that started at `since` has finished the on half of its last required blink.
+    fn shown_min_blinks(&self, since: Instant, now: Instant) -> bool {
+        let halves = (2 * self.min_blinks as u64).saturating_sub(1);
+        (now - since).to_millis() >= halves * self.period.to_millis()
+    }
+
+    /// Whether a blink that started at `since` is in its on half at `now`.
+    fn is_lit(&self, since: Instant, now: Instant) -> bool {
+        ((now - since).to_millis() / self.period.to_millis().max(1)) % 2 == 0
+    }
+}
+
+/// Whether the steering moving from `prev` to `cur` percent in `dt_ms` is
+/// faster than `max_percent_per_sec`.
+///
+/// Any change at all in no time is too fast, and a reading that hasn't
+/// changed never is.
+pub const fn steering_too_fast(prev: i16, cur: i16, dt_ms: u64, max_percent_per_sec: u32) -> bool {
+    let change = (cur as i32 - prev as i32).unsigned_abs() as u64;
+    change * 1000 > max_percent_per_sec as u64 * dt_ms
+}
+
+// At 10000 %/s: a whole lock-to-lock swing in one update is too fast, lock
+// to centre isn't.
+const _: () = {
+    assert!(!steering_too_fast(0, 0, 0, 10_000));
+    assert!(steering_too_fast(0, 1, 0, 10_000));
+    assert!(steering_too_fast(-100, 100, 10, 10_000));
+    assert!(steering_too_fast(100, -100, 10, 10_000));
+    assert!(!steering_too_fast(100, 0, 10, 10_000));
+    assert!(steering_too_fast(100, -1, 10, 10_000));
+    assert!(!steering_too_fast(-100, 100, 20, 10_000));
+    assert!(!steering_too_fast(-100, 100, 1000, 200));
+    assert!(steering_too_fast(i16::MIN, i16::MAX, 1, u32::MAX / 1000));
+};
+
+/// Watches for steering that moves faster than a hand on the wheel can, as
+/// interference or a crash tends to make it.
+///
+/// Each update compares the reading with the one before, over the time
+/// between them, using [`steering_too_fast`]. One fast change on its own
+/// only makes the steering suspect; a second within `settle` of it makes it
+/// erratic. Either way it stays that way until `settle` has passed with no
+/// fast changes at all.
+pub struct SteeringGuard {
+    /// The fastest plausible steering, in percent per second, or `None` to never trip.
+    max_rate: Option<u32>,
+    settle: MillisDurationU64,
+    last: Option<(i16, Instant)>,
+    /// When the last fast change was seen.
+    fast_at: Option<Instant>,
+    erratic: bool,
+}
+
+impl SteeringGuard {
+    pub fn new(max_rate: Option<u32>, settle: MillisDurationU64) -> Self {
+        Self {
+            max_rate,
+            settle,
+            last: None,
+            fast_at: None,
+            erratic: false,
+        }
+    }
+
+    /// Feeds the latest steering percentage, and returns whether the steering is erratic.
+    pub fn update(&mut self, steering: i16, now: Instant) -> bool {
+        let fast = match (self.max_rate, self.last) {
+            (Some(max_rate), Some((prev, at))) => {
+                steering_too_fast(prev, steering, (now - at).to_millis(), max_rate)
+            }
+            _ => false,
+        };
+        self.last = Some((steering, now));
+
+        let suspect = self.is_suspect(now);
+        if fast {
+            self.fast_at = Some(now);
+        }
+        // A fast change keeps it from settling, however many came before
+        let erratic = if fast {
+            suspect || self.erratic
+        } else {
+            self.erratic && suspect
+        };
+        if erratic != self.erratic {
+            if erratic {
+                defmt::warn!("Steering erratic");
+            } else {
+                defmt::info!("Steering settled");
+            }
+            self.erratic = erratic;
+        }
+        erratic
+    }
+
+    /// `steering` as the turn signals should see it: centred while a fast
+    /// change is still recent, so even the first wild swing can't start a blink.
+    pub fn filter(&self, steering: i16, now: Instant) -> i16 {
+        if self.is_suspect(now) {
+            0
+        } else {
+            steering
+        }
+    }
+
+    fn is_suspect(&self, now: Instant) -> bool {
+        self.fast_at.is_some_and(|at| now - at < self.settle)
+    }
+}
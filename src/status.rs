@@ -0,0 +1,46 @@
+use embedded_hal::digital::v2::OutputPin;
+use rp2040_hal::gpio::{DynPinId, FunctionSioOutput, Pin, PinState, PullDown};
+
+/// A GPIO set up as a push-pull output, as [`StatusLed`] drives it.
+pub type StatusPin = Pin<DynPinId, FunctionSioOutput, PullDown>;
+
+/// A single on/off indicator LED, such as the Pico's on-board LED on GP25.
+///
+/// By default the LED is treated as active-high (pin high = LED lit), which is
+/// how the Pico wires GP25. Boards that sink the LED into the pin instead can
+/// call [`StatusLed::active_low`] so `set(true)` still means "lit".
+pub struct StatusLed {
+    pin: StatusPin,
+    active_low: bool,
+    on: bool,
+}
+
+impl StatusLed {
+    pub fn new(pin: StatusPin) -> Self {
+        let mut led = Self {
+            pin,
+            active_low: false,
+            on: false,
+        };
+        led.set(false);
+        led
+    }
+
+    /// Inverts the pin writes for LEDs wired active-low.
+    pub fn active_low(mut self, active_low: bool) -> Self {
+        self.active_low = active_low;
+        let on = self.on;
+        self.set(on);
+        self
+    }
+
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+        let state = if on != self.active_low {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        self.pin.set_state(state).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+//! The bare-metal firmware as one object, built around the same [`Pipeline`]
+//! the RTIC build runs.
+//!
+//! [`System::new`] does the bring-up for the wiring the `main` settings
+//! name, and [`System::tick`] runs one spin of the control loop, so `main`
+//! only takes the peripherals and calls it in a loop.
+
+use defmt::{debug, error};
+use fugit::MicrosDurationU64;
+use rp2040_hal::{self as hal, pac, timer::Instant, watchdog::Watchdog, Clock, Timer};
+#[cfg(feature = "lights")]
+use rp2040_hal::{dma::DMAExt, gpio::FunctionPio0, pio::PIOExt};
+
+#[cfg(feature = "cli")]
+use crate::cli::Cli;
+#[cfg(feature = "lights")]
+use crate::{
+    ambient::AmbientLight,
+    battery::BatteryMonitor,
+    halt_with_fault, led_color_order,
+    led_core::{spawn_led_core, LedOutput},
+    lights::{
+        initialize_lights, initialize_split_lights, run_startup_sequence, LedDma, Leds, SplitLeds,
+        LED_FREQUENCY_HZ,
+    },
+    AnalogInputs, LedCore, LedWiring, AMBIENT_CONFIG, AMBIENT_DIMMING, BATTERY_CONFIG,
+    BATTERY_MONITOR, LED_CORE, LED_CORNERS, LED_FRAME_LIMIT, LED_PIXEL_FORMAT, LED_RESET_US,
+    LED_WIRING, RUN_STARTUP_SEQUENCE,
+};
+use crate::{
+    config::Config, fatal_blink, fatal_status_pin, hang::HangWatchdog, status::StatusLed, Pipeline,
+    STATUS_LED_ACTIVE_LOW, UPDATE_PERIOD_MS, XTAL_FREQ_HZ,
+};
+#[cfg(feature = "receiver")]
+use crate::{
+    receiver::{
+        crsf::initialize_crsf_receiver, initialize_receiver, ppm::initialize_ppm_receiver,
+        sbus::initialize_sbus_receiver, ReceiverPins,
+    },
+    ExternalIndicator, PwmPins, ReceiverInput, CAPTURE_MODE, COMBINED_FAULT_POLICY,
+    EXTERNAL_INDICATOR, LINK_THRESHOLDS, PWM_PINS, RECEIVER_CONFIG, RECEIVER_INPUT,
+};
+
+/// Everything the control loop needs between updates, around the [`Pipeline`] itself.
+pub struct System {
+    pipeline: Pipeline,
+    timer: Timer,
+    watchdog: HangWatchdog,
+    #[cfg(feature = "cli")]
+    cli: Cli,
+    /// When the next update is due.
+    next_update: Instant,
+}
+
+impl System {
+    /// Brings up the clocks, the receiver, with its interrupt unmasked, the
+    /// strip and its startup sequence, the status LEDs, the console and, last
+    /// of all, the hang watchdog.
+    ///
+    /// Clocks or a strip that fail to start halt here, blinking the status LED.
+    pub fn new(
+        mut pac: pac::Peripherals,
+        #[cfg(feature = "lights")] core: pac::CorePeripherals,
+    ) -> Self {
+        let mut watchdog = Watchdog::new(pac.WATCHDOG);
+
+        // Configure the clocks
+        let clocks = match hal::clocks::init_clocks_and_plls(
+            XTAL_FREQ_HZ,
+            pac.XOSC,
+            pac.CLOCKS,
+            pac.PLL_SYS,
+            pac.PLL_USB,
+            &mut pac.RESETS,
+            &mut watchdog,
+        ) {
+            Ok(clocks) => clocks,
+            Err(_) => {
+                error!("Clocks failed to start");
+                fatal_blink(fatal_status_pin(
+                    pac.IO_BANK0,
+                    pac.PADS_BANK0,
+                    pac.SIO,
+                    &mut pac.RESETS,
+                ))
+            }
+        };
+
+        defmt::debug!("{}", clocks.system_clock.freq().to_Hz());
+
+        // Only the startup sequence blocks; the control loop is paced by the timer
+        #[cfg(feature = "lights")]
+        let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+
+        // The single-cycle I/O block controls our GPIO pins
+        let sio = hal::Sio::new(pac.SIO);
+
+        let pins = hal::gpio::Pins::new(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        );
+
+        let status = StatusLed::new(pins.gpio25.into_push_pull_output().into_dyn_pin())
+            .active_low(STATUS_LED_ACTIVE_LOW);
+
+        let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+        let config = Config::load();
+        debug!("Loaded {}", config);
+
+        #[cfg(feature = "receiver")]
+        let mut receiver = match (RECEIVER_INPUT, PWM_PINS) {
+            (ReceiverInput::Pwm, PwmPins::Default) => initialize_receiver(
+                timer,
+                &mut pac.RESETS,
+                pac.PWM,
+                RECEIVER_CONFIG,
+                CAPTURE_MODE,
+                ReceiverPins {
+                    steering: pins.gpio3,
+                    throttle: pins.gpio5,
+                    update: pins.gpio4,
+                    aux: pins.gpio7,
+                },
+                clocks.system_clock.freq(),
+            ),
+            (ReceiverInput::Pwm, PwmPins::Alternate) => initialize_receiver(
+                timer,
+                &mut pac.RESETS,
+                pac.PWM,
+                RECEIVER_CONFIG,
+                CAPTURE_MODE,
+                ReceiverPins {
+                    steering: pins.gpio19,
+                    throttle: pins.gpio21,
+                    update: pins.gpio20,
+                    aux: pins.gpio7,
+                },
+                clocks.system_clock.freq(),
+            ),
+            (ReceiverInput::Ppm, _) => initialize_ppm_receiver(timer, pins.gpio3, RECEIVER_CONFIG),
+            (ReceiverInput::Sbus, _) => initialize_sbus_receiver(
+                timer,
+                &mut pac.RESETS,
+                pac.UART0,
+                pins.gpio0,
+                pins.gpio1,
+                RECEIVER_CONFIG,
+                clocks.peripheral_clock.freq(),
+            ),
+            (ReceiverInput::Crsf, _) => initialize_crsf_receiver(
+                timer,
+                &mut pac.RESETS,
+                pac.UART1,
+                pins.gpio4,
+                pins.gpio5,
+                RECEIVER_CONFIG,
+                clocks.peripheral_clock.freq(),
+            ),
+        };
+        #[cfg(feature = "receiver")]
+        receiver.set_combined_fault_policy(COMBINED_FAULT_POLICY);
+        #[cfg(feature = "receiver")]
+        receiver.set_link_thresholds(LINK_THRESHOLDS);
+        #[cfg(feature = "receiver")]
+        let external_indicator = (EXTERNAL_INDICATOR == ExternalIndicator::Gpio)
+            .then(|| StatusLed::new(pins.gpio15.into_push_pull_output().into_dyn_pin()));
+
+        #[cfg(feature = "lights")]
+        let output = {
+            let pin = pins
+                .gpio8
+                .into_push_pull_output_in_state(hal::gpio::PinState::Low)
+                .into_function::<FunctionPio0>()
+                .into_dyn_pin();
+
+            let (mut pio, sm0, sm1, _, _) = pac.PIO0.split(&mut pac.RESETS);
+
+            #[cfg(feature = "scope-pin")]
+            crate::lights::set_scope_pin(pins.gpio22.into_push_pull_output().into_dyn_pin());
+            match LED_WIRING {
+                LedWiring::Chain => {
+                    let tx = match initialize_lights(
+                        &mut pio,
+                        sm0,
+                        &clocks,
+                        pin,
+                        LED_FREQUENCY_HZ,
+                        LED_PIXEL_FORMAT.bits_per_pixel(),
+                        LED_RESET_US,
+                    ) {
+                        Ok(tx) => tx,
+                        Err(error) => {
+                            error!("Lights failed to start: {}", error);
+                            let mut status = status;
+                            halt_with_fault(&mut status, clocks.system_clock.freq().to_Hz())
+                        }
+                    };
+                    debug!(
+                        "LED frame takes {}us, so at most {} fps",
+                        Leds::frame_transmit_us(
+                            LED_FREQUENCY_HZ,
+                            LED_PIXEL_FORMAT.bits_per_pixel(),
+                            LED_RESET_US
+                        ),
+                        LED_FRAME_LIMIT.max_fps()
+                    );
+                    let dma = pac.DMA.split(&mut pac.RESETS);
+                    let mut strip =
+                        LedDma::new(dma.ch0, tx, led_color_order(&config), LED_PIXEL_FORMAT);
+                    if RUN_STARTUP_SEQUENCE {
+                        run_startup_sequence(&mut strip, &mut delay, LED_CORNERS);
+                    }
+                    match LED_CORE {
+                        LedCore::Core0 => LedOutput::Local(strip),
+                        LedCore::Core1 => LedOutput::Core1(spawn_led_core(
+                            &mut pac.PSM,
+                            &mut pac.PPB,
+                            sio.fifo,
+                            strip,
+                        )),
+                    }
+                }
+                LedWiring::Split => {
+                    let rear_pin = pins
+                        .gpio9
+                        .into_push_pull_output_in_state(hal::gpio::PinState::Low)
+                        .into_function::<FunctionPio0>()
+                        .into_dyn_pin();
+                    let (front, rear) = match initialize_split_lights(
+                        &mut pio,
+                        sm0,
+                        sm1,
+                        &clocks,
+                        pin,
+                        rear_pin,
+                        LED_FREQUENCY_HZ,
+                        LED_PIXEL_FORMAT.bits_per_pixel(),
+                        LED_RESET_US,
+                    ) {
+                        Ok(strips) => strips,
+                        Err(error) => {
+                            error!("Lights failed to start: {}", error);
+                            let mut status = status;
+                            halt_with_fault(&mut status, clocks.system_clock.freq().to_Hz())
+                        }
+                    };
+                    LedOutput::Split(SplitLeds::new(
+                        front,
+                        rear,
+                        led_color_order(&config),
+                        LED_PIXEL_FORMAT,
+                    ))
+                }
+            }
+        };
+        #[cfg(feature = "lights")]
+        let adc =
+            (AMBIENT_DIMMING || BATTERY_MONITOR).then(|| hal::Adc::new(pac.ADC, &mut pac.RESETS));
+        #[cfg(feature = "lights")]
+        let ambient = AMBIENT_DIMMING
+            .then(|| AmbientLight::new(pins.gpio26.into_floating_input(), AMBIENT_CONFIG));
+        #[cfg(feature = "lights")]
+        let battery = BATTERY_MONITOR
+            .then(|| BatteryMonitor::new(pins.gpio27.into_floating_input(), BATTERY_CONFIG));
+        let pipeline = Pipeline::new(
+            status,
+            config,
+            #[cfg(feature = "receiver")]
+            receiver,
+            #[cfg(feature = "receiver")]
+            external_indicator,
+            #[cfg(feature = "lights")]
+            output,
+            #[cfg(feature = "lights")]
+            AnalogInputs {
+                adc,
+                ambient,
+                battery,
+            },
+        );
+        // Last, as it takes the USB clock
+        #[cfg(feature = "cli")]
+        let cli = Cli::new(
+            pac.USBCTRL_REGS,
+            pac.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            &mut pac.RESETS,
+        );
+
+        // Last, so nothing slow at boot counts towards the timeout
+        let watchdog = HangWatchdog::start(watchdog);
+
+        Self {
+            pipeline,
+            timer,
+            watchdog,
+            #[cfg(feature = "cli")]
+            cli,
+            next_update: timer.get_counter(),
+        }
+    }
+
+    /// Runs one spin of the control loop: the console and telemetry every
+    /// time, and an update once `UPDATE_PERIOD_MS` has passed since the last.
+    pub fn tick(&mut self) {
+        let now = self.timer.get_counter();
+        // Serviced on every spin, not just every update, so the host never waits long
+        #[cfg(feature = "cli")]
+        if let Some(command) = self.cli.poll() {
+            self.pipeline.run_command(command, &mut self.cli, now);
+        }
+        #[cfg(feature = "receiver")]
+        self.pipeline.log_telemetry(now);
+        if now < self.next_update {
+            return;
+        }
+        // Scheduled from now rather than the missed deadline, so a late tick doesn't cause a burst
+        self.next_update = now + MicrosDurationU64::millis(UPDATE_PERIOD_MS as u64);
+        self.watchdog.pet();
+        self.pipeline.tick(now);
+    }
+}
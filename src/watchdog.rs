@@ -0,0 +1,86 @@
+//! The receiver watchdog's expiry decision, apart from the timer it reads.
+
+use fugit::{MillisDurationU64, TimerInstantU64};
+
+/// A reading of the RP2040's µs timer, the same type as `rp2040_hal::timer::Instant`.
+pub type Instant = TimerInstantU64<1_000_000>;
+
+/// Where the watchdog reads the time from.
+///
+/// On target this is always the receiver's `Timer`. The expiry decision only
+/// needs something that tells the time, so it can be run against a fake clock too.
+pub trait TimeSource {
+    fn now(&self) -> Instant;
+}
+
+/// Whether the gap from `since` to `now` is longer than `timeout`.
+///
+/// A gap of exactly `timeout` still counts as fresh.
+pub fn gap_exceeds(now: Instant, since: Instant, timeout: MillisDurationU64) -> bool {
+    now - since > timeout
+}
+
+/// Whether more than `timeout` has passed on `clock` since `since`.
+///
+/// Without a clock nothing can be fresh, so that counts as stale.
+pub fn is_stale_on(
+    clock: Option<&dyn TimeSource>,
+    since: Instant,
+    timeout: MillisDurationU64,
+) -> bool {
+    clock.is_none_or(|clock| gap_exceeds(clock.now(), since, timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Always reads the time it was set to.
+    struct FakeClock(Instant);
+
+    impl TimeSource for FakeClock {
+        fn now(&self) -> Instant {
+            self.0
+        }
+    }
+
+    const TIMEOUT: MillisDurationU64 = MillisDurationU64::millis(100);
+
+    fn at_ms(ms: u64) -> Instant {
+        Instant::from_ticks(ms * 1000)
+    }
+
+    fn stale_after(since_ms: u64, now: Instant) -> bool {
+        is_stale_on(Some(&FakeClock(now)), at_ms(since_ms), TIMEOUT)
+    }
+
+    #[test]
+    fn no_clock_is_stale() {
+        assert!(is_stale_on(None, at_ms(0), TIMEOUT));
+    }
+
+    #[test]
+    fn gap_of_the_timeout_is_fresh() {
+        assert!(!stale_after(0, at_ms(0)));
+        assert!(!stale_after(0, at_ms(99)));
+        assert!(!stale_after(0, at_ms(100)));
+        assert!(!stale_after(5000, at_ms(5100)));
+    }
+
+    #[test]
+    fn gap_past_the_timeout_is_stale() {
+        assert!(stale_after(0, Instant::from_ticks(100_001)));
+        assert!(stale_after(0, at_ms(101)));
+        assert!(stale_after(5000, at_ms(60_000)));
+    }
+
+    // A 50 Hz receiver that drops the odd frame wants a longer timeout
+    #[test]
+    fn configured_timeout() {
+        let timeout = MillisDurationU64::millis(250);
+        let stale = |now_ms| is_stale_on(Some(&FakeClock(at_ms(now_ms))), at_ms(0), timeout);
+        assert!(!stale(200));
+        assert!(!stale(250));
+        assert!(stale(300));
+    }
+}